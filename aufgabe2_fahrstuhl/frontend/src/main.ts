@@ -1,6 +1,19 @@
+/// <reference types="vite/client" />
 import p5 from "p5";
 import mqtt from "mqtt";
 
+// Same value as TOPIC_PREFIX of the simulation, e.g. VITE_TOPIC_PREFIX=building/a
+// Normalized like Topics::new: without leading / trailing slashes, one appended
+const TOPIC_PREFIX: string = topicPrefix(import.meta.env.VITE_TOPIC_PREFIX ?? "");
+
+function topicPrefix(prefix: string): string {
+    const trimmed = prefix.replace(/^\/+|\/+$/g, "");
+    return trimmed === "" ? "" : trimmed + "/";
+}
+
+// Must match FLOOR_HEIGHT_M of the simulation (kinematics.rs)
+const FLOOR_HEIGHT_M = 3.0;
+
 // Composite Structure
 
 interface Stage {
@@ -38,6 +51,11 @@ class MainStage implements Stage {
         [Floor.THIRD]: 100,
     };
 
+    // Car height in metres above Ground -> pixel
+    heightToPixel(height: number): number {
+        return this.FLOOR_PIXEL[Floor.GROUND] - height / FLOOR_HEIGHT_M * 200;
+    }
+
     draw(sk: p5): void {
         let x = 50;
         for (let e of this.elevators) {
@@ -72,6 +90,9 @@ class MainStage implements Stage {
                 console.log(find)
                 find?.setTarget(action.payload.targetY);
                 break;
+            case "ELEVATOR_HEIGHT_UPDATE":
+                this.elevators.find(e => e.name() === action.payload.id)?.lift.setY(action.payload.y);
+                break;
             case "ELEVATOR_DOOR_UPDATE":
                 let elevator = this.elevators.find(e => e.name() === action.payload.id);
                 elevator?.lift.setDoorStatus(action.payload.status);
@@ -166,6 +187,12 @@ class LiftStage implements Stage {
         }
     }
 
+    // Real position from elevator/{id}/height, no interpolation
+    setY(y: number) {
+        this.y = y;
+        this.targetY = y;
+    }
+
     setDoorStatus(status: DoorStatus) {
         this.doorStatus = status;
     }
@@ -223,13 +250,17 @@ new p5((sk) => {
         let client = mqtt.connect("ws://localhost:9001");
 
         client.on("connect", () => {
-            client.subscribe("elevator/+/position");
-            client.subscribe("elevator/+/door");
+            client.subscribe(TOPIC_PREFIX + "elevator/+/position");
+            client.subscribe(TOPIC_PREFIX + "elevator/+/door");
+            client.subscribe(TOPIC_PREFIX + "elevator/+/height");
         });
 
         client.on("message", (topic, msg) => {
+            topic = topic.substring(TOPIC_PREFIX.length);
             const [_, id, kind] = topic.split("/");
-            const json = JSON.parse(msg.toString());
+            const raw = JSON.parse(msg.toString());
+            // Versioned protocol (MQTT_PROTOCOL=v1) wraps the payload in an envelope
+            const json = raw.type !== undefined && raw.data !== undefined ? raw.data : raw;
 
             const targetY = main.FLOOR_PIXEL[json.floor as Floor];
 
@@ -241,6 +272,14 @@ new p5((sk) => {
                         targetY
                     }
                 });
+            } else if (kind === "height") {
+                main.action({
+                    type: "ELEVATOR_HEIGHT_UPDATE",
+                    payload: {
+                        id,
+                        y: main.heightToPixel(json.height)
+                    }
+                });
             } else if (kind === "door") {
                 main.action({
                     type: "ELEVATOR_DOOR_UPDATE",
@@ -0,0 +1,19 @@
+#!/bin/sh
+# Erzeugt eine lokale CA sowie Server- und Client-Zertifikate für mosquitto-tls.conf (nur zum Testen)
+set -e
+cd "$(dirname "$0")"
+
+openssl req -x509 -newkey rsa:2048 -nodes -days 365 -subj "/CN=Fahrstuhl Test CA" \
+    -keyout ca.key -out ca.crt
+
+openssl req -newkey rsa:2048 -nodes -subj "/CN=localhost" -keyout server.key -out server.csr
+printf "subjectAltName=DNS:localhost,IP:127.0.0.1\n" > server.ext
+openssl x509 -req -in server.csr -CA ca.crt -CAkey ca.key -CAcreateserial -days 365 \
+    -extfile server.ext -out server.crt
+
+openssl req -newkey rsa:2048 -nodes -subj "/CN=simulation" -keyout client.key -out client.csr
+openssl x509 -req -in client.csr -CA ca.crt -CAkey ca.key -CAcreateserial -days 365 -out client.crt
+
+rm -f server.csr client.csr server.ext
+# Private Schlüssel nur für den eigenen Benutzer, der Broker-Container läuft mit derselben UID
+chmod 600 *.key
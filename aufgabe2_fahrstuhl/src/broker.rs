@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use rumqttd::{Broker, Config, ConnectionSettings, RouterConfig, ServerSettings};
+
+pub const TCP_PORT: u16 = 1883;
+pub const WS_PORT: u16 = 9001;
+
+// Same listeners as the Docker mosquitto: MQTT (v5, used by the simulation) and
+// websockets (v4, used by the frontend)
+pub fn start(tcp_port: u16, ws_port: u16) -> Result<(), String> {
+    let tcp: SocketAddr = ([0, 0, 0, 0], tcp_port).into();
+    let ws: SocketAddr = ([0, 0, 0, 0], ws_port).into();
+    // Otherwise the client would silently talk to whatever broker already listens there
+    for addr in [tcp, ws] {
+        TcpListener::bind(addr).map_err(|e| format!("Port {} für den eingebetteten Broker ist belegt: {}", addr.port(), e))?;
+    }
+    let config = Config {
+        router: RouterConfig {
+            max_connections: 1000,
+            max_outgoing_packet_count: 200,
+            max_segment_size: 100 * 1024 * 1024,
+            max_segment_count: 10,
+            ..Default::default()
+        },
+        v5: Some(HashMap::from([("tcp".to_string(), server("tcp", tcp))])),
+        ws: Some(HashMap::from([("ws".to_string(), server("ws", ws))])),
+        ..Default::default()
+    };
+
+    let mut broker = Broker::new(config);
+    let (stopped_tx, stopped) = mpsc::channel();
+    thread::Builder::new()
+        .name("embedded-broker".to_string())
+        .spawn(move || {
+            let result = broker.start().map_err(|e| e.to_string());
+            if let Err(e) = &result {
+                tracing::error!("Eingebetteter Broker beendet: {}", e);
+            }
+            let _ = stopped_tx.send(result);
+        })
+        .map_err(|e| e.to_string())?;
+
+    wait_until_listening(([127, 0, 0, 1], tcp_port).into(), Duration::from_secs(5), &stopped)
+}
+
+fn server(name: &str, listen: SocketAddr) -> ServerSettings {
+    ServerSettings {
+        name: name.to_string(),
+        listen,
+        tls: None,
+        next_connection_delay_ms: 1,
+        connections: ConnectionSettings {
+            connection_timeout_ms: 60000,
+            max_payload_size: 1024 * 1024,
+            max_inflight_count: 100,
+            auth: None,
+            external_auth: None,
+            dynamic_filters: true,
+        },
+    }
+}
+
+// Fails as well if the broker thread ends before the port is open
+fn wait_until_listening(addr: SocketAddr, timeout: Duration, stopped: &mpsc::Receiver<Result<(), String>>) -> Result<(), String> {
+    let start = Instant::now();
+    while TcpStream::connect_timeout(&addr, Duration::from_millis(100)).is_err() {
+        if let Ok(result) = stopped.try_recv() {
+            return Err(format!("Eingebetteter Broker beendet: {}", result.err().unwrap_or_default()));
+        }
+        if start.elapsed() > timeout {
+            return Err(format!("Eingebetteter Broker lauscht nicht auf {}", addr));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use paho_mqtt::{AsyncClient, ConnectOptionsBuilder, CreateOptionsBuilder, Message, MQTT_VERSION_5};
+    use crate::mqtt::{MqttConfig, MqttConnector, Receive, Topics};
+    use crate::protocol::Codec;
+    use crate::transport::Transport;
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_embedded_broker_routes_messages() {
+        let port = free_port();
+        start(port, free_port()).unwrap();
+
+        let config = MqttConfig { server_uri: format!("mqtt://127.0.0.1:{}", port), ..Default::default() };
+        let connector = MqttConnector::new(Topics::new(""), Codec::new(Default::default()), &config).await.unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(10);
+        connector.inbound(tx);
+
+        let create = CreateOptionsBuilder::new()
+            .server_uri(&config.server_uri)
+            .mqtt_version(MQTT_VERSION_5)
+            .finalize();
+        let client = AsyncClient::new(create).unwrap();
+        client.connect(ConnectOptionsBuilder::new_v5().finalize()).await.unwrap();
+
+        // The subscription is set up asynchronously, publish until it arrives
+        let received = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                client.publish(Message::new("simulation/speed", r#"{"speed":50}"#, 1)).await.unwrap();
+                if let Ok(Some(receive)) = tokio::time::timeout(Duration::from_millis(200), rx.recv()).await {
+                    return receive;
+                }
+            }
+        }).await.unwrap();
+        assert!(matches!(received, Receive::Speed { speed: 50 }));
+    }
+
+    #[test]
+    fn test_port_taken_by_another_broker() {
+        let other = TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = other.local_addr().unwrap().port();
+
+        assert!(start(port, free_port()).unwrap_err().contains("belegt"));
+    }
+}
@@ -0,0 +1,300 @@
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use aufgabe2_fahrstuhl::config::{BrokerSettings, Config, Elevators, LogSettings};
+
+// Without a subcommand the simulator runs as before, so `fahrstuhl --persons 10` works like `fahrstuhl run --persons 10`
+#[derive(Parser, Debug)]
+#[command(version, about = "Fahrstuhl-Simulation mit MQTT-, HTTP- und WebSocket-Schnittstelle")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    /// Log-Filter, z.B. "debug,aufgabe2_fahrstuhl::controller=trace"
+    #[arg(long, global = true, env = "RUST_LOG", value_name = "FILTER")]
+    pub log_level: Option<String>,
+
+    /// Log-Format: text oder json
+    #[arg(long, global = true, env = "LOG_FORMAT", value_name = "FORMAT")]
+    pub log_format: Option<String>,
+
+    /// Kurzform für --log-format json
+    #[arg(long, global = true)]
+    pub log_json: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Simulation mit Broker und HTTP-API starten (Standard)
+    Run(Box<RunArgs>),
+    /// Personen aus einer Szenario-Datei (TOML) ohne Broker einspielen und die Statistik ausgeben
+    Scenario {
+        /// Szenario-Datei
+        file: PathBuf,
+        #[command(flatten)]
+        playback: PlaybackArgs,
+    },
+    /// Ein mit `run --journal` aufgezeichnetes Journal ohne Broker erneut abspielen
+    Replay {
+        /// Journal-Datei (JSON Lines)
+        journal: PathBuf,
+        #[command(flatten)]
+        playback: PlaybackArgs,
+    },
+    /// Konfigurationsdatei prüfen und alle Fehler auflisten
+    ValidateConfig {
+        /// Konfigurationsdatei (TOML)
+        config: PathBuf,
+    },
+}
+
+#[derive(Args, Debug, Default)]
+pub struct RunArgs {
+    /// Konfigurationsdatei (TOML), Kommandozeile und Umgebung haben Vorrang
+    #[arg(long, short, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Broker-URL, z.B. mqtt://localhost:1883 oder mqtts://broker:8883
+    #[arg(long, env = "MQTT_URL", value_name = "URL")]
+    pub broker_url: Option<String>,
+
+    #[arg(long, env = "MQTT_USERNAME")]
+    pub username: Option<String>,
+
+    #[arg(long, env = "MQTT_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+
+    /// CA-Zertifikat für TLS
+    #[arg(long, env = "MQTT_CA_FILE", value_name = "FILE")]
+    pub ca_file: Option<String>,
+
+    /// Client-Zertifikat für gegenseitiges TLS
+    #[arg(long, env = "MQTT_CERT_FILE", value_name = "FILE")]
+    pub cert_file: Option<String>,
+
+    /// Privater Schlüssel zum Client-Zertifikat
+    #[arg(long, env = "MQTT_KEY_FILE", value_name = "FILE")]
+    pub key_file: Option<String>,
+
+    #[arg(long, env = "MQTT_KEY_PASSWORD", hide_env_values = true)]
+    pub key_password: Option<String>,
+
+    /// Anzahl der Aufzüge oder kommagetrennte Namen [Standard: Dorisch,Ionisch,Korinthisch]
+    #[arg(long, short, value_parser = parse_elevators, value_name = "N|NAMEN")]
+    pub elevators: Option<Elevators>,
+
+    /// Anzahl zufällig fahrender Personen beim Start [Standard: 5]
+    #[arg(long, short, value_name = "N")]
+    pub persons: Option<usize>,
+
+    /// Geschwindigkeit in Prozent der Wartezeiten, 100 = Echtzeit, 10 = zehnmal schneller
+    #[arg(long, short, value_name = "PROZENT")]
+    pub speed: Option<u64>,
+
+    /// Startwert für den Zufallsgenerator, macht Stockwerke und Wartezeiten wiederholbar
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Präfix für alle Topics, z.B. building/a
+    #[arg(long, env = "TOPIC_PREFIX", value_name = "PREFIX")]
+    pub topic_prefix: Option<String>,
+
+    /// MQTT-Protokoll: legacy oder v1
+    #[arg(long, env = "MQTT_PROTOCOL")]
+    pub protocol: Option<String>,
+
+    /// Intervall des Snapshots auf simulation/snapshot, 0 schaltet ihn ab [Standard: 5000]
+    #[arg(long, env = "SNAPSHOT_INTERVAL_MS", value_name = "MS")]
+    pub snapshot_interval_ms: Option<u64>,
+
+    /// Port der REST- und WebSocket-API (ohne Authentifizierung), ohne Angabe oder mit 0 abgeschaltet
+    #[arg(long, env = "HTTP_PORT", value_name = "PORT")]
+    pub http_port: Option<u16>,
+
+    /// Adresse, an die die REST- und WebSocket-API bindet [Standard: 127.0.0.1]
+    #[arg(long, env = "HTTP_BIND", value_name = "ADDR")]
+    pub http_bind: Option<String>,
+
+    /// Eingebetteten Broker starten (Feature embedded-broker)
+    #[arg(long)]
+    pub embedded_broker: bool,
+
+    /// Alle eingehenden Nachrichten in eine Datei schreiben, abspielbar mit `replay`
+    #[arg(long, value_name = "FILE")]
+    pub journal: Option<PathBuf>,
+
+    /// Gebäude im Terminal anzeigen, Log-Ausgaben sind dabei abgeschaltet
+    #[arg(long)]
+    pub tui: bool,
+
+    /// Ohne MQTT-Broker starten, z.B. zusammen mit --tui
+    #[arg(long)]
+    pub offline: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct PlaybackArgs {
+    /// Konfigurationsdatei wie bei `run` (Fahrphysik, Zonen, Energie, Parken, Nennlast)
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Anzahl der Aufzüge oder kommagetrennte Namen
+    #[arg(long, short, value_parser = parse_elevators, value_name = "N|NAMEN")]
+    pub elevators: Option<Elevators>,
+
+    /// Anzahl zufällig fahrender Personen wie bei `run` [Standard: Szenario 0, Replay 5]
+    #[arg(long, short, value_name = "N")]
+    pub persons: Option<usize>,
+
+    /// Geschwindigkeit in Prozent der Wartezeiten, 100 = Echtzeit
+    #[arg(long, short, value_name = "PROZENT")]
+    pub speed: Option<u64>,
+
+    /// Startwert für den Zufallsgenerator
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Abbruch, wenn nach dieser Zeit nicht alle Personen angekommen sind
+    #[arg(long, default_value_t = 600, value_name = "SEKUNDEN")]
+    pub timeout: u64,
+}
+
+fn parse_elevators(s: &str) -> Result<Elevators, String> {
+    match s.parse() {
+        Ok(count) => Ok(Elevators::Count(count)),
+        Err(_) => Ok(Elevators::Names(s.split(',').map(|name| name.trim().to_string()).collect())),
+    }
+}
+
+impl Cli {
+
+    // Log settings from the command line / environment as a config to overlay
+    pub fn log_config(&self) -> Config {
+        Config { log: self.log_settings(), ..Default::default() }
+    }
+
+    pub fn log_settings(&self) -> LogSettings {
+        LogSettings {
+            filter: self.log_level.clone(),
+            format: if self.log_json { Some("json".to_string()) } else { self.log_format.clone() },
+        }
+    }
+}
+
+impl RunArgs {
+
+    // Config file overlaid with command line and environment
+    pub fn config(&self) -> Result<Config, String> {
+        let file = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        Ok(file.overlay(Config {
+            elevators: self.elevators.clone(),
+            persons: self.persons,
+            speed: self.speed,
+            seed: self.seed,
+            topic_prefix: self.topic_prefix.clone(),
+            protocol: self.protocol.clone(),
+            snapshot_interval_ms: self.snapshot_interval_ms,
+            http_port: self.http_port,
+            http_bind: self.http_bind.clone(),
+            broker: BrokerSettings {
+                url: self.broker_url.clone(),
+                username: self.username.clone(),
+                password: self.password.clone(),
+                ca_file: self.ca_file.clone(),
+                cert_file: self.cert_file.clone(),
+                key_file: self.key_file.clone(),
+                key_password: self.key_password.clone(),
+            },
+            log: LogSettings::default(),
+            // Only in the config file
+            motion: Default::default(),
+            energy: None,
+            served_floors: Default::default(),
+            parking: None,
+            rated_load_kg: Default::default(),
+        }))
+    }
+}
+
+impl PlaybackArgs {
+
+    // Config file, then `base` (e.g. the scenario), then the command line
+    pub fn config(&self, base: Config) -> Result<Config, String> {
+        let file = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        Ok(file.overlay(base).overlay(Config {
+            elevators: self.elevators.clone(),
+            persons: self.persons,
+            speed: self.speed,
+            seed: self.seed,
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_run_is_default() {
+        let cli = Cli::try_parse_from(["fahrstuhl", "--elevators", "4", "--persons", "2", "--seed", "7"]).unwrap();
+        assert!(cli.command.is_none());
+
+        let config = cli.run.config().unwrap();
+        assert_eq!(config.elevator_names().len(), 4);
+        assert_eq!(config.persons(), 2);
+        assert_eq!(config.seed, Some(7));
+    }
+
+    #[test]
+    fn test_run_subcommand() {
+        let cli = Cli::try_parse_from(["fahrstuhl", "run", "--elevators", "A,B", "--log-json"]).unwrap();
+        let Some(Command::Run(args)) = &cli.command else { panic!("run erwartet") };
+
+        assert_eq!(args.config().unwrap().elevator_names(), vec!["A", "B"]);
+        assert!(!args.tui);
+        assert_eq!(cli.log_settings().format.as_deref(), Some("json"));
+    }
+
+    #[test]
+    fn test_tui_without_broker() {
+        let cli = Cli::try_parse_from(["fahrstuhl", "--tui", "--offline"]).unwrap();
+        assert!(cli.run.tui);
+        assert!(cli.run.offline);
+    }
+
+    #[test]
+    fn test_scenario_subcommand() {
+        let cli = Cli::try_parse_from(["fahrstuhl", "scenario", "rush.toml", "--speed", "1", "--timeout", "30"]).unwrap();
+        let Some(Command::Scenario { file, playback }) = cli.command else { panic!("scenario erwartet") };
+
+        assert_eq!(file, PathBuf::from("rush.toml"));
+        let base = Config { speed: Some(100), persons: Some(0), ..Default::default() };
+        let config = playback.config(base).unwrap();
+        assert_eq!(config.speed, Some(1));
+        assert_eq!(config.persons(), 0);
+        assert_eq!(playback.timeout, 30);
+    }
+
+    #[test]
+    fn test_invalid_arguments_are_rejected() {
+        assert!(Cli::try_parse_from(["fahrstuhl", "--persons", "viele"]).is_err());
+        assert!(Cli::try_parse_from(["fahrstuhl", "validate-config"]).is_err());
+        assert!(Cli::try_parse_from(["fahrstuhl", "--persons", "2", "replay", "journal.jsonl"]).is_err());
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use serde::Deserialize;
+use crate::controller::Floor;
+use crate::kinematics::MotionProfile;
+use crate::energy::EnergyModel;
+use crate::parking::ParkingPolicy;
+use crate::logger;
+use crate::logger::LogFormat;
+use crate::mqtt::{MqttConfig, MAX_SPEED, MIN_SPEED};
+use crate::protocol::Protocol;
+
+pub const ELEVATOR_NAMES: [&str; 3] = ["Dorisch", "Ionisch", "Korinthisch"];
+
+// Either a number of cars or their names
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Elevators {
+    Count(usize),
+    Names(Vec<String>),
+}
+
+impl Elevators {
+    pub fn names(&self) -> Vec<String> {
+        match self {
+            Elevators::Names(names) => names.clone(),
+            Elevators::Count(count) => (0..*count)
+                .map(|i| ELEVATOR_NAMES.get(i).map_or(format!("Aufzug_{}", i + 1), |name| name.to_string()))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrokerSettings {
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub ca_file: Option<String>,
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    pub key_password: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    pub filter: Option<String>,
+    pub format: Option<String>,
+}
+
+// Everything is optional: values from the config file are overlaid with
+// environment / command line values, the rest falls back to the defaults
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub elevators: Option<Elevators>,
+    pub persons: Option<usize>,
+    pub speed: Option<u64>,
+    pub seed: Option<u64>,
+    pub topic_prefix: Option<String>,
+    pub protocol: Option<String>,
+    pub snapshot_interval_ms: Option<u64>,
+    pub http_port: Option<u16>,
+    pub http_bind: Option<String>,
+    pub broker: BrokerSettings,
+    pub log: LogSettings,
+    // Per elevator, cars without an entry use MotionProfile::default()
+    pub motion: BTreeMap<String, MotionProfile>,
+    pub energy: Option<EnergyModel>,
+    // Per elevator, cars without an entry serve every floor
+    pub served_floors: BTreeMap<String, Vec<Floor>>,
+    // Without it idle cars stay where they are
+    pub parking: Option<ParkingPolicy>,
+    // Per elevator, cars without an entry carry DEFAULT_RATED_LOAD_KG
+    pub rated_load_kg: BTreeMap<String, f64>,
+}
+
+impl Config {
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Values set in `other` win
+    pub fn overlay(self, other: Config) -> Config {
+        Config {
+            elevators: other.elevators.or(self.elevators),
+            persons: other.persons.or(self.persons),
+            speed: other.speed.or(self.speed),
+            seed: other.seed.or(self.seed),
+            topic_prefix: other.topic_prefix.or(self.topic_prefix),
+            protocol: other.protocol.or(self.protocol),
+            snapshot_interval_ms: other.snapshot_interval_ms.or(self.snapshot_interval_ms),
+            http_port: other.http_port.or(self.http_port),
+            http_bind: other.http_bind.or(self.http_bind),
+            broker: BrokerSettings {
+                url: other.broker.url.or(self.broker.url),
+                username: other.broker.username.or(self.broker.username),
+                password: other.broker.password.or(self.broker.password),
+                ca_file: other.broker.ca_file.or(self.broker.ca_file),
+                cert_file: other.broker.cert_file.or(self.broker.cert_file),
+                key_file: other.broker.key_file.or(self.broker.key_file),
+                key_password: other.broker.key_password.or(self.broker.key_password),
+            },
+            log: LogSettings {
+                filter: other.log.filter.or(self.log.filter),
+                format: other.log.format.or(self.log.format),
+            },
+            motion: self.motion.into_iter().chain(other.motion).collect(),
+            energy: other.energy.or(self.energy),
+            served_floors: self.served_floors.into_iter().chain(other.served_floors).collect(),
+            parking: other.parking.or(self.parking),
+            rated_load_kg: self.rated_load_kg.into_iter().chain(other.rated_load_kg).collect(),
+        }
+    }
+
+    pub fn elevator_names(&self) -> Vec<String> {
+        self.elevators.clone().unwrap_or(Elevators::Count(ELEVATOR_NAMES.len())).names()
+    }
+
+    pub fn persons(&self) -> usize {
+        self.persons.unwrap_or(5)
+    }
+
+    // SNAPSHOT_INTERVAL_MS=0 disables the periodic snapshot
+    pub fn snapshot_interval_ms(&self) -> u64 {
+        self.snapshot_interval_ms.unwrap_or(5000)
+    }
+
+    // The REST / WebSocket API has no authentication and is off unless a port is set, 0 keeps it off
+    pub fn http_port(&self) -> Option<u16> {
+        self.http_port.filter(|&port| port != 0)
+    }
+
+    // Only local clients unless configured otherwise, e.g. "0.0.0.0" inside a container
+    pub fn http_bind(&self) -> &str {
+        self.http_bind.as_deref().unwrap_or("127.0.0.1")
+    }
+
+    pub fn protocol(&self) -> Result<Protocol, String> {
+        self.protocol.as_deref().map_or(Ok(Protocol::default()), str::parse)
+    }
+
+    pub fn log_filter(&self) -> String {
+        self.log.filter.clone().unwrap_or(logger::DEFAULT_FILTER.to_string())
+    }
+
+    pub fn log_format(&self) -> Result<LogFormat, String> {
+        self.log.format.as_deref().map_or(Ok(LogFormat::default()), str::parse)
+    }
+
+    pub fn mqtt(&self) -> MqttConfig {
+        MqttConfig {
+            server_uri: self.broker.url.clone().unwrap_or(MqttConfig::default().server_uri),
+            username: self.broker.username.clone(),
+            password: self.broker.password.clone(),
+            ca_file: self.broker.ca_file.clone(),
+            cert_file: self.broker.cert_file.clone(),
+            key_file: self.broker.key_file.clone(),
+            key_password: self.broker.key_password.clone(),
+        }
+    }
+
+    // All problems at once, so validate-config can list them
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let names = self.elevator_names();
+        if names.is_empty() {
+            errors.push("elevators: mindestens ein Aufzug wird benötigt".to_string());
+        }
+        if names.iter().any(|name| name.trim().is_empty()) {
+            errors.push("elevators: leerer Name".to_string());
+        }
+        if names.iter().collect::<HashSet<_>>().len() != names.len() {
+            errors.push("elevators: doppelte Namen".to_string());
+        }
+        if let Some(speed) = self.speed && !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+            errors.push(format!("speed: {} liegt nicht zwischen {} und {}", speed, MIN_SPEED, MAX_SPEED));
+        }
+        if let Err(e) = self.protocol() {
+            errors.push(format!("protocol: {}", e));
+        }
+        if let Err(e) = logger::parse_filter(&self.log_filter()) {
+            errors.push(format!("log.filter: {}", e));
+        }
+        if let Err(e) = self.log_format() {
+            errors.push(format!("log.format: {}", e));
+        }
+        if let Err(e) = self.mqtt().connect_options() {
+            errors.push(format!("broker: {}", e));
+        }
+        for (name, profile) in &self.motion {
+            if !names.contains(name) {
+                errors.push(format!("motion.{}: unbekannter Aufzug", name));
+            }
+            if let Err(e) = profile.validate() {
+                errors.push(format!("motion.{}: {}", name, e));
+            }
+        }
+        for (name, floors) in &self.served_floors {
+            if !names.contains(name) {
+                errors.push(format!("served_floors.{}: unbekannter Aufzug", name));
+            }
+            if floors.iter().collect::<HashSet<_>>().len() < 2 {
+                errors.push(format!("served_floors.{}: mindestens zwei Ebenen werden benötigt", name));
+            }
+        }
+        for (name, rated_load_kg) in &self.rated_load_kg {
+            if !names.contains(name) {
+                errors.push(format!("rated_load_kg.{}: unbekannter Aufzug", name));
+            }
+            if !(rated_load_kg.is_finite() && *rated_load_kg > 0.0) {
+                errors.push(format!("rated_load_kg.{}: muss größer als 0 sein", name));
+            }
+        }
+        if let Some(energy) = &self.energy && let Err(e) = energy.validate() {
+            errors.push(format!("energy: {}", e));
+        }
+        if let Some(parking) = &self.parking && let Err(e) = parking.validate() {
+            errors.push(format!("parking: {}", e));
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parking::ParkingStrategy;
+
+    #[test]
+    fn test_parse_config_file() {
+        let config: Config = toml::from_str(r#"
+            elevators = ["A", "B"]
+            persons = 10
+            speed = 50
+            seed = 42
+
+            [broker]
+            url = "mqtt://broker:1883"
+            username = "sim"
+
+            [log]
+            format = "json"
+        "#).unwrap();
+
+        assert_eq!(config.elevator_names(), vec!["A", "B"]);
+        assert_eq!(config.persons(), 10);
+        assert_eq!(config.mqtt().server_uri, "mqtt://broker:1883");
+        assert_eq!(config.log_format(), Ok(LogFormat::Json));
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn test_parse_motion_profiles() {
+        let config: Config = toml::from_str(r#"
+            [motion.Dorisch]
+            max_speed = 2.5
+
+            [motion.Ionisch]
+            acceleration = 0.8
+            jerk = 1.2
+        "#).unwrap();
+
+        assert_eq!(config.motion["Dorisch"], MotionProfile { max_speed: 2.5, ..Default::default() });
+        assert_eq!(config.motion["Ionisch"].max_speed, MotionProfile::default().max_speed);
+        assert!(config.validate().is_empty());
+        assert!(toml::from_str::<Config>("[motion.Dorisch]\nspeed = 2").is_err());
+    }
+
+    #[test]
+    fn test_validate_motion_profiles() {
+        let mut config = Config::default();
+        config.motion.insert("Gotisch".to_string(), MotionProfile::default());
+        config.motion.insert("Dorisch".to_string(), MotionProfile { acceleration: -1.0, ..Default::default() });
+
+        let errors = config.validate();
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|e| e.starts_with("motion.Gotisch")));
+        assert!(errors.iter().any(|e| e.starts_with("motion.Dorisch: acceleration")));
+    }
+
+    #[test]
+    fn test_parse_served_floors() {
+        let config: Config = toml::from_str(r#"
+            [served_floors]
+            Dorisch = ["Ground", "Third"]
+            Ionisch = ["Ground", "First", "Second"]
+        "#).unwrap();
+
+        assert_eq!(config.served_floors["Dorisch"], vec![Floor::Ground, Floor::Third]);
+        assert!(config.validate().is_empty());
+        assert!(toml::from_str::<Config>("[served_floors]\nDorisch = [\"Fourth\"]").is_err());
+    }
+
+    #[test]
+    fn test_validate_served_floors() {
+        let mut config = Config::default();
+        config.served_floors.insert("Gotisch".to_string(), vec![Floor::Ground, Floor::First]);
+        config.served_floors.insert("Dorisch".to_string(), vec![Floor::First, Floor::First]);
+
+        assert_eq!(config.validate(), vec![
+            "served_floors.Dorisch: mindestens zwei Ebenen werden benötigt",
+            "served_floors.Gotisch: unbekannter Aufzug",
+        ]);
+    }
+
+    #[test]
+    fn test_parse_rated_load() {
+        let config: Config = toml::from_str(r#"
+            [rated_load_kg]
+            Dorisch = 630
+            Gotisch = -1
+        "#).unwrap();
+
+        assert_eq!(config.rated_load_kg["Dorisch"], 630.0);
+        assert_eq!(config.validate(), vec![
+            "rated_load_kg.Gotisch: unbekannter Aufzug",
+            "rated_load_kg.Gotisch: muss größer als 0 sein",
+        ]);
+    }
+
+    #[test]
+    fn test_parse_energy_model() {
+        let config: Config = toml::from_str(r#"
+            [energy]
+            regeneration = 0.6
+            standby_w = 80
+        "#).unwrap();
+
+        assert_eq!(config.energy, Some(EnergyModel { regeneration: 0.6, standby_w: 80.0, ..Default::default() }));
+        assert!(config.validate().is_empty());
+
+        let config: Config = toml::from_str("[energy]\nefficiency = 1.2").unwrap();
+        assert_eq!(config.validate(), vec!["energy: efficiency muss zwischen 0 und 1 liegen"]);
+    }
+
+    #[test]
+    fn test_parse_parking_policy() {
+        let config: Config = toml::from_str(r#"
+            [parking]
+            strategy = "demand"
+        "#).unwrap();
+
+        assert_eq!(config.parking, Some(ParkingPolicy { strategy: ParkingStrategy::Demand, ..Default::default() }));
+        assert!(config.validate().is_empty());
+        assert!(toml::from_str::<Config>("[parking]\nstrategy = \"random\"").is_err());
+
+        let config: Config = toml::from_str("[parking]\nidle_timeout_ms = 0").unwrap();
+        assert_eq!(config.validate(), vec!["parking: idle_timeout_ms muss größer als 0 sein"]);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        assert!(toml::from_str::<Config>("elevator = 3").is_err());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::default();
+        assert_eq!(config.elevator_names(), vec!["Dorisch", "Ionisch", "Korinthisch"]);
+        assert_eq!(config.persons(), 5);
+        assert_eq!(config.http_port(), None);
+        assert_eq!(config.http_bind(), "127.0.0.1");
+        assert_eq!(config.mqtt(), MqttConfig::default());
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn test_elevator_count_generates_names() {
+        assert_eq!(Elevators::Count(4).names(), vec!["Dorisch", "Ionisch", "Korinthisch", "Aufzug_4"]);
+    }
+
+    #[test]
+    fn test_overlay_prefers_other() {
+        let file = Config { persons: Some(3), speed: Some(50), ..Default::default() };
+        let cli = Config { speed: Some(200), ..Default::default() };
+
+        let config = file.overlay(cli);
+        assert_eq!(config.persons, Some(3));
+        assert_eq!(config.speed, Some(200));
+    }
+
+    #[test]
+    fn test_validate_reports_all_errors() {
+        let config = Config {
+            elevators: Some(Elevators::Names(vec!["A".to_string(), "A".to_string()])),
+            speed: Some(0),
+            protocol: Some("v9".to_string()),
+            ..Default::default()
+        };
+
+        let errors = config.validate();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("elevators"));
+        assert!(errors[1].starts_with("speed"));
+        assert!(errors[2].starts_with("protocol"));
+    }
+}
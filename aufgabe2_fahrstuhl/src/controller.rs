@@ -1,25 +1,42 @@
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt::{Debug, Display, Formatter};
-use std::thread::spawn;
-use log::{info, trace};
+use std::time::Duration;
+use tracing::{debug, info, trace, warn};
 use serde::{Deserialize, Serialize};
 use tokio::{select, sync};
 use tokio::sync::broadcast::Sender;
 use tokio::sync::mpsc::Receiver;
 use tokio::task::JoinHandle;
+use tokio::time::Interval;
 use ControllerToElevatorsMsg::CloseDoors;
-use crate::msg::{ControllerToElevatorsMsg, ControllerToPersonsMsg, ElevatorToControllerMsg, PersonToControllerMsg};
-use crate::msg::ControllerToElevatorsMsg::{ElevatorMission, OpenDoors};
+use crate::msg::{ControllerToElevatorsMsg, ControllerToPersonsMsg, ElevatorToControllerMsg, PersonToControllerMsg, SimulationToControllerMsg};
+use crate::msg::ControllerToElevatorsMsg::{CancelMission, ElevatorMission, EmergencyStop, EndParking, OpenDoors, RetargetMission, StopOnTheWay};
 use crate::msg::ControllerToPersonsMsg::{ElevatorHalt, UpdateBoardingStatus};
-use crate::msg::ElevatorToControllerMsg::{DoorsClosed, DoorsClosing, DoorsOpened, DoorsOpening, ElevatorArrived, ElevatorMoving};
-use crate::msg::PersonToControllerMsg::{PersonChoosingFloor, PersonEnteredElevator, PersonEnteringElevator, PersonLeavingElevator, PersonLeftElevator, PersonRequestElevator};
+use crate::msg::ElevatorToControllerMsg::{CommandAck, DoorsClosed, DoorsClosing, DoorsOpened, DoorsOpening, ElevatorArrived, ElevatorMoving, ElevatorPassing, ElevatorStopped};
+use crate::msg::PersonToControllerMsg::{PersonChoosingFloor, PersonEnteredElevator, PersonEnteringElevator, PersonLeavingElevator, PersonLeftElevator, PersonRequestElevator, PersonTakingStairs, PersonTookStairs, PersonTransferring};
 use sync::mpsc;
 use DoorStatus::Open;
 use crate::controller::DoorStatus::Closed;
-use crate::mqtt::ElevatorMsg::{Missions, Moving, Passengers};
-use crate::mqtt::Send::ElevatorTopic;
-
-#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+use crate::msg::SimulationToControllerMsg::{Ask, Command, PublishSnapshot, Query as QueryMsg};
+use crate::msg::Rejection;
+use crate::mqtt::ElevatorMsg::{Energy, Load, Missions, Moving, Passengers};
+use crate::mqtt::Send::{ElevatorTopic, ReplyTopic, SimulationTopic};
+use crate::mqtt::{ElevatorCommand, Query, Reply, SimulationMsg};
+use crate::metrics::MetricsText;
+use crate::energy::{BuildingEnergy, EnergyModel, EnergyReport};
+use crate::kinematics::{floor_at, height};
+use crate::parking::{IdleCar, ParkingPolicy};
+use crate::person::Attributes;
+use crate::stats::Statistics;
+use crate::utils::{delay, is_paused, sim_time_ms, speed_factor};
+
+// Places per car, a wheelchair takes two, a stretcher or freight the whole car
+pub const CAPACITY: usize = 2;
+pub const DEFAULT_RATED_LOAD_KG: f64 = 180.0;
+// Share of the rated load from which a car gets no new calls while others are available
+const NEARLY_FULL: f64 = 0.8;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 pub enum Floor {
     Ground = 1,
     First = 2,
@@ -27,13 +44,39 @@ pub enum Floor {
     Third = 4
 }
 
+// Published as {"status": "Rejected", "reason": "Full"}
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "reason")]
 pub enum BoardingStatus {
     Accepted,
-    Rejected
+    Rejected(RejectReason)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    // No room left for the person
+    Full,
+    // Stepped in and the car exceeded its rated load
+    Overloaded,
+    // The doors closed before the person got in
+    DoorsNotOpen,
+    // The passengers travel the other way
+    WrongDirection,
+    // Emergency stop
+    OutOfService,
+    // The car does not serve the destination
+    ZoneMismatch,
+}
+
+impl RejectReason {
+
+    // Another car is on its way (or will be sent once the doors close), no new call needed
+    pub fn wait_for_next_car(&self) -> bool {
+        matches!(self, RejectReason::Full | RejectReason::Overloaded | RejectReason::WrongDirection | RejectReason::ZoneMismatch)
+    }
 }
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Serialize)]
 enum DoorStatus {
     Open,
     Closed
@@ -42,19 +85,77 @@ enum DoorStatus {
 pub struct ElevatorController {
     from_elevators: Receiver<ElevatorToControllerMsg>,
     from_persons: Receiver<PersonToControllerMsg>,
+    from_simulation: Receiver<SimulationToControllerMsg>,
     to_elevators: Sender<ControllerToElevatorsMsg>,
     to_persons: Sender<ControllerToPersonsMsg>,
     to_mqtt: mpsc::Sender<crate::mqtt::Send>,
     state: HashMap<String, ElevatorState>,
+    waiting: BTreeMap<Floor, Vec<String>>,
+    hall_calls: Vec<HallCall>,
+    // Requested destination per person, boarding a car that does not serve it is rejected
+    destinations: HashMap<String, Floor>,
+    // Leaving the car on a transfer floor, the journey goes on with another car
+    transferring: HashSet<String>,
+    // Persons without an entry have the default attributes
+    attributes: HashMap<String, Attributes>,
+    stats: Statistics,
+    snapshot_interval: Option<Duration>,
+    parking: Option<ParkingPolicy>,
+    // Idle cars at the last parking decision, the others park again when it changes
+    idle_cars: BTreeSet<String>,
+    // Idle timers send the car and the time it became idle
+    idle_tx: mpsc::Sender<(String, u64)>,
+    idle_rx: Receiver<(String, u64)>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HallCall {
+    floor: Floor,
+    elevator: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Snapshot {
+    sim_time_ms: u64,
+    speed: u64,
+    paused: bool,
+    elevators: Vec<ElevatorState>,
+    waiting: BTreeMap<Floor, Vec<String>>,
+    hall_calls: Vec<HallCall>,
 }
 
-struct ElevatorState {
+#[derive(Clone, Serialize)]
+pub struct ElevatorState {
     id: String,
     floor: Floor,
     mission: Option<Floor>,
     missions: VecDeque<Floor>,
     passengers: Vec<String>,
     door: DoorStatus,
+    // Emergency stop, gets no missions until retargeted
+    stopped: bool,
+    // Height of an emergency stop between floors, the next trip starts there
+    #[serde(skip)]
+    stopped_at: Option<f64>,
+    // Zone of the car, it passes the other floors without stopping
+    floors: Vec<Floor>,
+    // On the way to its parking floor, the first call cancels the trip
+    parking: bool,
+    #[serde(skip)]
+    idle_since: Option<u64>,
+    // Waited out its idle timer, parks again whenever the idle cars change
+    #[serde(skip)]
+    parked: bool,
+    // Slow persons getting in or out, the doors stay open for them
+    #[serde(skip)]
+    holding: HashSet<String>,
+    // Rejected persons waiting for the next car, their calls are dispatched again once the doors close
+    #[serde(skip)]
+    left_behind: Vec<String>,
+    rated_load_kg: f64,
+    load_kg: f64,
+    // Stepped in and overloaded the car, the doors stay open until the person is out again
+    overload: Option<String>,
 }
 
 impl Display for Floor {
@@ -69,6 +170,12 @@ impl Display for BoardingStatus {
     }
 }
 
+impl Display for RejectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
 impl Display for DoorStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         Debug::fmt(self, f)
@@ -77,8 +184,8 @@ impl Display for DoorStatus {
 
 impl Debug for ElevatorState {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ElevatorState {{ id: {}, floor: {:?}, mission={:?}, missions: {:?}, passengers: {:?}, door: {:?} }}",
-               self.id, self.floor, self.mission, self.missions, self.passengers, self.door)
+        write!(f, "ElevatorState {{ id: {}, floor: {:?}, mission={:?}, missions: {:?}, passengers: {:?}, door: {:?}, stopped: {}, parking: {} }}",
+               self.id, self.floor, self.mission, self.missions, self.passengers, self.door, self.stopped, self.parking)
     }
 }
 
@@ -97,14 +204,53 @@ impl ElevatorState {
             missions: VecDeque::new(),
             passengers: Vec::new(),
             door: Closed,
+            stopped: false,
+            stopped_at: None,
+            floors: vec![Floor::Ground, Floor::First, Floor::Second, Floor::Third],
+            parking: false,
+            idle_since: None,
+            parked: false,
+            holding: HashSet::new(),
+            left_behind: Vec::new(),
+            rated_load_kg: DEFAULT_RATED_LOAD_KG,
+            load_kg: 0.0,
+            overload: None,
         }
     }
+
+    fn nearly_full(&self) -> bool {
+        self.load_kg >= NEARLY_FULL * self.rated_load_kg
+    }
+
+    // Nothing to do, a parking trip counts as idle
+    fn idle(&self) -> bool {
+        (self.mission.is_none() || self.parking) && self.missions.is_empty() && !self.stopped && self.door == Closed && self.passengers.is_empty()
+    }
+
+    // Metres above the ground floor, the start of the next stretch of travel
+    fn height(&self) -> f64 {
+        self.stopped_at.unwrap_or(height(self.floor))
+    }
+
+    fn serves(&self, floor: Floor) -> bool {
+        self.floors.contains(&floor)
+    }
+
+    // Moving (or about to move) and `floor` still lies ahead on the way to the mission
+    fn passes(&self, floor: Floor) -> bool {
+        self.mission.is_some_and(|mission| floor > self.floor.min(mission) && floor < self.floor.max(mission))
+    }
 }
 
 impl ElevatorController {
 
     pub fn init(mut self) -> JoinHandle<()> {
         tokio::spawn(async move {
+            let mut snapshot_interval = self.snapshot_interval.map(tokio::time::interval);
+            let elevators: Vec<String> = self.state.keys().cloned().collect();
+            for elevator in elevators {
+                self.went_idle(&elevator);
+            }
             loop {
                 select! {
                     Some(msg) = self.from_elevators.recv() => {
@@ -112,9 +258,15 @@ impl ElevatorController {
                             ElevatorMoving(elevator, from, to) => {
                                 self.handle_elevator_moving(elevator.clone(), from, to).await;
                             }
+                            ElevatorPassing(elevator, floor) => {
+                                self.handle_elevator_passing(elevator.clone(), floor).await;
+                            }
                             ElevatorArrived(elevator, dest) => {
                                 self.handle_elevator_arrived(elevator.clone(), dest).await;
                             }
+                            ElevatorStopped(elevator, height) => {
+                                self.handle_elevator_stopped(elevator, height).await;
+                            }
                             DoorsOpening(elevator) => {
                                 self.handle_doors_opening(elevator.clone()).await;
                             }
@@ -127,14 +279,20 @@ impl ElevatorController {
                             DoorsClosed(elevator) => {
                                 self.handle_doors_closed(elevator.clone()).await;
                             }
+                            CommandAck(elevator, command, result) => {
+                                self.handle_command_ack(elevator, command, result).await;
+                            }
                         }
-                        info!("{:?}", self.state);
+                        trace!(state = ?self.state);
                     }
                     Some(msg) = self.from_persons.recv() => {
-                        info!("{:?}", msg);
+                        debug!(?msg);
                         match msg {
-                            PersonRequestElevator(floor) => {
-                                self.handle_person_request_elevator(floor).await;
+                            PersonRequestElevator(person, floor, dest, attributes) => {
+                                if !attributes.is_default() {
+                                    self.attributes.insert(person.clone(), attributes);
+                                }
+                                self.handle_person_request_elevator(person, floor, dest).await;
                             }
                             PersonEnteringElevator(person, elevator) => {
                                 self.handle_person_entering_elevator(person, elevator.clone()).await;
@@ -151,8 +309,39 @@ impl ElevatorController {
                             PersonChoosingFloor(person, elevator, floor) => {
                                 self.handle_person_choosing_floor(person, elevator.clone(), floor).await;
                             }
+                            PersonTransferring(person, floor) => {
+                                self.handle_person_transferring(person, floor).await;
+                            }
+                            PersonTakingStairs(person) => {
+                                self.handle_person_taking_stairs(person).await;
+                            }
+                            PersonTookStairs(person) => {
+                                self.handle_person_took_stairs(person).await;
+                            }
+                        }
+                        trace!(state = ?self.state);
+                    }
+                    Some(msg) = self.from_simulation.recv() => {
+                        match msg {
+                            PublishSnapshot => {
+                                self.handle_publish_snapshot().await;
+                            }
+                            QueryMsg(query, reply) => {
+                                self.handle_query(query, reply).await;
+                            }
+                            Ask(query, answer) => {
+                                let _ = answer.send(self.answer(&query));
+                            }
+                            Command(elevator, command) => {
+                                self.handle_command(elevator, command).await;
+                            }
                         }
-                        info!("{:?}", self.state);
+                    }
+                    Some((elevator, since)) = self.idle_rx.recv() => {
+                        self.handle_idle_timeout(elevator, since).await;
+                    }
+                    _ = ElevatorController::next_tick(&mut snapshot_interval) => {
+                        self.handle_publish_snapshot().await;
                     }
                 }
             }
@@ -163,34 +352,192 @@ impl ElevatorController {
                to_elevators: Sender<ControllerToElevatorsMsg>,
                from_persons: Receiver<PersonToControllerMsg>,
                to_persons: Sender<ControllerToPersonsMsg>,
+               from_simulation: Receiver<SimulationToControllerMsg>,
                to_mqtt: mpsc::Sender<crate::mqtt::Send>,
                elevators: Vec<String>) -> Self {
         let mut state = HashMap::new();
+        let mut stats = Statistics::default();
         for elevator in elevators {
+            stats.halted(&elevator, sim_time_ms());
             state.insert(elevator.clone(), ElevatorState::new(elevator.clone()));
         }
+        let (idle_tx, idle_rx) = mpsc::channel(100);
         ElevatorController {
             from_elevators,
             from_persons,
+            from_simulation,
             to_elevators,
             to_persons,
             to_mqtt,
             state,
+            waiting: BTreeMap::new(),
+            hall_calls: Vec::new(),
+            destinations: HashMap::new(),
+            transferring: HashSet::new(),
+            attributes: HashMap::new(),
+            stats,
+            snapshot_interval: None,
+            parking: None,
+            idle_cars: BTreeSet::new(),
+            idle_tx,
+            idle_rx,
+        }
+    }
+
+    pub fn with_snapshot_interval(mut self, interval: Duration) -> Self {
+        self.snapshot_interval = Some(interval);
+        self
+    }
+
+    // Cars without served floors stop everywhere
+    pub fn with_served_floors(mut self, elevator: &str, floors: Vec<Floor>) -> Self {
+        if let Some(state) = self.state.get_mut(elevator) {
+            state.floors = floors;
+        }
+        self
+    }
+
+    // Idle cars park after the timeout, without a policy they stay where they are
+    pub fn with_parking(mut self, policy: ParkingPolicy) -> Self {
+        self.parking = Some(policy);
+        self
+    }
+
+    pub fn with_rated_load(mut self, elevator: &str, rated_load_kg: f64) -> Self {
+        if let Some(state) = self.state.get_mut(elevator) {
+            state.rated_load_kg = rated_load_kg;
+        }
+        self
+    }
+
+    pub fn with_energy_model(mut self, model: EnergyModel) -> Self {
+        self.stats = self.stats.with_energy_model(model);
+        self
+    }
+
+    async fn next_tick(interval: &mut Option<Interval>) {
+        match interval {
+            Some(interval) => { interval.tick().await; }
+            None => std::future::pending().await,
+        }
+    }
+
+    fn elevators(&self) -> Vec<ElevatorState> {
+        let mut elevators: Vec<ElevatorState> = self.state.values().cloned().collect();
+        elevators.sort_by(|a, b| a.id.cmp(&b.id));
+        elevators
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            sim_time_ms: sim_time_ms(),
+            speed: speed_factor(),
+            paused: is_paused(),
+            elevators: self.elevators(),
+            waiting: self.waiting.clone(),
+            hall_calls: self.hall_calls.clone(),
         }
     }
 
+    pub fn metrics(&self) -> String {
+        let summary = self.stats.summary(sim_time_ms());
+        let elevators = self.elevators();
+        let mut text = MetricsText::default();
+
+        let waiting: Vec<_> = [Floor::Ground, Floor::First, Floor::Second, Floor::Third].iter()
+            .map(|floor| (vec![("floor", floor.to_string())], self.waiting.get(floor).map_or(0, |p| p.len() as u64)))
+            .collect();
+        text.gauge("elevator_persons_waiting", "Persons waiting per floor", &waiting);
+        let passengers: Vec<_> = elevators.iter()
+            .map(|e| (vec![("car", e.id.clone())], e.passengers.len() as u64))
+            .collect();
+        text.gauge("elevator_car_passengers", "Passengers per car", &passengers);
+        let queue: Vec<_> = elevators.iter()
+            .map(|e| (vec![("car", e.id.clone())], e.missions.len() as u64))
+            .collect();
+        text.gauge("elevator_car_queue_length", "Queued missions per car", &queue);
+
+        text.counter("elevator_trips_total", "Trips started by all cars", &[(vec![], summary.trips)]);
+        text.counter("elevator_parking_trips_total", "Parking trips started by all cars", &[(vec![], summary.parking_trips)]);
+        text.counter("elevator_door_cycles_total", "Door openings of all cars", &[(vec![], summary.door_cycles)]);
+        text.counter("elevator_boardings_total", "Boarding attempts by result", &[
+            (vec![("result", "accepted".to_string())], summary.boardings_accepted),
+            (vec![("result", "rejected".to_string())], summary.boardings_rejected),
+        ]);
+        text.counter("elevator_persons_completed_total", "Persons that reached their destination", &[(vec![], summary.persons_completed)]);
+        text.counter("elevator_persons_took_stairs_total", "Persons that walked instead", &[(vec![], summary.persons_took_stairs)]);
+
+        text.histogram("elevator_wait_time_seconds", "Simulated time from request to boarding", self.stats.wait_times());
+        text.histogram("elevator_transfer_wait_time_seconds", "Simulated time waiting on a transfer floor", self.stats.transfer_wait_times());
+        text.histogram("elevator_journey_time_seconds", "Simulated time from request to leaving the car", self.stats.journey_times());
+        text.finish()
+    }
+
     // Handlers
 
     async fn handle_elevator_moving(&mut self, elevator: String, from: Floor, to: Floor) {
-        ElevatorController::moving(self.to_mqtt.clone(), elevator, from, to);
+        let state = self.state.get_mut(&elevator).unwrap();
+        match state.parking {
+            true => self.stats.parking_trip(),
+            false => {
+                self.stats.trip();
+                state.parked = false;
+            }
+        }
+        self.stats.departed(&elevator, sim_time_ms());
+        state.idle_since = None;
+        ElevatorController::moving(self.to_mqtt.clone(), elevator, from, to, state.parking);
+    }
+
+    async fn handle_elevator_passing(&mut self, elevator: String, floor: Floor) {
+        let state = self.state.get_mut(&elevator).unwrap();
+        self.stats.travelled(&elevator, height(floor) - state.height(), state.load_kg);
+        state.floor = floor;
+        state.stopped_at = None;
     }
 
     async fn handle_elevator_arrived(&mut self, elevator: String, dest: Floor) {
         let state = self.state.get_mut(&elevator).unwrap();
 
+        self.stats.travelled(&elevator, height(dest) - state.height(), state.load_kg);
         state.floor = dest;
+        state.stopped_at = None;
+
+        let parked = state.parking;
+        if parked {
+            // Parks with closed doors
+            info!(%elevator, floor = %dest, "Aufzug geparkt");
+            state.parking = false;
+            state.mission = None;
+        } else {
+            let _ = self.to_elevators.send(OpenDoors(elevator.clone())).unwrap();
+        }
+
+        let now = sim_time_ms();
+        self.stats.halted(&elevator, now);
+        ElevatorController::energy(self.to_mqtt.clone(), elevator.clone(), self.stats.energy(&elevator, now), self.stats.building_energy(now));
+        if parked {
+            self.start_next_mission_if_idle(&elevator);
+            // The idle cars may have changed on the way
+            self.park();
+        }
+    }
+
+    // The car stands still after an emergency stop, possibly between floors
+    async fn handle_elevator_stopped(&mut self, elevator: String, stop: f64) {
+        let state = self.state.get_mut(&elevator).unwrap();
 
-        let _ = self.to_elevators.send(OpenDoors(elevator.clone())).unwrap();
+        self.stats.travelled(&elevator, stop - state.height(), state.load_kg);
+        match floor_at(stop) {
+            Some(floor) => {
+                state.floor = floor;
+                state.stopped_at = None;
+            }
+            None => state.stopped_at = Some(stop),
+        }
+        let now = sim_time_ms();
+        self.stats.halted(&elevator, now);
+        ElevatorController::energy(self.to_mqtt.clone(), elevator.clone(), self.stats.energy(&elevator, now), self.stats.building_energy(now));
     }
 
     async fn handle_doors_opening(&mut self, elevator: String) {
@@ -199,6 +546,8 @@ impl ElevatorController {
         state.mission = None;
         state.missions.retain(|&floor| floor != state.floor);
         state.door = Open;
+        self.stats.door_cycle(&elevator);
+        self.hall_calls.retain(|call| call.floor != state.floor || call.elevator != elevator);
 
         let _ = self.to_persons.send(ElevatorHalt(elevator.clone(), state.floor));
 
@@ -207,58 +556,240 @@ impl ElevatorController {
         ElevatorController::missions(self.to_mqtt.clone(), id, missions);
     }
 
-    async fn handle_doors_closing(&mut self, elevator: String) {
+    async fn handle_doors_closing(&mut self, _elevator: String) {
 
     }
 
     async fn handle_doors_opened(&mut self, elevator: String) {
-        let _ = self.to_elevators.send(CloseDoors(elevator.clone())).unwrap();
+        self.close_doors(&elevator);
     }
 
     async fn handle_doors_closed(&mut self, elevator: String) {
         let state = self.state.get_mut(&elevator).unwrap();
 
         state.door = Closed;
+        let floor = state.floor;
+        let left_behind = std::mem::take(&mut state.left_behind);
 
         self.start_next_mission_if_idle(&elevator);
+        // The car left them behind, their calls go to the next car
+        for person in left_behind {
+            if let Some(&dest) = self.destinations.get(&person)
+                && self.waiting.get(&floor).is_some_and(|waiting| waiting.contains(&person)) {
+                self.dispatch(&person, floor, dest);
+            }
+        }
+        self.went_idle(&elevator);
     }
 
     fn start_next_mission_if_idle(&mut self, elevator_id: &str) {
         let state = self.state.get_mut(elevator_id).unwrap();
 
-        if state.mission.is_none() {
-            if let Some(next_floor) = state.missions.pop_front() {
-                state.mission = Some(next_floor);
-                let _ = self.to_elevators.send(ElevatorMission(elevator_id.to_string(), next_floor));
+        if state.mission.is_none() && !state.stopped && let Some(next_floor) = state.missions.pop_front() {
+            state.mission = Some(next_floor);
+            let _ = self.to_elevators.send(ElevatorMission(elevator_id.to_string(), next_floor));
+            // The nearest queued floor on the way is served first if the car can still brake for it
+            let floor = state.floor;
+            if let Some(&stop) = state.missions.iter().filter(|&&f| state.passes(f)).min_by_key(|&&f| (f as i8 - floor as i8).abs()) {
+                let _ = self.to_elevators.send(StopOnTheWay(elevator_id.to_string(), stop));
             }
         }
     }
 
-    async fn handle_person_request_elevator(&mut self, target: Floor) {
-        let (_, elevator) = self.state
+    async fn handle_command(&mut self, elevator: String, command: ElevatorCommand) {
+        if !self.state.contains_key(&elevator) {
+            warn!(%elevator, "Befehl für unbekannten Aufzug");
+            return;
+        }
+        let msg = match command {
+            ElevatorCommand::CancelMission => CancelMission(elevator),
+            ElevatorCommand::RetargetMission { floor } => RetargetMission(elevator, floor),
+            ElevatorCommand::EmergencyStop => EmergencyStop(elevator),
+        };
+        let _ = self.to_elevators.send(msg);
+    }
+
+    async fn handle_command_ack(&mut self, elevator: String, command: ElevatorCommand, result: Result<Option<Floor>, Rejection>) {
+        let state = self.state.get_mut(&elevator).unwrap();
+        let Ok(stop) = result else {
+            // A rejected stop on the way stays queued
+            debug!(%elevator, ?command, ?result, "Befehl abgelehnt");
+            return;
+        };
+        let released = state.stopped && matches!(command, ElevatorCommand::RetargetMission { .. });
+        match command {
+            // Stop on the way (or leaving an emergency stop), the previous mission comes next
+            ElevatorCommand::RetargetMission { floor } => {
+                // A parking trip is not resumed
+                if let Some(mission) = state.mission && mission != floor && !state.missions.contains(&mission) && !state.parking {
+                    state.missions.push_front(mission);
+                }
+                state.mission = Some(floor);
+                state.stopped = false;
+                state.parking = false;
+            }
+            // Stops early, the cancelled destination is served after the stop (a parking trip is dropped)
+            ElevatorCommand::CancelMission => {
+                info!(%elevator, ?stop, "Auftrag abgebrochen");
+                if let Some(mission) = state.mission && state.mission != stop && !state.missions.contains(&mission) && !state.parking {
+                    state.missions.push_front(mission);
+                }
+                state.mission = stop;
+                state.parking = false;
+            }
+            ElevatorCommand::EmergencyStop => {
+                warn!(%elevator, ?stop, "Nothalt");
+                if let Some(mission) = state.mission.take() && !state.missions.contains(&mission) && !state.parking {
+                    state.missions.push_front(mission);
+                }
+                state.stopped = true;
+                state.parking = false;
+            }
+        }
+        let missions = state.missions.iter().cloned().collect();
+        ElevatorController::missions(self.to_mqtt.clone(), elevator, missions);
+        if released {
+            self.dispatch_pending();
+        }
+        self.repark_if_idle_changed();
+    }
+
+    // Calls made while every car for the trip stood in an emergency stop have no car yet
+    fn dispatch_pending(&mut self) {
+        let pending: Vec<(String, Floor, Floor)> = self.waiting.iter()
+            .flat_map(|(&floor, persons)| persons.iter().map(move |person| (person, floor)))
+            .filter_map(|(person, floor)| self.destinations.get(person).map(|&dest| (person.clone(), floor, dest)))
+            .filter(|&(_, floor, dest)| !self.hall_calls.iter().any(|call| call.floor == floor && self.state[&call.elevator].serves(dest)))
+            .collect();
+        for (person, floor, dest) in pending {
+            self.dispatch(&person, floor, dest);
+        }
+    }
+
+    async fn handle_person_request_elevator(&mut self, person: String, target: Floor, dest: Floor) {
+        if !self.state.values().any(|s| s.serves(target) && s.serves(dest)) {
+            warn!(%person, from = %target, to = %dest, "Kein Aufzug bedient diese Fahrt");
+            return;
+        }
+        self.stats.requested(&person, target, sim_time_ms());
+        self.destinations.insert(person.clone(), dest);
+        let waiting = self.waiting.entry(target).or_default();
+        if !waiting.contains(&person) {
+            waiting.push(person.clone());
+        }
+        self.dispatch(&person, target, dest);
+    }
+
+    // Assigns the call of a waiting person to a car, also again when a car left them behind
+    fn dispatch(&mut self, person: &str, target: Floor, dest: Floor) {
+        let attributes = self.attributes.get(person).copied().unwrap_or_default();
+        let occupied = |s: &ElevatorState| s.passengers.iter().map(|p| self.attributes.get(p).map_or(1, Attributes::space)).sum::<usize>();
+        // A car with room for the person right now comes first, on a tie the lowest id
+        let Some((_, elevator)) = self.state
             .iter_mut()
-            .min_by_key(|(_, s)| s.missions.len())
-            .unwrap();
+            .filter(|(_, s)| !s.stopped && s.serves(target) && s.serves(dest))
+            .min_by_key(|(id, s)| (s.nearly_full(), attributes.space() > 1 && occupied(s) + attributes.space() > CAPACITY, s.missions.len(), !s.passes(target), *id)) else {
+            warn!(floor = %target, "Kein Aufzug verfügbar, alle Kabinen für diese Fahrt im Nothalt");
+            return;
+        };
+
+        let call = HallCall { floor: target, elevator: elevator.id.clone() };
+        if !self.hall_calls.contains(&call) {
+            self.hall_calls.push(call);
+        }
 
         if elevator.mission.is_none() && elevator.missions.is_empty() {
             elevator.mission = Some(target);
             let _ = self.to_elevators.send(ElevatorMission(elevator.id.clone(), target));
+        } else if elevator.parking && elevator.mission == Some(target) {
+            // The parking trip serves the call, the doors open on arrival
+            elevator.parking = false;
+        } else if attributes.urgent() && elevator.missions.front() != Some(&target) {
+            // Next stop after the current mission
+            info!(%person, floor = %target, elevator = %elevator.id, "Vorrangiger Ruf");
+            elevator.missions.retain(|&floor| floor != target);
+            elevator.missions.push_front(target);
+            if elevator.passes(target) {
+                let _ = self.to_elevators.send(StopOnTheWay(elevator.id.clone(), target));
+            } else if elevator.parking && elevator.missions.len() == 1 {
+                let _ = self.to_elevators.send(EndParking(elevator.id.clone()));
+            }
         } else if !elevator.missions.contains(&target) {
             elevator.missions.push_back(target);
+            if elevator.passes(target) {
+                let _ = self.to_elevators.send(StopOnTheWay(elevator.id.clone(), target));
+            } else if elevator.parking && elevator.missions.len() == 1 {
+                // The first call ends the parking trip at the next floor the car can stop at
+                let _ = self.to_elevators.send(EndParking(elevator.id.clone()));
+            }
         }
+        self.repark_if_idle_changed();
     }
 
     async fn handle_person_entering_elevator(&mut self, person: String, elevator: String) {
-
+        self.hold(person, elevator);
     }
 
     async fn handle_person_entered_elevator(&mut self, person: String, elevator: String) {
+        self.release(&person, &elevator);
+        let space = |p: &String| self.attributes.get(p).map_or(1, Attributes::space);
+        let urgent = |p: &String| self.attributes.get(p).is_some_and(Attributes::urgent);
+        let mass = self.mass_kg(&person);
         let state = self.state.get_mut(&elevator).unwrap();
-        if state.passengers.len() < 2 && state.door.eq(&Open) {
+        let served = self.destinations.get(&person).is_none_or(|&dest| state.serves(dest));
+        let occupied: usize = state.passengers.iter().map(space).sum();
+        // Room kept for urgent persons waiting on this floor for a trip the car serves
+        let reserved: usize = match urgent(&person) {
+            true => 0,
+            false => self.waiting.get(&state.floor).into_iter().flatten()
+                .filter(|&p| *p != person && urgent(p) && self.destinations.get(p).is_none_or(|&dest| state.serves(dest)))
+                .map(space)
+                .sum(),
+        };
+        // Riding along is fine unless every passenger travels the other way
+        let wrong_direction = self.destinations.get(&person).is_some_and(|&dest| {
+            let mut others = state.passengers.iter()
+                .filter_map(|p| self.destinations.get(p))
+                .filter(|&&other| other != state.floor)
+                .peekable();
+            others.peek().is_some() && others.all(|&other| (other > state.floor) != (dest > state.floor))
+        });
+        let reason = if state.stopped {
+            Some(RejectReason::OutOfService)
+        } else if state.door.ne(&Open) {
+            Some(RejectReason::DoorsNotOpen)
+        } else if !served {
+            Some(RejectReason::ZoneMismatch)
+        } else if wrong_direction {
+            Some(RejectReason::WrongDirection)
+        } else if occupied + space(&person) + reserved > CAPACITY {
+            Some(RejectReason::Full)
+        } else if state.load_kg + mass > state.rated_load_kg {
+            // Steps in, the car reports the overload and the person has to get out again
+            warn!(%person, %elevator, load_kg = state.load_kg + mass, rated_load_kg = state.rated_load_kg, "Überlast");
+            state.overload = Some(person.clone());
+            ElevatorController::load(self.to_mqtt.clone(), elevator.clone(), state.load_kg + mass, state.rated_load_kg);
+            Some(RejectReason::Overloaded)
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            debug!(%person, %elevator, %reason, "Einstieg abgelehnt");
+            if reason.wait_for_next_car() && !state.left_behind.contains(&person) {
+                state.left_behind.push(person.clone());
+            }
+            self.stats.rejected();
+            let _ = self.to_persons.send(UpdateBoardingStatus(person, elevator.clone(), BoardingStatus::Rejected(reason)));
+        } else {
             state.passengers.push(person.clone());
+            state.load_kg += mass;
+            ElevatorController::load(self.to_mqtt.clone(), elevator.clone(), state.load_kg, state.rated_load_kg);
+            for waiting in self.waiting.values_mut() {
+                waiting.retain(|p| p.ne(&person));
+            }
+            self.waiting.retain(|_, waiting| !waiting.is_empty());
+            self.stats.boarded(&person, &elevator, sim_time_ms());
             let _ = self.to_persons.send(UpdateBoardingStatus(person, elevator.clone(), BoardingStatus::Accepted));
-        } else {
-            let _ = self.to_persons.send(UpdateBoardingStatus(person, elevator.clone(), BoardingStatus::Rejected));
         }
 
         let passengers = state.passengers.clone();
@@ -266,19 +797,81 @@ impl ElevatorController {
     }
 
     async fn handle_person_leaving_elevator(&mut self, person: String, elevator: String) {
-
+        self.hold(person, elevator);
     }
 
     async fn handle_person_left_elevator(&mut self, person: String, elevator: String) {
+        let mass = self.mass_kg(&person);
         let state = self.state.get_mut(&elevator).unwrap();
 
+        let relieved = state.overload.as_ref() == Some(&person);
+        if relieved {
+            info!(%person, %elevator, "Überlast behoben");
+            state.overload = None;
+            ElevatorController::load(self.to_mqtt.clone(), elevator.clone(), state.load_kg, state.rated_load_kg);
+        }
+        if state.passengers.contains(&person) {
+            state.load_kg = (state.load_kg - mass).max(0.0);
+            ElevatorController::load(self.to_mqtt.clone(), elevator.clone(), state.load_kg, state.rated_load_kg);
+            if self.transferring.remove(&person) {
+                self.stats.transferred(&person, sim_time_ms());
+            } else {
+                self.stats.completed(&person, sim_time_ms());
+                self.destinations.remove(&person);
+                self.attributes.remove(&person);
+            }
+        }
         state.passengers.retain(|x| { x.ne(&person) });
 
         let passengers = state.passengers.clone();
+        self.release(&person, &elevator);
+        if relieved {
+            self.close_doors(&elevator);
+        }
         ElevatorController::passengers(self.to_mqtt.clone(), elevator, passengers);
     }
 
+    // The call is withdrawn as soon as the person starts walking
+    async fn handle_person_taking_stairs(&mut self, person: String) {
+        info!(%person, "Person nimmt die Treppe");
+        for waiting in self.waiting.values_mut() {
+            waiting.retain(|p| p.ne(&person));
+        }
+        self.waiting.retain(|_, waiting| !waiting.is_empty());
+        self.transferring.remove(&person);
+        self.destinations.remove(&person);
+        self.attributes.remove(&person);
+        self.stats.taking_stairs(&person);
+
+        // Nobody waits there any more, unless a passenger gets off there the car need not stop
+        let (abandoned, calls): (Vec<HallCall>, Vec<HallCall>) = std::mem::take(&mut self.hall_calls)
+            .into_iter()
+            .partition(|call| !self.waiting.contains_key(&call.floor));
+        self.hall_calls = calls;
+        for call in abandoned {
+            let Some(state) = self.state.get_mut(&call.elevator) else { continue };
+            let needed = state.passengers.iter().any(|p| self.stats.journey(p).and_then(|j| j.destination) == Some(call.floor));
+            if needed || !state.missions.contains(&call.floor) {
+                continue;
+            }
+            state.missions.retain(|&floor| floor != call.floor);
+            let missions = state.missions.iter().cloned().collect();
+            ElevatorController::missions(self.to_mqtt.clone(), call.elevator, missions);
+        }
+    }
+
+    async fn handle_person_took_stairs(&mut self, person: String) {
+        info!(%person, "Person hat die Treppe genommen");
+        self.stats.took_stairs(&person, sim_time_ms());
+    }
+
+    async fn handle_person_transferring(&mut self, person: String, floor: Floor) {
+        debug!(%person, %floor, "Umstieg");
+        self.transferring.insert(person);
+    }
+
     async fn handle_person_choosing_floor(&mut self, person: String, elevator: String, dest: Floor) {
+        self.stats.destination_chosen(&person, dest);
         let state = self.state.get_mut(&elevator).unwrap();
 
         if !state.missions.contains(&dest) {
@@ -288,21 +881,151 @@ impl ElevatorController {
         }
 
         if state.door.eq(&Open) {
-            let _ = self.to_elevators.send(CloseDoors(elevator));
+            self.close_doors(&elevator);
         } else {
             self.start_next_mission_if_idle(&elevator);
         }
     }
 
+    // Slow persons keep the doors open until they are through
+    fn hold(&mut self, person: String, elevator: String) {
+        if self.attributes.get(&person).is_some_and(Attributes::slow) {
+            debug!(%person, %elevator, "Türen bleiben offen");
+            self.state.get_mut(&elevator).unwrap().holding.insert(person);
+        }
+    }
+
+    fn release(&mut self, person: &str, elevator: &str) {
+        if self.state.get_mut(elevator).unwrap().holding.remove(person) {
+            self.close_doors(elevator);
+        }
+    }
+
+    // Not while a slow person is in the doorway or the car is overloaded
+    fn close_doors(&self, elevator: &str) {
+        let state = &self.state[elevator];
+        if state.door == Open && state.holding.is_empty() && state.overload.is_none() {
+            let _ = self.to_elevators.send(CloseDoors(elevator.to_string()));
+        }
+    }
+
+    fn mass_kg(&self, person: &str) -> f64 {
+        self.attributes.get(person).and_then(|a| a.mass_kg).unwrap_or(self.stats.energy_model().person_mass_kg)
+    }
+
+    // Starts the idle timer of a car without anything to do
+    fn went_idle(&mut self, elevator: &str) {
+        let Some(policy) = self.parking else {
+            return;
+        };
+        let state = self.state.get_mut(elevator).unwrap();
+        if state.mission.is_none() && state.idle() {
+            let since = sim_time_ms();
+            state.idle_since = Some(since);
+            ElevatorController::idle_timer(self.idle_tx.clone(), elevator.to_string(), since, policy.idle_timeout_ms);
+        }
+        self.repark_if_idle_changed();
+    }
+
+    async fn handle_idle_timeout(&mut self, elevator: String, since: u64) {
+        if self.parking.is_none() {
+            return;
+        }
+        let Some(state) = self.state.get_mut(&elevator) else {
+            return;
+        };
+        // Busy again since the timer started
+        if state.idle_since != Some(since) || state.mission.is_some() || !state.idle() {
+            return;
+        }
+        state.idle_since = None;
+        state.parked = true;
+        self.park();
+    }
+
+    // Sends every idle car whose timer ran out to its parking floor, the floors depend on all idle cars
+    fn park(&mut self) {
+        let Some(policy) = self.parking else {
+            return;
+        };
+        let cars: Vec<IdleCar> = self.elevators_idle().collect();
+        let targets = policy.targets(&cars, self.stats.calls());
+        self.idle_cars = targets.keys().cloned().collect();
+
+        for (elevator, target) in targets {
+            let state = self.state.get_mut(&elevator).unwrap();
+            // Still waiting for its timer, or on the way (a parking trip is checked again on arrival)
+            if !state.parked || state.mission.is_some() || target == state.floor {
+                continue;
+            }
+            info!(%elevator, floor = %target, strategy = ?policy.strategy, "Aufzug fährt zum Parken");
+            state.parking = true;
+            state.mission = Some(target);
+            let _ = self.to_elevators.send(ElevatorMission(elevator, target));
+        }
+    }
+
+    // A car joined or left the idle cars, the parked ones spread out again
+    fn repark_if_idle_changed(&mut self) {
+        if self.parking.is_some() && self.elevators_idle().map(|car| car.id.to_string()).collect::<BTreeSet<_>>() != self.idle_cars {
+            self.park();
+        }
+    }
+
+    fn elevators_idle(&self) -> impl Iterator<Item = IdleCar<'_>> {
+        self.state.values()
+            .filter(|state| state.idle())
+            .map(|state| IdleCar { id: &state.id, floor: state.floor, floors: &state.floors })
+    }
+
+    async fn handle_publish_snapshot(&mut self) {
+        let msg = SimulationTopic {
+            msg: SimulationMsg::Snapshot(self.snapshot()),
+        };
+        let _ = self.to_mqtt.send(msg).await;
+        let energy = SimulationTopic {
+            msg: SimulationMsg::Energy(self.stats.building_energy(sim_time_ms())),
+        };
+        let _ = self.to_mqtt.send(energy).await;
+    }
+
+    async fn handle_query(&mut self, query: Query, reply: Reply) {
+        let result = self.answer(&query);
+        let _ = self.to_mqtt.send(ReplyTopic { reply, result }).await;
+    }
+
+    fn answer(&self, query: &Query) -> serde_json::Value {
+        match query {
+            Query::Elevators => serde_json::json!(self.elevators()),
+            Query::Stats => serde_json::json!(self.stats.summary(sim_time_ms())),
+            Query::Metrics => serde_json::json!(self.metrics()),
+            Query::Snapshot => serde_json::json!(self.snapshot()),
+            Query::Person(id) => match self.stats.journey(id) {
+                Some(journey) => serde_json::json!(journey),
+                None => serde_json::json!({ "error": format!("Unknown person {}", id) }),
+            },
+        }
+    }
+
+    // Timers
+
+    fn idle_timer(idle_tx: mpsc::Sender<(String, u64)>, elevator: String, since: u64, timeout_ms: u64) {
+        tokio::spawn(async move {
+            delay(timeout_ms).await;
+            let _ = idle_tx.send((elevator, since)).await;
+        });
+    }
+
     // MQTT Updates
 
-    fn moving(to_mqtt: mpsc::Sender<crate::mqtt::Send>, elevator: String, from: Floor, to: Floor) {
+    fn moving(to_mqtt: mpsc::Sender<crate::mqtt::Send>, elevator: String, from: Floor, to: Floor, parking: bool) {
         tokio::spawn(async move {
             let msg = ElevatorTopic {
                 id: elevator,
                 msg: Moving {
                     from,
-                    to
+                    to,
+                    parking
                 },
             };
             let _ = to_mqtt.send(msg).await;
@@ -321,6 +1044,27 @@ impl ElevatorController {
         });
     }
 
+    fn energy(to_mqtt: mpsc::Sender<crate::mqtt::Send>, elevator: String, report: EnergyReport, building: BuildingEnergy) {
+        tokio::spawn(async move {
+            let _ = to_mqtt.send(ElevatorTopic { id: elevator, msg: Energy(report) }).await;
+            let _ = to_mqtt.send(SimulationTopic { msg: SimulationMsg::Energy(building) }).await;
+        });
+    }
+
+    fn load(to_mqtt: mpsc::Sender<crate::mqtt::Send>, elevator: String, load_kg: f64, rated_load_kg: f64) {
+        tokio::spawn(async move {
+            let msg = ElevatorTopic {
+                id: elevator,
+                msg: Load {
+                    load_kg,
+                    percent: load_kg / rated_load_kg * 100.0,
+                    overloaded: load_kg > rated_load_kg,
+                },
+            };
+            let _ = to_mqtt.send(msg).await;
+        });
+    }
+
     fn missions(to_mqtt: mpsc::Sender<crate::mqtt::Send>, elevator: String, missions: Vec<Floor>) {
         tokio::spawn(async move {
             let msg = ElevatorTopic {
@@ -337,6 +1081,7 @@ impl ElevatorController {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::person::Load;
     use tokio::sync::{broadcast, mpsc};
     use Floor::*;
 
@@ -351,6 +1096,7 @@ mod tests {
         let (person_tx, person_rx) = mpsc::channel(100);
         let (to_elevators_tx, to_elevators_rx) = broadcast::channel(100);
         let (to_persons_tx, to_persons_rx) = broadcast::channel(100);
+        let (_simulation_tx, simulation_rx) = mpsc::channel(100);
         let (mqtt_tx, _mqtt_rx) = mpsc::channel(100);
 
         let controller = ElevatorController::new(
@@ -358,6 +1104,7 @@ mod tests {
             to_elevators_tx,
             person_rx,
             to_persons_tx,
+            simulation_rx,
             mqtt_tx,
             elevators,
         );
@@ -393,7 +1140,7 @@ mod tests {
         let elevators = vec!["E1".to_string()];
         let (mut controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
 
-        controller.handle_person_request_elevator(First).await;
+        controller.handle_person_request_elevator("P1".to_string(), First, Third).await;
 
         let state = controller.state.get("E1").unwrap();
         assert_eq!(state.mission, Some(First));
@@ -409,9 +1156,9 @@ mod tests {
         let (mut controller, _, _, _, _) = create_test_controller(elevators);
 
         // First request gets assigned immediately
-        controller.handle_person_request_elevator(First).await;
+        controller.handle_person_request_elevator("P2".to_string(), First, Third).await;
         // Second request gets queued
-        controller.handle_person_request_elevator(Second).await;
+        controller.handle_person_request_elevator("P3".to_string(), Second, Third).await;
 
         let state = controller.state.get("E1").unwrap();
         assert_eq!(state.mission, Some(First));
@@ -423,9 +1170,9 @@ mod tests {
         let elevators = vec!["E1".to_string()];
         let (mut controller, _, _, _, _) = create_test_controller(elevators);
 
-        controller.handle_person_request_elevator(First).await;
-        controller.handle_person_request_elevator(Second).await;
-        controller.handle_person_request_elevator(Second).await; // Duplicate
+        controller.handle_person_request_elevator("P4".to_string(), First, Third).await;
+        controller.handle_person_request_elevator("P5".to_string(), Second, Third).await;
+        controller.handle_person_request_elevator("P6".to_string(), Second, Third).await; // Duplicate
 
         let state = controller.state.get("E1").unwrap();
         let count = state.missions.iter().filter(|&&f| f == Second).count();
@@ -438,8 +1185,8 @@ mod tests {
         let (mut controller, _, _, _, _) = create_test_controller(elevators);
 
         // Give E1 some missions
-        controller.handle_person_request_elevator(First).await;
-        controller.handle_person_request_elevator(Second).await;
+        controller.handle_person_request_elevator("P7".to_string(), First, Third).await;
+        controller.handle_person_request_elevator("P8".to_string(), Second, Third).await;
 
         // E2 should be selected for next request (has 0 missions)
         // Note: HashMap iteration order is not guaranteed, but with min_by_key
@@ -495,10 +1242,219 @@ mod tests {
         assert_eq!(msg, ControllerToPersonsMsg::UpdateBoardingStatus(
             "P3".to_string(), 
             "E1".to_string(), 
-            BoardingStatus::Rejected
+            BoardingStatus::Rejected(RejectReason::Full)
         ));
     }
 
+    #[tokio::test]
+    async fn test_wheelchair_takes_two_places() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, mut to_persons_rx) = create_test_controller(elevators);
+        controller.state.get_mut("E1").unwrap().door = Open;
+        controller.attributes.insert("P1".to_string(), Attributes { wheelchair: true, ..Default::default() });
+
+        controller.handle_person_entered_elevator("P2".to_string(), "E1".to_string()).await;
+        controller.handle_person_entered_elevator("P1".to_string(), "E1".to_string()).await;
+
+        assert_eq!(to_persons_rx.recv().await.unwrap(), UpdateBoardingStatus("P2".to_string(), "E1".to_string(), BoardingStatus::Accepted));
+        assert_eq!(to_persons_rx.recv().await.unwrap(), UpdateBoardingStatus("P1".to_string(), "E1".to_string(), BoardingStatus::Rejected(RejectReason::Full)));
+    }
+
+    #[tokio::test]
+    async fn test_room_kept_for_urgent_person() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, mut to_persons_rx) = create_test_controller(elevators);
+        controller.attributes.insert("VIP".to_string(), Attributes { priority: true, ..Default::default() });
+        for person in ["P1", "P2", "VIP"] {
+            controller.handle_person_request_elevator(person.to_string(), Ground, Third).await;
+        }
+        controller.state.get_mut("E1").unwrap().door = Open;
+
+        for person in ["P1", "P2", "VIP"] {
+            controller.handle_person_entered_elevator(person.to_string(), "E1".to_string()).await;
+        }
+
+        let boarding = |person: &str, status| UpdateBoardingStatus(person.to_string(), "E1".to_string(), status);
+        assert_eq!(to_persons_rx.recv().await.unwrap(), boarding("P1", BoardingStatus::Accepted));
+        assert_eq!(to_persons_rx.recv().await.unwrap(), boarding("P2", BoardingStatus::Rejected(RejectReason::Full)));
+        assert_eq!(to_persons_rx.recv().await.unwrap(), boarding("VIP", BoardingStatus::Accepted));
+    }
+
+    #[tokio::test]
+    async fn test_priority_call_is_served_next() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        let state = controller.state.get_mut("E1").unwrap();
+        state.mission = Some(First);
+        state.missions.push_back(Third);
+        controller.attributes.insert("VIP".to_string(), Attributes { priority: true, ..Default::default() });
+
+        controller.handle_person_request_elevator("P1".to_string(), Second, Ground).await;
+        controller.handle_person_request_elevator("VIP".to_string(), Second, Ground).await;
+
+        assert_eq!(controller.state["E1"].missions, [Second, Third]);
+        assert!(to_elevators_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_doors_wait_for_slow_person() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        controller.state.get_mut("E1").unwrap().door = Open;
+        controller.attributes.insert("P1".to_string(), Attributes { load: Some(Load::Stretcher), ..Default::default() });
+
+        controller.handle_person_entering_elevator("P1".to_string(), "E1".to_string()).await;
+        controller.handle_doors_opened("E1".to_string()).await;
+        assert!(to_elevators_rx.try_recv().is_err());
+
+        controller.handle_person_entered_elevator("P1".to_string(), "E1".to_string()).await;
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), CloseDoors("E1".to_string()));
+        assert_eq!(controller.state["E1"].passengers, ["P1"]);
+    }
+
+    #[tokio::test]
+    async fn test_overloaded_car_keeps_doors_open() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, mut to_elevators_rx, mut to_persons_rx) = create_test_controller(elevators);
+        controller.state.get_mut("E1").unwrap().door = Open;
+        for person in ["P1", "P2"] {
+            controller.attributes.insert(person.to_string(), Attributes { mass_kg: Some(100.0), ..Default::default() });
+        }
+
+        controller.handle_person_entered_elevator("P1".to_string(), "E1".to_string()).await;
+        controller.handle_person_entered_elevator("P2".to_string(), "E1".to_string()).await;
+        controller.handle_person_choosing_floor("P1".to_string(), "E1".to_string(), Third).await;
+
+        assert_eq!(to_persons_rx.recv().await.unwrap(), UpdateBoardingStatus("P1".to_string(), "E1".to_string(), BoardingStatus::Accepted));
+        assert_eq!(to_persons_rx.recv().await.unwrap(), UpdateBoardingStatus("P2".to_string(), "E1".to_string(), BoardingStatus::Rejected(RejectReason::Overloaded)));
+        let state = controller.state.get("E1").unwrap();
+        assert_eq!(state.overload, Some("P2".to_string()));
+        assert_eq!(state.load_kg, 100.0);
+        assert!(to_elevators_rx.try_recv().is_err());
+
+        // Closes once the person who overloaded the car is out again
+        controller.handle_person_left_elevator("P2".to_string(), "E1".to_string()).await;
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), CloseDoors("E1".to_string()));
+        assert_eq!(controller.state["E1"].overload, None);
+        assert_eq!(controller.state["E1"].passengers, ["P1"]);
+    }
+
+    #[tokio::test]
+    async fn test_nearly_full_car_gets_no_calls() {
+        let elevators = vec!["E1".to_string(), "E2".to_string()];
+        let (controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        let mut controller = controller.with_rated_load("E1", 200.0);
+        controller.state.get_mut("E1").unwrap().load_kg = 170.0;
+        controller.state.get_mut("E2").unwrap().missions.extend([First, Second]);
+        controller.state.get_mut("E2").unwrap().mission = Some(Third);
+
+        controller.handle_person_request_elevator("P1".to_string(), Ground, Third).await;
+
+        assert!(controller.state["E2"].missions.contains(&Ground));
+        assert!(controller.state["E1"].mission.is_none());
+        assert!(to_elevators_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_call_of_rejected_person_goes_to_next_car() {
+        let elevators = vec!["E1".to_string(), "E2".to_string()];
+        let (mut controller, _, _, mut to_elevators_rx, mut to_persons_rx) = create_test_controller(elevators);
+        for person in ["P1", "P2"] {
+            controller.destinations.insert(person.to_string(), Third);
+        }
+        controller.destinations.insert("P3".to_string(), Third);
+        controller.waiting.insert(Ground, vec!["P3".to_string()]);
+        {
+            let state = controller.state.get_mut("E1").unwrap();
+            state.door = Open;
+            state.passengers = vec!["P1".to_string(), "P2".to_string()];
+            state.missions.push_back(Third);
+            state.load_kg = 150.0;
+        }
+
+        controller.handle_person_entered_elevator("P3".to_string(), "E1".to_string()).await;
+        assert_eq!(to_persons_rx.recv().await.unwrap(), UpdateBoardingStatus("P3".to_string(), "E1".to_string(), BoardingStatus::Rejected(RejectReason::Full)));
+
+        controller.handle_doors_closed("E1".to_string()).await;
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), ElevatorMission("E1".to_string(), Third));
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), ElevatorMission("E2".to_string(), Ground));
+        assert!(controller.state["E1"].left_behind.is_empty());
+        // Handed on by the controller, nobody pressed the button again
+        assert!(controller.stats.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rejection_reasons() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, mut to_persons_rx) = create_test_controller(elevators);
+        for (person, dest) in [("P1", Third), ("P2", Ground), ("P3", Third)] {
+            controller.destinations.insert(person.to_string(), dest);
+        }
+        {
+            let state = controller.state.get_mut("E1").unwrap();
+            state.floor = Second;
+            state.door = Open;
+            state.passengers.push("P1".to_string());
+        }
+        let boarding = |person: &str, status| UpdateBoardingStatus(person.to_string(), "E1".to_string(), status);
+
+        controller.handle_person_entered_elevator("P2".to_string(), "E1".to_string()).await;
+        controller.handle_person_entered_elevator("P3".to_string(), "E1".to_string()).await;
+        assert_eq!(to_persons_rx.recv().await.unwrap(), boarding("P2", BoardingStatus::Rejected(RejectReason::WrongDirection)));
+        assert_eq!(to_persons_rx.recv().await.unwrap(), boarding("P3", BoardingStatus::Accepted));
+
+        controller.state.get_mut("E1").unwrap().stopped = true;
+        controller.handle_person_entered_elevator("P4".to_string(), "E1".to_string()).await;
+        assert_eq!(to_persons_rx.recv().await.unwrap(), boarding("P4", BoardingStatus::Rejected(RejectReason::OutOfService)));
+        assert_eq!(controller.state["E1"].left_behind, ["P2"]);
+    }
+
+    #[tokio::test]
+    async fn test_person_took_stairs() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, _) = create_test_controller(elevators);
+        controller.handle_person_request_elevator("P1".to_string(), Ground, Third).await;
+
+        // Withdrawn when the person starts walking
+        controller.handle_person_taking_stairs("P1".to_string()).await;
+        assert!(controller.waiting.is_empty());
+        assert!(controller.hall_calls.is_empty());
+        assert!(!controller.destinations.contains_key("P1"));
+        let summary = controller.stats.summary(0);
+        assert_eq!(summary.persons_waiting, 0);
+        assert_eq!(summary.persons_took_stairs, 0);
+
+        controller.handle_person_took_stairs("P1".to_string()).await;
+        let summary = controller.stats.summary(0);
+        assert_eq!(summary.persons_completed, 0);
+        assert_eq!(summary.persons_took_stairs, 1);
+        assert_eq!(controller.stats.journey_times().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_taking_stairs_drops_calls_nobody_waits_for() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, _) = create_test_controller(elevators);
+        controller.state.get_mut("E1").unwrap().mission = Some(Third);
+        controller.handle_person_request_elevator("P1".to_string(), First, Ground).await;
+        controller.handle_person_request_elevator("P2".to_string(), Second, Ground).await;
+        controller.handle_person_request_elevator("P3".to_string(), Second, Ground).await;
+        // P4 rides to Second
+        controller.state.get_mut("E1").unwrap().passengers.push("P4".to_string());
+        controller.stats.requested("P4", Ground, 0);
+        controller.stats.destination_chosen("P4", Second);
+        assert_eq!(controller.state["E1"].missions, [First, Second]);
+
+        controller.handle_person_taking_stairs("P1".to_string()).await;
+        controller.handle_person_taking_stairs("P2".to_string()).await;
+        assert_eq!(controller.state["E1"].missions, [Second]);
+        assert_eq!(controller.hall_calls, vec![HallCall { floor: Second, elevator: "E1".to_string() }]);
+
+        controller.handle_person_taking_stairs("P3".to_string()).await;
+        assert!(controller.hall_calls.is_empty());
+        assert_eq!(controller.state["E1"].missions, [Second]);
+    }
+
     #[tokio::test]
     async fn test_handle_person_entered_elevator_rejects_when_doors_closed() {
         let elevators = vec!["E1".to_string()];
@@ -514,7 +1470,7 @@ mod tests {
         assert_eq!(msg, ControllerToPersonsMsg::UpdateBoardingStatus(
             "P1".to_string(), 
             "E1".to_string(), 
-            BoardingStatus::Rejected
+            BoardingStatus::Rejected(RejectReason::DoorsNotOpen)
         ));
     }
 
@@ -546,6 +1502,325 @@ mod tests {
         assert_eq!(msg, ControllerToElevatorsMsg::OpenDoors("E1".to_string()));
     }
 
+    #[tokio::test]
+    async fn test_travel_energy_depends_on_load_and_direction() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _to_elevators_rx, _) = create_test_controller(elevators);
+        let state = controller.state.get_mut("E1").unwrap();
+        state.passengers = vec!["P1".to_string(), "P2".to_string()];
+        state.load_kg = 150.0;
+
+        controller.handle_elevator_moving("E1".to_string(), Ground, Third).await;
+        controller.handle_elevator_passing("E1".to_string(), First).await;
+        controller.handle_elevator_arrived("E1".to_string(), Third).await;
+        let up = controller.stats.energy("E1", sim_time_ms()).travel_wh;
+        assert!((up - EnergyModel::default().travel(9.0, 150.0).0).abs() < 1e-9);
+
+        // The full car is heavier than the counterweight, going down needs less
+        controller.handle_elevator_moving("E1".to_string(), Third, Ground).await;
+        controller.handle_elevator_arrived("E1".to_string(), Ground).await;
+        let down = controller.stats.energy("E1", sim_time_ms()).travel_wh - up;
+        assert!(down < up);
+    }
+
+    #[tokio::test]
+    async fn test_travel_to_emergency_stop_counts() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _to_elevators_rx, _) = create_test_controller(elevators);
+
+        // Up to 1 m above the first floor and back down to the ground floor
+        let stop = height(First) + 1.0;
+        controller.handle_elevator_moving("E1".to_string(), Ground, Third).await;
+        controller.handle_elevator_passing("E1".to_string(), First).await;
+        controller.handle_command_ack("E1".to_string(), ElevatorCommand::EmergencyStop, Ok(None)).await;
+        controller.handle_elevator_stopped("E1".to_string(), stop).await;
+        controller.handle_command_ack("E1".to_string(), ElevatorCommand::RetargetMission { floor: Ground }, Ok(Some(Ground))).await;
+        controller.handle_elevator_moving("E1".to_string(), First, Ground).await;
+        controller.handle_elevator_passing("E1".to_string(), First).await;
+        controller.handle_elevator_arrived("E1".to_string(), Ground).await;
+
+        let model = EnergyModel::default();
+        let expected = model.travel(stop, 0.0).0 + model.travel(-stop, 0.0).0;
+        assert!((controller.stats.energy("E1", sim_time_ms()).travel_wh - expected).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_call_during_emergency_stop_served_after_release() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        controller.handle_command_ack("E1".to_string(), ElevatorCommand::EmergencyStop, Ok(Some(Ground))).await;
+
+        controller.handle_person_request_elevator("P1".to_string(), Second, Ground).await;
+        assert!(to_elevators_rx.try_recv().is_err());
+        assert!(controller.hall_calls.is_empty());
+
+        controller.handle_command_ack("E1".to_string(), ElevatorCommand::RetargetMission { floor: Ground }, Ok(Some(Ground))).await;
+        assert_eq!(controller.hall_calls, vec![HallCall { floor: Second, elevator: "E1".to_string() }]);
+        controller.handle_doors_opening("E1".to_string()).await;
+        controller.handle_doors_closed("E1".to_string()).await;
+        assert_eq!(to_elevators_rx.try_recv().unwrap(), ElevatorMission("E1".to_string(), Second));
+    }
+
+    #[tokio::test]
+    async fn test_request_only_assigned_to_car_serving_the_trip() {
+        let elevators = vec!["E1".to_string(), "E2".to_string()];
+        let (controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        let mut controller = controller.with_served_floors("E1", vec![Ground, Third]);
+
+        // E1 skips the first floor
+        controller.handle_person_request_elevator("P1".to_string(), First, Third).await;
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), ControllerToElevatorsMsg::ElevatorMission("E2".to_string(), First));
+
+        controller.state.get_mut("E2").unwrap().missions.push_back(Second);
+        controller.handle_person_request_elevator("P2".to_string(), Ground, Third).await;
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), ControllerToElevatorsMsg::ElevatorMission("E1".to_string(), Ground));
+    }
+
+    #[tokio::test]
+    async fn test_boarding_rejected_when_car_skips_destination() {
+        let elevators = vec!["E1".to_string(), "E2".to_string()];
+        let (controller, _, _, _to_elevators_rx, mut to_persons_rx) = create_test_controller(elevators);
+        let mut controller = controller.with_served_floors("E1", vec![Ground, First]);
+        controller.state.get_mut("E1").unwrap().door = Open;
+
+        controller.handle_person_request_elevator("P1".to_string(), Ground, Third).await;
+        controller.handle_person_entered_elevator("P1".to_string(), "E1".to_string()).await;
+
+        assert!(controller.state["E1"].passengers.is_empty());
+        assert_eq!(to_persons_rx.recv().await.unwrap(), ControllerToPersonsMsg::UpdateBoardingStatus("P1".to_string(), "E1".to_string(), BoardingStatus::Rejected(RejectReason::ZoneMismatch)));
+    }
+
+    #[tokio::test]
+    async fn test_trip_without_serving_car_is_not_dispatched() {
+        let elevators = vec!["E1".to_string()];
+        let (controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        let mut controller = controller.with_served_floors("E1", vec![First, Second]);
+
+        controller.handle_person_request_elevator("P1".to_string(), Ground, Second).await;
+
+        assert!(controller.waiting.is_empty());
+        assert!(controller.destinations.is_empty());
+        assert!(to_elevators_rx.try_recv().is_err());
+        assert_eq!(controller.stats.summary(0).persons_waiting, 0);
+        assert!(controller.stats.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tie_goes_to_lowest_id() {
+        for _ in 0..10 {
+            let elevators = vec!["E3".to_string(), "E1".to_string(), "E2".to_string()];
+            let (mut controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+
+            controller.handle_person_request_elevator("P1".to_string(), Second, Ground).await;
+            assert_eq!(to_elevators_rx.recv().await.unwrap(), ControllerToElevatorsMsg::ElevatorMission("E1".to_string(), Second));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_ahead_of_moving_car_adds_stop() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        controller.state.get_mut("E1").unwrap().mission = Some(Third);
+
+        controller.handle_person_request_elevator("P1".to_string(), Second, Third).await;
+        // Behind the car, only queued
+        controller.handle_elevator_passing("E1".to_string(), First).await;
+        controller.handle_person_request_elevator("P2".to_string(), Ground, Third).await;
+
+        let state = controller.state.get("E1").unwrap();
+        assert_eq!(state.floor, First);
+        assert_eq!(state.missions, [Second, Ground]);
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), ControllerToElevatorsMsg::StopOnTheWay("E1".to_string(), Second));
+        assert!(to_elevators_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_accepted_retarget_keeps_original_mission() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _to_elevators_rx, _) = create_test_controller(elevators);
+        {
+            let state = controller.state.get_mut("E1").unwrap();
+            state.mission = Some(Third);
+            state.missions.push_back(Second);
+        }
+
+        controller.handle_command_ack("E1".to_string(), ElevatorCommand::RetargetMission { floor: Second }, Ok(Some(Second))).await;
+        assert_eq!(controller.state.get("E1").unwrap().mission, Some(Second));
+
+        controller.handle_elevator_arrived("E1".to_string(), Second).await;
+        controller.handle_doors_opening("E1".to_string()).await;
+
+        let state = controller.state.get("E1").unwrap();
+        assert_eq!(state.mission, None);
+        assert_eq!(state.missions, [Third]);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_retarget_stays_queued() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, _) = create_test_controller(elevators);
+        {
+            let state = controller.state.get_mut("E1").unwrap();
+            state.mission = Some(Third);
+            state.missions.push_back(Second);
+        }
+
+        controller.handle_command_ack("E1".to_string(), ElevatorCommand::RetargetMission { floor: Second }, Err(Rejection::CannotBrake)).await;
+
+        let state = controller.state.get("E1").unwrap();
+        assert_eq!(state.mission, Some(Third));
+        assert_eq!(state.missions, [Second]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_early_and_keeps_destination() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        controller.state.get_mut("E1").unwrap().mission = Some(Third);
+
+        controller.handle_command("E9".to_string(), ElevatorCommand::CancelMission).await;
+        controller.handle_command("E1".to_string(), ElevatorCommand::CancelMission).await;
+        controller.handle_command_ack("E1".to_string(), ElevatorCommand::CancelMission, Ok(Some(First))).await;
+
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), ControllerToElevatorsMsg::CancelMission("E1".to_string()));
+        assert!(to_elevators_rx.try_recv().is_err());
+        let state = controller.state.get("E1").unwrap();
+        assert_eq!(state.mission, Some(First));
+        assert_eq!(state.missions, [Third]);
+
+        // The third floor is still served after the stop
+        controller.handle_elevator_arrived("E1".to_string(), First).await;
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), OpenDoors("E1".to_string()));
+        controller.handle_doors_opening("E1".to_string()).await;
+        controller.handle_doors_closed("E1".to_string()).await;
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), ControllerToElevatorsMsg::ElevatorMission("E1".to_string(), Third));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_car_parks_after_timeout() {
+        let elevators = vec!["E1".to_string()];
+        let (controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        let mut controller = controller.with_parking(ParkingPolicy { idle_timeout_ms: 1000, ..Default::default() });
+        controller.state.get_mut("E1").unwrap().floor = Third;
+
+        // Busy again before the timer fires
+        controller.went_idle("E1");
+        controller.state.get_mut("E1").unwrap().mission = Some(First);
+        let (elevator, since) = controller.idle_rx.recv().await.unwrap();
+        controller.handle_idle_timeout(elevator, since).await;
+        assert!(to_elevators_rx.try_recv().is_err());
+
+        controller.state.get_mut("E1").unwrap().mission = None;
+        controller.went_idle("E1");
+        let (elevator, since) = controller.idle_rx.recv().await.unwrap();
+        controller.handle_idle_timeout(elevator, since).await;
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), ControllerToElevatorsMsg::ElevatorMission("E1".to_string(), Ground));
+        assert!(controller.state["E1"].parking);
+
+        // Parks with closed doors
+        controller.handle_elevator_arrived("E1".to_string(), Ground).await;
+        assert!(to_elevators_rx.try_recv().is_err());
+        let state = controller.state.get("E1").unwrap();
+        assert!(!state.parking);
+        assert_eq!(state.mission, None);
+        assert_eq!(state.door, Closed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_parked_cars_spread_again_when_one_is_called() {
+        let elevators = vec!["E1".to_string(), "E2".to_string()];
+        let (controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        let mut controller = controller.with_parking(ParkingPolicy { strategy: crate::parking::ParkingStrategy::Spread, idle_timeout_ms: 1000 });
+
+        controller.went_idle("E1");
+        controller.went_idle("E2");
+        for _ in 0..2 {
+            let (elevator, since) = controller.idle_rx.recv().await.unwrap();
+            controller.handle_idle_timeout(elevator, since).await;
+        }
+        assert_eq!(to_elevators_rx.try_recv().unwrap(), ControllerToElevatorsMsg::ElevatorMission("E1".to_string(), First));
+        assert_eq!(to_elevators_rx.try_recv().unwrap(), ControllerToElevatorsMsg::ElevatorMission("E2".to_string(), Third));
+        for (elevator, floor) in [("E1", First), ("E2", Third)] {
+            controller.handle_elevator_moving(elevator.to_string(), Ground, floor).await;
+            controller.handle_elevator_arrived(elevator.to_string(), floor).await;
+        }
+
+        // E1 is busy, E2 alone takes the middle of the building
+        controller.handle_person_request_elevator("P1".to_string(), First, Ground).await;
+        assert_eq!(to_elevators_rx.try_recv().unwrap(), ControllerToElevatorsMsg::ElevatorMission("E1".to_string(), First));
+        assert_eq!(to_elevators_rx.try_recv().unwrap(), ControllerToElevatorsMsg::ElevatorMission("E2".to_string(), Second));
+        assert!(to_elevators_rx.try_recv().is_err());
+
+        // Parking trips are not counted as trips
+        let summary = controller.stats.summary(sim_time_ms());
+        assert_eq!(summary.parking_trips, 2);
+        assert_eq!(summary.trips, 0);
+    }
+
+    #[tokio::test]
+    async fn test_call_cancels_parking_trip() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        let state = controller.state.get_mut("E1").unwrap();
+        state.floor = First;
+        state.mission = Some(Third);
+        state.parking = true;
+
+        // Both calls lie behind the car, only the first one cancels
+        controller.handle_person_request_elevator("P1".to_string(), Ground, Third).await;
+        controller.handle_person_request_elevator("P2".to_string(), First, Third).await;
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), ControllerToElevatorsMsg::EndParking("E1".to_string()));
+        assert!(to_elevators_rx.try_recv().is_err());
+
+        // Stops on the next floor, the parking floor is not queued
+        controller.handle_command_ack("E1".to_string(), ElevatorCommand::CancelMission, Ok(Some(Second))).await;
+        let state = controller.state.get("E1").unwrap();
+        assert!(!state.parking);
+        assert_eq!(state.mission, Some(Second));
+        assert_eq!(state.missions, [Ground, First]);
+    }
+
+    #[tokio::test]
+    async fn test_call_on_parking_floor_keeps_trip() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        let state = controller.state.get_mut("E1").unwrap();
+        state.floor = Third;
+        state.mission = Some(Ground);
+        state.parking = true;
+
+        controller.handle_person_request_elevator("P1".to_string(), Ground, First).await;
+        assert!(to_elevators_rx.try_recv().is_err());
+
+        // A normal stop now, the doors open for the person
+        controller.handle_elevator_arrived("E1".to_string(), Ground).await;
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), OpenDoors("E1".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_emergency_stopped_car_gets_no_missions() {
+        let elevators = vec!["E1".to_string(), "E2".to_string()];
+        let (mut controller, _, _, mut to_elevators_rx, _) = create_test_controller(elevators);
+        controller.state.get_mut("E1").unwrap().mission = Some(Third);
+
+        controller.handle_command_ack("E1".to_string(), ElevatorCommand::EmergencyStop, Ok(None)).await;
+        controller.handle_person_request_elevator("P1".to_string(), Second, Third).await;
+        controller.start_next_mission_if_idle("E1");
+
+        let state = controller.state.get("E1").unwrap();
+        assert!(state.stopped);
+        assert_eq!(state.mission, None);
+        assert_eq!(state.missions, [Third]);
+        assert_eq!(to_elevators_rx.recv().await.unwrap(), ControllerToElevatorsMsg::ElevatorMission("E2".to_string(), Second));
+        assert!(to_elevators_rx.try_recv().is_err());
+
+        // Retargeting releases the car
+        controller.handle_command_ack("E1".to_string(), ElevatorCommand::RetargetMission { floor: Ground }, Ok(Some(Ground))).await;
+        let state = controller.state.get("E1").unwrap();
+        assert!(!state.stopped);
+        assert_eq!(state.mission, Some(Ground));
+    }
+
     #[tokio::test]
     async fn test_handle_doors_opening_clears_mission_and_notifies() {
         let elevators = vec!["E1".to_string()];
@@ -670,7 +1945,7 @@ mod tests {
         assert_eq!(Third as i8, 4);
         
         // 4 floors total (0, 1, 2, 3 in user terms = Ground, First, Second, Third)
-        let floors = vec![Ground, First, Second, Third];
+        let floors = [Ground, First, Second, Third];
         assert_eq!(floors.len(), 4);
     }
 
@@ -684,9 +1959,9 @@ mod tests {
         let (mut controller, _, _, _, _) = create_test_controller(elevators);
 
         // Give E1 a mission to First floor
-        controller.handle_person_request_elevator(First).await;
+        controller.handle_person_request_elevator("P9".to_string(), First, Third).await;
         // Give E2 a mission to Third floor (E2 should get it as it has fewer missions)
-        controller.handle_person_request_elevator(Third).await;
+        controller.handle_person_request_elevator("P10".to_string(), Third, Ground).await;
 
         let e1_state = controller.state.get("E1").unwrap();
         let e2_state = controller.state.get("E2").unwrap();
@@ -694,7 +1969,7 @@ mod tests {
         // Both elevators should have independent missions
         // One should have First, one should have Third
         let missions: Vec<Option<Floor>> = vec![e1_state.mission, e2_state.mission];
-        assert!(missions.contains(&Some(First)) || missions.iter().any(|m| 
+        assert!(missions.contains(&Some(First)) || missions.iter().any(|_| 
             controller.state.values().any(|s| s.missions.contains(&First))));
     }
 
@@ -773,7 +2048,7 @@ mod tests {
         controller.handle_person_entered_elevator("P3".to_string(), "E1".to_string()).await;
         let msg3 = to_persons_rx.recv().await.unwrap();
         assert_eq!(msg3, ControllerToPersonsMsg::UpdateBoardingStatus(
-            "P3".to_string(), "E1".to_string(), BoardingStatus::Rejected));
+            "P3".to_string(), "E1".to_string(), BoardingStatus::Rejected(RejectReason::Full)));
 
         // Verify only 2 passengers in elevator
         let state = controller.state.get("E1").unwrap();
@@ -793,11 +2068,11 @@ mod tests {
         let (mut controller, _, _, _, _) = create_test_controller(elevators);
 
         // Give E1 two missions
-        controller.handle_person_request_elevator(First).await;
-        controller.handle_person_request_elevator(Second).await;
+        controller.handle_person_request_elevator("P11".to_string(), First, Third).await;
+        controller.handle_person_request_elevator("P12".to_string(), Second, Third).await;
 
         // E2 and E3 should have 0 missions, so next request goes to one of them
-        controller.handle_person_request_elevator(Third).await;
+        controller.handle_person_request_elevator("P13".to_string(), Third, Ground).await;
 
         // E1 should still have its missions, one of E2/E3 should have Third
         let e1 = controller.state.get("E1").unwrap();
@@ -869,7 +2144,7 @@ mod tests {
         // All 4 door states must exist: Closed, Opening, Open, Closing
         use crate::elevator::DoorStatus::*;
         
-        let states = vec![Closed, Opening, Open, Closing];
+        let states = [Closed, Opening, Open, Closing];
         assert_eq!(states.len(), 4);
         
         // All states are distinct
@@ -911,4 +2186,223 @@ mod tests {
         // Doors should be closed initially
         assert_eq!(controller.state.get("E1").unwrap().door, Closed);
     }
-}
\ No newline at end of file
+
+    // ========================================================================
+    // Snapshot
+    // ========================================================================
+
+    #[tokio::test]
+    async fn test_request_tracks_waiting_person_and_hall_call() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, _) = create_test_controller(elevators);
+
+        controller.handle_person_request_elevator("P1".to_string(), Second, Third).await;
+        controller.handle_person_request_elevator("P1".to_string(), Second, Third).await;
+
+        assert_eq!(controller.waiting.get(&Second), Some(&vec!["P1".to_string()]));
+        assert_eq!(controller.hall_calls, vec![HallCall { floor: Second, elevator: "E1".to_string() }]);
+    }
+
+    #[tokio::test]
+    async fn test_boarding_removes_waiting_person() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, _) = create_test_controller(elevators);
+
+        controller.handle_person_request_elevator("P1".to_string(), Ground, Third).await;
+        controller.state.get_mut("E1").unwrap().door = Open;
+        controller.handle_person_entered_elevator("P1".to_string(), "E1".to_string()).await;
+
+        assert!(controller.waiting.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_doors_opening_clears_hall_call() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _to_elevators_rx, _) = create_test_controller(elevators);
+
+        controller.handle_person_request_elevator("P1".to_string(), First, Third).await;
+        controller.handle_elevator_arrived("E1".to_string(), First).await;
+        controller.handle_doors_opening("E1".to_string()).await;
+
+        assert!(controller.hall_calls.is_empty());
+        // Person is still waiting until boarding is accepted
+        assert!(controller.waiting.contains_key(&First));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_contains_full_state() {
+        let elevators = vec!["E2".to_string(), "E1".to_string()];
+        let (mut controller, _, _, _, _) = create_test_controller(elevators);
+
+        controller.handle_person_request_elevator("P1".to_string(), Third, Ground).await;
+        controller.state.get_mut("E2").unwrap().passengers.push("P9".to_string());
+
+        let json = serde_json::to_value(controller.snapshot()).unwrap();
+
+        assert!(json["sim_time_ms"].is_u64());
+        assert!(json["speed"].is_u64());
+        assert_eq!(json["paused"], false);
+        assert_eq!(json["elevators"][0]["id"], "E1");
+        assert_eq!(json["elevators"][1]["id"], "E2");
+        assert_eq!(json["elevators"][1]["passengers"][0], "P9");
+        assert_eq!(json["elevators"][0]["door"], "Closed");
+        assert_eq!(json["waiting"]["Third"][0], "P1");
+        assert_eq!(json["hall_calls"][0]["floor"], "Third");
+    }
+
+    #[tokio::test]
+    async fn test_publish_snapshot_on_demand() {
+        let (_, elevator_rx) = mpsc::channel(100);
+        let (_, person_rx) = mpsc::channel(100);
+        let (to_elevators_tx, _) = broadcast::channel(100);
+        let (to_persons_tx, _) = broadcast::channel(100);
+        let (simulation_tx, simulation_rx) = mpsc::channel(100);
+        let (mqtt_tx, mut mqtt_rx) = mpsc::channel(100);
+
+        let handle = ElevatorController::new(
+            elevator_rx,
+            to_elevators_tx,
+            person_rx,
+            to_persons_tx,
+            simulation_rx,
+            mqtt_tx,
+            vec!["E1".to_string()],
+        ).init();
+
+        simulation_tx.send(SimulationToControllerMsg::PublishSnapshot).await.unwrap();
+        let msg = mqtt_rx.recv().await.unwrap();
+        assert!(matches!(msg, SimulationTopic { msg: SimulationMsg::Snapshot(_) }));
+        let msg = mqtt_rx.recv().await.unwrap();
+        assert!(matches!(msg, SimulationTopic { msg: SimulationMsg::Energy(_) }));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_publish_snapshot_periodically() {
+        let (_, elevator_rx) = mpsc::channel(100);
+        let (_, person_rx) = mpsc::channel(100);
+        let (to_elevators_tx, _) = broadcast::channel(100);
+        let (to_persons_tx, _) = broadcast::channel(100);
+        let (_simulation_tx, simulation_rx) = mpsc::channel(100);
+        let (mqtt_tx, mut mqtt_rx) = mpsc::channel(100);
+
+        let handle = ElevatorController::new(
+            elevator_rx,
+            to_elevators_tx,
+            person_rx,
+            to_persons_tx,
+            simulation_rx,
+            mqtt_tx,
+            vec!["E1".to_string()],
+        ).with_snapshot_interval(Duration::from_millis(10)).init();
+
+        for _ in 0..2 {
+            let msg = mqtt_rx.recv().await.unwrap();
+            assert!(matches!(msg, SimulationTopic { msg: SimulationMsg::Snapshot(_) }));
+            let msg = mqtt_rx.recv().await.unwrap();
+            assert!(matches!(msg, SimulationTopic { msg: SimulationMsg::Energy(_) }));
+        }
+
+        handle.abort();
+    }
+
+    // ========================================================================
+    // Query
+    // ========================================================================
+
+    #[tokio::test]
+    async fn test_answer_elevators_query() {
+        let elevators = vec!["E1".to_string(), "E2".to_string()];
+        let (controller, _, _, _, _) = create_test_controller(elevators);
+
+        let result = controller.answer(&Query::Elevators);
+
+        assert_eq!(result.as_array().unwrap().len(), 2);
+        assert_eq!(result[0]["id"], "E1");
+        assert_eq!(result[0]["floor"], "Ground");
+    }
+
+    #[tokio::test]
+    async fn test_answer_person_query() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, _) = create_test_controller(elevators);
+
+        controller.handle_person_request_elevator("P1".to_string(), Second, Third).await;
+        controller.state.get_mut("E1").unwrap().door = Open;
+        controller.handle_person_entered_elevator("P1".to_string(), "E1".to_string()).await;
+        controller.handle_person_choosing_floor("P1".to_string(), "E1".to_string(), Third).await;
+
+        let result = controller.answer(&Query::Person("P1".to_string()));
+        assert_eq!(result["status"], "Riding");
+        assert_eq!(result["origin"], "Second");
+        assert_eq!(result["destination"], "Third");
+        assert_eq!(result["elevator"], "E1");
+
+        let unknown = controller.answer(&Query::Person("P2".to_string()));
+        assert!(unknown["error"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_answer_stats_query() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, _) = create_test_controller(elevators);
+
+        controller.handle_person_request_elevator("P1".to_string(), Ground, Third).await;
+        controller.state.get_mut("E1").unwrap().door = Open;
+        controller.handle_person_entered_elevator("P1".to_string(), "E1".to_string()).await;
+        controller.handle_person_left_elevator("P1".to_string(), "E1".to_string()).await;
+
+        let result = controller.answer(&Query::Stats);
+        assert_eq!(result["boardings_accepted"], 1);
+        assert_eq!(result["persons_completed"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let elevators = vec!["E1".to_string()];
+        let (mut controller, _, _, _, _) = create_test_controller(elevators);
+
+        controller.handle_person_request_elevator("P1".to_string(), Ground, Third).await;
+        controller.handle_person_request_elevator("P2".to_string(), Second, Third).await;
+        controller.state.get_mut("E1").unwrap().door = Open;
+        controller.handle_person_entered_elevator("P1".to_string(), "E1".to_string()).await;
+
+        let metrics = controller.metrics();
+        assert!(metrics.contains("elevator_persons_waiting{floor=\"Ground\"} 0\n"));
+        assert!(metrics.contains("elevator_persons_waiting{floor=\"Second\"} 1\n"));
+        assert!(metrics.contains("elevator_car_passengers{car=\"E1\"} 1\n"));
+        assert!(metrics.contains("elevator_car_queue_length{car=\"E1\"} "));
+        assert!(metrics.contains("elevator_boardings_total{result=\"accepted\"} 1\n"));
+        assert!(metrics.contains("elevator_wait_time_seconds_count 1\n"));
+        assert!(metrics.contains("# TYPE elevator_journey_time_seconds histogram\n"));
+    }
+
+    #[tokio::test]
+    async fn test_query_is_answered_on_reply_topic() {
+        let elevators = vec!["E1".to_string()];
+        let (_, elevator_rx) = mpsc::channel(100);
+        let (_, person_rx) = mpsc::channel(100);
+        let (to_elevators_tx, _) = broadcast::channel(100);
+        let (to_persons_tx, _) = broadcast::channel(100);
+        let (simulation_tx, simulation_rx) = mpsc::channel(100);
+        let (mqtt_tx, mut mqtt_rx) = mpsc::channel(100);
+
+        let handle = ElevatorController::new(
+            elevator_rx, to_elevators_tx, person_rx, to_persons_tx, simulation_rx, mqtt_tx, elevators,
+        ).init();
+
+        let reply = Reply { topic: "client/reply".to_string(), correlation: Some(b"1".to_vec()) };
+        simulation_tx.send(SimulationToControllerMsg::Query(Query::Stats, reply.clone())).await.unwrap();
+
+        match mqtt_rx.recv().await.unwrap() {
+            ReplyTopic { reply: answered, result } => {
+                assert_eq!(answered, reply);
+                assert_eq!(result["trips"], 0);
+            }
+            _ => panic!("Expected ReplyTopic"),
+        }
+
+        handle.abort();
+    }
+}
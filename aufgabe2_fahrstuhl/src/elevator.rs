@@ -1,27 +1,37 @@
-use log::error;
-use crate::controller::{ElevatorController, Floor};
+use std::collections::VecDeque;
+use tracing::{debug, error, field, instrument, Span};
+use crate::controller::Floor;
+use crate::kinematics::{self, MotionProfile};
 use crate::elevator::DoorStatus::{Closed, Open};
 use crate::elevator::ElevatorStatus::IdleIn;
-use crate::mqtt::ElevatorMsg::{Door, Position};
+use crate::mqtt::ElevatorCommand;
+use crate::mqtt::ElevatorMsg::{Ack, Door, Height, Passing, Position};
 use crate::mqtt::Send::ElevatorTopic;
-use crate::msg::ElevatorToControllerMsg::{DoorsClosed, DoorsClosing, DoorsOpened, DoorsOpening, ElevatorArrived, ElevatorMoving};
-use crate::msg::{ControllerToElevatorsMsg, ElevatorToControllerMsg};
+use crate::msg::ElevatorToControllerMsg::{CommandAck, DoorsClosed, DoorsClosing, DoorsOpened, DoorsOpening, ElevatorArrived, ElevatorMoving, ElevatorPassing, ElevatorStopped};
+use crate::msg::Rejection::{CannotBrake, EmergencyStopped, FloorNotServed, NotMoving, WrongDirection};
+use crate::msg::{ControllerToElevatorsMsg, ElevatorToControllerMsg, Rejection};
 use crate::utils;
+use crate::utils::sim_time_ms;
 use serde::{Deserialize, Serialize};
 use task::JoinHandle;
 use tokio::sync::broadcast::Receiver;
 use tokio::sync::mpsc::Sender;
-use tokio::task;
+use tokio::{select, task};
 use utils::delay;
-use ControllerToElevatorsMsg::{CloseDoors, ElevatorMission, OpenDoors};
+use ControllerToElevatorsMsg::{CancelMission, CloseDoors, ElevatorMission, EmergencyStop, EndParking, OpenDoors, RetargetMission, StopOnTheWay};
 use DoorStatus::{Closing, Opening};
-use ElevatorStatus::MovingFromTo;
-use Floor::Ground;
+use ElevatorStatus::{MovingFromTo, Stopped};
+use Floor::{First, Ground, Second, Third};
+
+// Height updates while the car is moving (simulated time)
+const HEIGHT_INTERVAL_MS: u64 = 200;
 
 #[derive(PartialEq, Debug)]
 pub enum ElevatorStatus {
     IdleIn(Floor),
-    MovingFromTo(Floor, Floor)
+    MovingFromTo(Floor, Floor),
+    // Emergency stop, possibly between floors
+    Stopped
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
@@ -38,6 +48,13 @@ pub struct Elevator {
     from_controller: Receiver<ControllerToElevatorsMsg>,
     to_controller: Sender<ElevatorToControllerMsg>,
     state: ElevatorState,
+    motion: MotionProfile,
+    // Floors the car may stop at, it still passes the others
+    floors: Vec<Floor>,
+    // Metres above the ground floor
+    height: f64,
+    // Commands for this car that arrived during a trip, handled after it
+    pending: VecDeque<ControllerToElevatorsMsg>,
     pub to_mqtt: Sender<crate::mqtt::Send>,
 }
 
@@ -52,29 +69,28 @@ impl Elevator {
     pub fn init(mut self) -> JoinHandle<()> {
         tokio::spawn(async move {
             loop {
-                match self.from_controller.recv().await {
-                    Ok(msg) => {
-                        match msg {
-                            ElevatorMission(elevator, dest) => {
-                                if self.id.eq(&elevator) {
-                                    self.handle_mission(dest).await
-                                }
-                            }
-                            OpenDoors(elevator) => {
-                                if self.id.eq(&elevator) {
-                                    self.handle_open_doors().await
-                                }
-                            }
-                            CloseDoors(elevator) => {
-                                if self.id.eq(&elevator) {
-                                    self.handle_close_doors().await
-                                }
-                            }
+                let msg = match self.pending.pop_front() {
+                    Some(msg) => msg,
+                    None => match self.from_controller.recv().await {
+                        Ok(msg) => msg,
+                        Err(err) => {
+                            error!("Error in Channel: {}", err);
+                            continue;
                         }
-                    }
-                    Err(err) => {
-                        error!("Error in Channel: {}", err);
-                    }
+                    },
+                };
+                if msg.elevator() != self.id {
+                    continue;
+                }
+                match msg {
+                    ElevatorMission(_, dest) => self.handle_mission(dest).await,
+                    CancelMission(_) => self.handle_command_at_rest(ElevatorCommand::CancelMission, true).await,
+                    RetargetMission(_, floor) => self.handle_command_at_rest(ElevatorCommand::RetargetMission { floor }, true).await,
+                    EmergencyStop(_) => self.handle_command_at_rest(ElevatorCommand::EmergencyStop, true).await,
+                    EndParking(_) => self.handle_command_at_rest(ElevatorCommand::CancelMission, false).await,
+                    StopOnTheWay(_, floor) => self.handle_command_at_rest(ElevatorCommand::RetargetMission { floor }, false).await,
+                    OpenDoors(_) => self.handle_open_doors().await,
+                    CloseDoors(_) => self.handle_close_doors().await,
                 }
             }
         })
@@ -90,22 +106,164 @@ impl Elevator {
                 floor: Ground,
                 status: IdleIn(Ground),
                 doors_status: Closed,
-            }
+            },
+            motion: MotionProfile::default(),
+            floors: vec![Ground, First, Second, Third],
+            height: 0.0,
+            pending: VecDeque::new(),
         }
     }
 
+    pub fn with_motion(mut self, motion: MotionProfile) -> Self {
+        self.motion = motion;
+        self
+    }
+
+    pub fn with_served_floors(mut self, floors: Vec<Floor>) -> Self {
+        self.floors = floors;
+        self
+    }
+
     // Handlers
 
+    #[instrument(name = "mission", skip(self, dest), fields(elevator = %self.id, from = %self.state.floor, to = %dest, started_at_ms = sim_time_ms(), arrived_at_ms = field::Empty))]
     async fn handle_mission(&mut self, dest: Floor) {
         self.state.status = MovingFromTo(self.state.floor, dest);
         let _ = self.to_controller.send(ElevatorMoving(self.id.clone(), self.state.floor, dest)).await;
-        let distance_to_travel = (self.state.floor as i8 - dest as i8).abs() as u64;
-        delay(distance_to_travel * 1000).await;
+        let Some(dest) = self.travel(dest).await else {
+            self.state.status = Stopped;
+            if let Some(floor) = kinematics::floor_at(self.height) {
+                self.state.floor = floor;
+            }
+            let _ = self.to_controller.send(ElevatorStopped(self.id.clone(), self.height)).await;
+            return;
+        };
+        self.state.floor = dest;
         self.state.status = IdleIn(dest);
+        Span::current().record("arrived_at_ms", sim_time_ms());
         Elevator::position(self.to_mqtt.clone(), self.id.clone(), dest);
         let _ = self.to_controller.send(ElevatorArrived(self.id.clone(), dest)).await;
     }
 
+    // Cancel and retarget outside of a trip, `report`: requested by a user, the ack is published
+    async fn handle_command_at_rest(&mut self, command: ElevatorCommand, report: bool) {
+        match (command, &self.state.status) {
+            (ElevatorCommand::EmergencyStop, _) => {
+                self.state.status = Stopped;
+                self.ack(command, Ok(kinematics::floor_at(self.height)), report).await;
+            }
+            (ElevatorCommand::RetargetMission { floor }, _) if !self.floors.contains(&floor) => {
+                self.ack(command, Err(FloorNotServed), report).await;
+            }
+            // Leaves the emergency stop, only on request
+            (ElevatorCommand::RetargetMission { floor }, Stopped) if report => {
+                self.ack(command, Ok(Some(floor)), report).await;
+                self.handle_mission(floor).await;
+            }
+            (_, Stopped) => self.ack(command, Err(EmergencyStopped), report).await,
+            _ => self.ack(command, Err(NotMoving), report).await,
+        }
+    }
+
+    // Follows the motion profile and reports the height and every floor passed on the way.
+    // Commands from the controller change the target on the way, returns where the car
+    // stopped (None: emergency stop, also when the car comes to rest on a floor)
+    async fn travel(&mut self, dest: Floor) -> Option<Floor> {
+        let mut dest = Some(dest);
+        let mut stopped = false;
+        let mut start = self.height;
+        let mut target = kinematics::height(dest.unwrap());
+        let direction = (target - start).signum();
+        let mut trip = self.motion.trip(target - start);
+        let mut started = sim_time_ms();
+
+        let mut passing: Vec<Floor> = [Ground, First, Second, Third].into_iter()
+            .filter(|&floor| direction * (kinematics::height(floor) - start) > 0.0 && direction * (target - kinematics::height(floor)) > 0.0)
+            .collect();
+        // Next floor to pass is at the end
+        if direction > 0.0 {
+            passing.reverse();
+        }
+
+        // Simulated ms since `started` up to which the height was published
+        let mut elapsed = 0;
+        loop {
+            let duration_ms = (trip.duration() * 1000.0).round() as u64;
+            if elapsed >= duration_ms {
+                break;
+            }
+            let next = (elapsed + HEIGHT_INTERVAL_MS).min(duration_ms);
+            select! {
+                // The clock may lag behind the timer, never wait for a published part again
+                _ = delay(next.saturating_sub((sim_time_ms() - started).max(elapsed))) => {
+                    elapsed = next;
+                    let t = if next == duration_ms { trip.duration() } else { next as f64 / 1000.0 };
+                    let (distance, velocity) = trip.at(t);
+                    self.height = start + direction * distance;
+                    self.publish(Height { height: self.height, velocity: direction * velocity }).await;
+                    while let Some(&floor) = passing.last() && direction * (self.height - kinematics::height(floor)) >= 0.0 {
+                        passing.pop();
+                        self.state.floor = floor;
+                        self.publish(Passing { passing: floor }).await;
+                        let _ = self.to_controller.send(ElevatorPassing(self.id.clone(), floor)).await;
+                    }
+                }
+                // While braking for an emergency stop commands wait until the car stands still
+                Ok(msg) = self.from_controller.recv(), if !stopped => {
+                    let now = sim_time_ms();
+                    let (distance, velocity) = trip.at((now - started).min(duration_ms) as f64 / 1000.0);
+                    let height = start + direction * distance;
+                    let ahead = |floor: Floor| direction * (kinematics::height(floor) - height);
+                    let reachable = |floor: Floor| match ahead(floor) > 0.0 {
+                        true => self.motion.trip_from(velocity, ahead(floor)).map(|trip| (Some(floor), trip)),
+                        false => None,
+                    };
+                    let report = !matches!(msg, StopOnTheWay(..) | EndParking(..));
+                    let (command, result) = match msg {
+                        RetargetMission(elevator, floor) | StopOnTheWay(elevator, floor) if elevator == self.id => {
+                            let result = match ahead(floor) > 0.0 {
+                                _ if !self.floors.contains(&floor) => Err(FloorNotServed),
+                                true => reachable(floor).ok_or(CannotBrake),
+                                false => Err(WrongDirection),
+                            };
+                            (ElevatorCommand::RetargetMission { floor }, result)
+                        }
+                        CancelMission(elevator) | EndParking(elevator) if elevator == self.id => {
+                            // Next floor the car can still brake for, at the latest the current target
+                            let stop = passing.iter().rev().filter(|floor| self.floors.contains(floor)).chain(dest.iter()).find_map(|&floor| reachable(floor));
+                            (ElevatorCommand::CancelMission, stop.ok_or(CannotBrake))
+                        }
+                        EmergencyStop(elevator) if elevator == self.id => {
+                            let braking = self.motion.braking_distance(velocity);
+                            let trip = self.motion.trip_from(velocity, braking).unwrap_or(self.motion.trip(0.0));
+                            stopped = true;
+                            (ElevatorCommand::EmergencyStop, Ok((kinematics::floor_at(height + direction * braking), trip)))
+                        }
+                        msg => {
+                            if msg.elevator() == self.id {
+                                debug!(elevator = %self.id, ?msg, "Befehl während der Fahrt zurückgestellt");
+                                self.pending.push_back(msg);
+                            }
+                            continue;
+                        }
+                    };
+                    let ack = result.as_ref().map(|(stop, _)| *stop).map_err(|reason| *reason);
+                    if let Ok((stop, stop_trip)) = result {
+                        target = stop.map_or(height + direction * stop_trip.at(stop_trip.duration()).0, kinematics::height);
+                        (start, trip, started, elapsed, dest) = (height, stop_trip, now, 0, stop);
+                        passing.retain(|&floor| direction * (target - kinematics::height(floor)) > 0.0);
+                        if let Some(stop) = stop {
+                            Span::current().record("to", field::display(stop));
+                        }
+                    }
+                    self.ack(command, ack, report).await;
+                }
+            }
+        }
+        self.height = target;
+        if stopped { None } else { dest }
+    }
+
     async fn handle_open_doors(&mut self) {
         if self.state.doors_status.eq(&Closed) {
 
@@ -133,6 +291,22 @@ impl Elevator {
 
     // MQTT Updates
 
+    // Awaited, height and passing updates must not overtake each other
+    async fn publish(&self, msg: crate::mqtt::ElevatorMsg) {
+        let _ = self.to_mqtt.send(ElevatorTopic { id: self.id.clone(), msg }).await;
+    }
+
+    async fn ack(&self, command: ElevatorCommand, result: Result<Option<Floor>, Rejection>, report: bool) {
+        match result {
+            Ok(stop) => debug!(elevator = %self.id, ?command, ?stop, "Befehl angenommen"),
+            Err(reason) => debug!(elevator = %self.id, ?command, ?reason, "Befehl abgelehnt"),
+        }
+        if report {
+            self.publish(Ack { command, accepted: result.is_ok(), stop: result.ok().flatten(), reason: result.err() }).await;
+        }
+        let _ = self.to_controller.send(CommandAck(self.id.clone(), command, result)).await;
+    }
+
     fn position(to_mqtt: Sender<crate::mqtt::Send>, id: String, floor: Floor) {
         tokio::spawn(async move {
             let msg = ElevatorTopic {
@@ -162,7 +336,6 @@ impl Elevator {
 mod tests {
     use super::*;
     use tokio::sync::{broadcast, mpsc};
-    use crate::controller::Floor::*;
 
     fn create_test_elevator(id: &str) -> (
         Elevator,
@@ -232,10 +405,10 @@ mod tests {
         // Test the distance formula used in handle_mission
         let from = Ground;
         let to = Third;
-        let distance = (from as i8 - to as i8).abs() as u64;
+        let distance = (from as i8 - to as i8).unsigned_abs() as u64;
         assert_eq!(distance, 3);
 
-        let distance_same = (First as i8 - First as i8).abs() as u64;
+        let distance_same = (First as i8 - First as i8).unsigned_abs() as u64;
         assert_eq!(distance_same, 0);
     }
 
@@ -244,7 +417,7 @@ mod tests {
         let (mut elevator, _, mut from_elevator_rx, _) = create_test_elevator("E1");
 
         // Set a very fast speed factor for testing
-        crate::utils::SPEED_FACTOR.store(1, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(1);
 
         elevator.handle_mission(First).await;
 
@@ -272,13 +445,13 @@ mod tests {
         }
 
         // Reset speed factor
-        crate::utils::SPEED_FACTOR.store(100, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(100);
     }
 
     #[tokio::test]
     async fn test_handle_open_doors_only_when_closed() {
         let (mut elevator, _, mut from_elevator_rx, _) = create_test_elevator("E1");
-        crate::utils::SPEED_FACTOR.store(1, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(1);
 
         // Doors start Closed
         elevator.handle_open_doors().await;
@@ -292,7 +465,7 @@ mod tests {
         let msg2 = from_elevator_rx.recv().await.unwrap();
         assert!(matches!(msg2, crate::msg::ElevatorToControllerMsg::DoorsOpened(_)));
 
-        crate::utils::SPEED_FACTOR.store(100, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(100);
     }
 
     #[tokio::test]
@@ -316,7 +489,7 @@ mod tests {
     #[tokio::test]
     async fn test_handle_close_doors_only_when_open() {
         let (mut elevator, _, mut from_elevator_rx, _) = create_test_elevator("E1");
-        crate::utils::SPEED_FACTOR.store(1, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(1);
 
         // Set doors to Open
         elevator.state.doors_status = Open;
@@ -332,7 +505,7 @@ mod tests {
         let msg2 = from_elevator_rx.recv().await.unwrap();
         assert!(matches!(msg2, crate::msg::ElevatorToControllerMsg::DoorsClosed(_)));
 
-        crate::utils::SPEED_FACTOR.store(100, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(100);
     }
 
     #[tokio::test]
@@ -358,7 +531,7 @@ mod tests {
     #[tokio::test]
     async fn test_s1_doors_closed_when_moving() {
         let (mut elevator, _, _, _) = create_test_elevator("E1");
-        crate::utils::SPEED_FACTOR.store(1, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(1);
 
         // Doors start closed
         assert_eq!(elevator.state.doors_status, Closed);
@@ -369,7 +542,7 @@ mod tests {
         // After mission, doors should still be closed (controller opens them)
         assert_eq!(elevator.state.doors_status, Closed);
 
-        crate::utils::SPEED_FACTOR.store(100, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(100);
     }
 
     #[tokio::test]
@@ -407,7 +580,7 @@ mod tests {
     #[tokio::test]
     async fn test_z1_elevator_moving_state() {
         let (mut elevator, _, _, _) = create_test_elevator("E1");
-        crate::utils::SPEED_FACTOR.store(1, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(1);
 
         // Start mission - state changes to Moving
         // Note: floor is only updated after arrival, not during movement
@@ -415,11 +588,9 @@ mod tests {
 
         // After arrival, should be IdleIn(Second) and floor updated
         assert_eq!(elevator.state.status, IdleIn(Second));
-        // Note: The implementation doesn't update state.floor during handle_mission
-        // It only sends messages. Floor tracking is done by controller.
-        // This is an architectural decision - elevator reports position via messages.
+        assert_eq!(elevator.state.floor, Second);
 
-        crate::utils::SPEED_FACTOR.store(100, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(100);
     }
 
     // ========================================================================
@@ -429,7 +600,7 @@ mod tests {
     #[tokio::test]
     async fn test_z2_door_state_transitions_open_cycle() {
         let (mut elevator, _, _, _) = create_test_elevator("E1");
-        crate::utils::SPEED_FACTOR.store(1, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(1);
 
         // Start: Closed
         assert_eq!(elevator.state.doors_status, Closed);
@@ -442,7 +613,7 @@ mod tests {
         elevator.handle_close_doors().await;
         assert_eq!(elevator.state.doors_status, Closed);
 
-        crate::utils::SPEED_FACTOR.store(100, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(100);
     }
 
     // ========================================================================
@@ -452,7 +623,7 @@ mod tests {
     #[tokio::test]
     async fn test_f5_doors_open_and_close_sequence() {
         let (mut elevator, _, mut from_elevator_rx, _) = create_test_elevator("E1");
-        crate::utils::SPEED_FACTOR.store(1, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(1);
 
         // Open doors
         elevator.handle_open_doors().await;
@@ -472,7 +643,200 @@ mod tests {
         let msg4 = from_elevator_rx.recv().await.unwrap();
         assert!(matches!(msg4, crate::msg::ElevatorToControllerMsg::DoorsClosed(_)));
 
-        crate::utils::SPEED_FACTOR.store(100, std::sync::atomic::Ordering::Relaxed);
+        crate::utils::set_speed_factor(100);
+    }
+
+    // ========================================================================
+    // Fahrphysik: Höhe und passierte Ebenen
+    // ========================================================================
+
+    #[tokio::test]
+    async fn test_mission_publishes_height_and_passed_floors() {
+        let (mut elevator, _, _, mut mqtt_rx) = create_test_elevator("E1");
+        crate::utils::set_speed_factor(1);
+
+        elevator.handle_mission(Third).await;
+        elevator.handle_mission(First).await;
+
+        let mut heights = Vec::new();
+        let mut passed = Vec::new();
+        while let Ok(send) = mqtt_rx.try_recv() {
+            match send {
+                ElevatorTopic { msg: Height { height, velocity }, .. } => heights.push((height, velocity)),
+                ElevatorTopic { msg: Passing { passing }, .. } => passed.push(passing),
+                _ => {}
+            }
+        }
+
+        // First, Second on the way up, Second on the way down; never start or destination
+        assert_eq!(passed, vec![First, Second, Second]);
+        let top = heights.iter().position(|&(height, _)| height == kinematics::height(Third)).unwrap();
+        assert!(heights[..top].windows(2).all(|pair| pair[1].0 >= pair[0].0));
+        assert!(heights[top + 1..].iter().all(|&(_, velocity)| velocity <= 0.0));
+        assert_eq!(heights.last().unwrap().0, kinematics::height(First));
+        assert_eq!(elevator.height, kinematics::height(First));
+
+        crate::utils::set_speed_factor(100);
+    }
+
+    #[tokio::test]
+    async fn test_retarget_on_the_way() {
+        let (elevator, to_elevator_tx, mut from_elevator_rx, _) = create_test_elevator("E1");
+        crate::utils::set_speed_factor(1);
+        elevator.init();
+
+        to_elevator_tx.send(ElevatorMission("E1".to_string(), Third)).unwrap();
+        assert!(matches!(from_elevator_rx.recv().await.unwrap(), ElevatorMoving(_, Ground, Third)));
+        to_elevator_tx.send(RetargetMission("E1".to_string(), Ground)).unwrap();
+        to_elevator_tx.send(RetargetMission("E2".to_string(), First)).unwrap();
+        to_elevator_tx.send(RetargetMission("E1".to_string(), Second)).unwrap();
+
+        let retarget = |floor| ElevatorCommand::RetargetMission { floor };
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), retarget(Ground), Err(WrongDirection)));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), retarget(Second), Ok(Some(Second))));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorPassing("E1".to_string(), First));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorArrived("E1".to_string(), Second));
+
+        crate::utils::set_speed_factor(100);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_at_next_floor() {
+        let (elevator, to_elevator_tx, mut from_elevator_rx, _) = create_test_elevator("E1");
+        crate::utils::set_speed_factor(1);
+        elevator.init();
+
+        to_elevator_tx.send(CancelMission("E1".to_string())).unwrap();
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), ElevatorCommand::CancelMission, Err(NotMoving)));
+
+        to_elevator_tx.send(ElevatorMission("E1".to_string(), Third)).unwrap();
+        assert!(matches!(from_elevator_rx.recv().await.unwrap(), ElevatorMoving(..)));
+        to_elevator_tx.send(CancelMission("E1".to_string())).unwrap();
+
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), ElevatorCommand::CancelMission, Ok(Some(First))));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorArrived("E1".to_string(), First));
+
+        crate::utils::set_speed_factor(100);
+    }
+
+    #[tokio::test]
+    async fn test_commands_during_trip_run_after_arrival() {
+        let (elevator, to_elevator_tx, mut from_elevator_rx, _) = create_test_elevator("E1");
+        crate::utils::set_speed_factor(1);
+        elevator.init();
+
+        to_elevator_tx.send(ElevatorMission("E1".to_string(), First)).unwrap();
+        assert!(matches!(from_elevator_rx.recv().await.unwrap(), ElevatorMoving(..)));
+        to_elevator_tx.send(OpenDoors("E2".to_string())).unwrap();
+        to_elevator_tx.send(OpenDoors("E1".to_string())).unwrap();
+
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorArrived("E1".to_string(), First));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), DoorsOpening("E1".to_string()));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), DoorsOpened("E1".to_string()));
+
+        crate::utils::set_speed_factor(100);
+    }
+
+    #[tokio::test]
+    async fn test_express_car_only_stops_at_served_floors() {
+        let (elevator, to_elevator_tx, mut from_elevator_rx, _) = create_test_elevator("E1");
+        crate::utils::set_speed_factor(1);
+        elevator.with_served_floors(vec![Ground, Third]).init();
+
+        to_elevator_tx.send(ElevatorMission("E1".to_string(), Third)).unwrap();
+        assert!(matches!(from_elevator_rx.recv().await.unwrap(), ElevatorMoving(..)));
+        to_elevator_tx.send(RetargetMission("E1".to_string(), Second)).unwrap();
+        to_elevator_tx.send(CancelMission("E1".to_string())).unwrap();
+
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), ElevatorCommand::RetargetMission { floor: Second }, Err(FloorNotServed)));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), ElevatorCommand::CancelMission, Ok(Some(Third))));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorPassing("E1".to_string(), First));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorPassing("E1".to_string(), Second));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorArrived("E1".to_string(), Third));
+
+        crate::utils::set_speed_factor(100);
+    }
+
+    #[tokio::test]
+    async fn test_emergency_stop_between_floors() {
+        let (elevator, to_elevator_tx, mut from_elevator_rx, mut mqtt_rx) = create_test_elevator("E1");
+        crate::utils::set_speed_factor(1);
+        elevator.init();
+
+        to_elevator_tx.send(ElevatorMission("E1".to_string(), Third)).unwrap();
+        assert!(matches!(from_elevator_rx.recv().await.unwrap(), ElevatorMoving(..)));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorPassing("E1".to_string(), First));
+        to_elevator_tx.send(EmergencyStop("E1".to_string())).unwrap();
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), ElevatorCommand::EmergencyStop, Ok(None)));
+        assert!(matches!(from_elevator_rx.recv().await.unwrap(), ElevatorStopped(_, height) if height > kinematics::height(First)));
+
+        // Stands still, only a retarget moves it again
+        to_elevator_tx.send(CancelMission("E1".to_string())).unwrap();
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), ElevatorCommand::CancelMission, Err(EmergencyStopped)));
+        to_elevator_tx.send(RetargetMission("E1".to_string(), Ground)).unwrap();
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), ElevatorCommand::RetargetMission { floor: Ground }, Ok(Some(Ground))));
+        assert!(matches!(from_elevator_rx.recv().await.unwrap(), ElevatorMoving(_, First, Ground)));
+        // Stopped above the first floor, so it passes it again on the way down
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorPassing("E1".to_string(), First));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorArrived("E1".to_string(), Ground));
+
+        let mut acks = Vec::new();
+        while let Ok(send) = mqtt_rx.try_recv() {
+            if let ElevatorTopic { msg: Ack { accepted, reason, .. }, .. } = send {
+                acks.push((accepted, reason));
+            }
+        }
+        assert_eq!(acks, [(true, None), (false, Some(EmergencyStopped)), (true, None)]);
+
+        crate::utils::set_speed_factor(100);
+    }
+
+    #[tokio::test]
+    async fn test_emergency_stop_on_a_floor_is_no_arrival() {
+        let (elevator, to_elevator_tx, mut from_elevator_rx, _) = create_test_elevator("E1");
+        crate::utils::set_speed_factor(1);
+        // Barely moves before the stop
+        elevator.with_motion(MotionProfile { max_speed: 1.6, acceleration: 1.0, jerk: 0.00001 }).init();
+
+        to_elevator_tx.send(ElevatorMission("E1".to_string(), First)).unwrap();
+        assert!(matches!(from_elevator_rx.recv().await.unwrap(), ElevatorMoving(..)));
+        to_elevator_tx.send(EmergencyStop("E1".to_string())).unwrap();
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), ElevatorCommand::EmergencyStop, Ok(Some(Ground))));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorStopped("E1".to_string(), 0.0));
+
+        to_elevator_tx.send(CancelMission("E1".to_string())).unwrap();
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), ElevatorCommand::CancelMission, Err(EmergencyStopped)));
+
+        crate::utils::set_speed_factor(100);
+    }
+
+    #[tokio::test]
+    async fn test_stop_on_the_way_is_not_published() {
+        let (elevator, to_elevator_tx, mut from_elevator_rx, mut mqtt_rx) = create_test_elevator("E1");
+        crate::utils::set_speed_factor(1);
+        elevator.init();
+
+        to_elevator_tx.send(ElevatorMission("E1".to_string(), Third)).unwrap();
+        assert!(matches!(from_elevator_rx.recv().await.unwrap(), ElevatorMoving(..)));
+        to_elevator_tx.send(StopOnTheWay("E1".to_string(), Second)).unwrap();
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), CommandAck("E1".to_string(), ElevatorCommand::RetargetMission { floor: Second }, Ok(Some(Second))));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorPassing("E1".to_string(), First));
+        assert_eq!(from_elevator_rx.recv().await.unwrap(), ElevatorArrived("E1".to_string(), Second));
+
+        while let Ok(send) = mqtt_rx.try_recv() {
+            assert!(!matches!(send, ElevatorTopic { msg: Ack { .. }, .. }));
+        }
+
+        crate::utils::set_speed_factor(100);
+    }
+
+    #[tokio::test]
+    async fn test_faster_profile_arrives_sooner() {
+        let (slow, _, _, _) = create_test_elevator("E1");
+        let (fast, _, _, _) = create_test_elevator("E2");
+        let fast = fast.with_motion(MotionProfile { max_speed: 4.0, acceleration: 2.0, jerk: 4.0 });
+
+        assert!(fast.motion.trip(9.0).duration() < slow.motion.trip(9.0).duration());
     }
 
     // ========================================================================
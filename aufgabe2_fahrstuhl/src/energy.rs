@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+const GRAVITY: f64 = 9.81;
+const JOULES_PER_WH: f64 = 3600.0;
+
+// Building wide, can be set in the config file:
+//   [energy]
+//   regeneration = 0.6
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnergyModel {
+    pub car_mass_kg: f64,
+    // Usually the car plus half the rated load
+    pub counterweight_kg: f64,
+    // Assumed weight of a passenger whose mass is unknown
+    pub person_mass_kg: f64,
+    // Motor and gearbox, 0..1
+    pub efficiency: f64,
+    // Rope and guide rail friction
+    pub friction_wh_per_m: f64,
+    // Opening and closing the doors once
+    pub door_cycle_wh: f64,
+    // Lights, ventilation and control while the car stands still
+    pub standby_w: f64,
+    // Share of the released potential energy fed back, 0 disables regenerative braking
+    pub regeneration: f64,
+}
+
+impl Default for EnergyModel {
+    fn default() -> Self {
+        EnergyModel {
+            car_mass_kg: 1000.0,
+            counterweight_kg: 1075.0,
+            person_mass_kg: 75.0,
+            efficiency: 0.8,
+            friction_wh_per_m: 0.1,
+            door_cycle_wh: 0.1,
+            standby_w: 150.0,
+            regeneration: 0.0,
+        }
+    }
+}
+
+impl EnergyModel {
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [("car_mass_kg", self.car_mass_kg), ("counterweight_kg", self.counterweight_kg), ("person_mass_kg", self.person_mass_kg),
+                              ("friction_wh_per_m", self.friction_wh_per_m), ("door_cycle_wh", self.door_cycle_wh), ("standby_w", self.standby_w)] {
+            if !(value.is_finite() && value >= 0.0) {
+                return Err(format!("{} darf nicht negativ sein", name));
+            }
+        }
+        if !(self.efficiency > 0.0 && self.efficiency <= 1.0) {
+            return Err("efficiency muss zwischen 0 und 1 liegen".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.regeneration) {
+            return Err("regeneration muss zwischen 0 und 1 liegen".to_string());
+        }
+        Ok(())
+    }
+
+    // (drawn, regenerated) in Wh for `distance` metres (negative is down) with `load_kg` on board.
+    // A car heavier than its counterweight releases energy going down, a lighter one going up.
+    pub fn travel(&self, distance: f64, load_kg: f64) -> (f64, f64) {
+        let imbalance = self.car_mass_kg + load_kg - self.counterweight_kg;
+        let potential = imbalance * GRAVITY * distance / JOULES_PER_WH;
+        let needed = potential + self.friction_wh_per_m * distance.abs();
+        if needed >= 0.0 {
+            (needed / self.efficiency, 0.0)
+        } else {
+            (0.0, -needed * self.regeneration)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EnergyMeter {
+    travel_wh: f64,
+    doors_wh: f64,
+    regenerated_wh: f64,
+    standby_ms: u64,
+    // Standing still since, None while moving
+    idle_since: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EnergyReport {
+    pub travel_wh: f64,
+    pub doors_wh: f64,
+    pub standby_wh: f64,
+    pub regenerated_wh: f64,
+    // Travel, doors and standby minus the regenerated energy
+    pub total_wh: f64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BuildingEnergy {
+    pub total_wh: f64,
+    pub elevators: BTreeMap<String, EnergyReport>,
+}
+
+impl EnergyMeter {
+
+    pub fn travelled(&mut self, model: &EnergyModel, distance: f64, load_kg: f64) {
+        let (drawn, regenerated) = model.travel(distance, load_kg);
+        self.travel_wh += drawn;
+        self.regenerated_wh += regenerated;
+    }
+
+    pub fn door_cycle(&mut self, model: &EnergyModel) {
+        self.doors_wh += model.door_cycle_wh;
+    }
+
+    pub fn departed(&mut self, now: u64) {
+        if let Some(since) = self.idle_since.take() {
+            self.standby_ms += now.saturating_sub(since);
+        }
+    }
+
+    pub fn halted(&mut self, now: u64) {
+        self.idle_since.get_or_insert(now);
+    }
+
+    pub fn report(&self, model: &EnergyModel, now: u64) -> EnergyReport {
+        let standby_ms = self.standby_ms + self.idle_since.map_or(0, |since| now.saturating_sub(since));
+        let standby_wh = model.standby_w * standby_ms as f64 / 1000.0 / 3600.0;
+        EnergyReport {
+            travel_wh: self.travel_wh,
+            doors_wh: self.doors_wh,
+            standby_wh,
+            regenerated_wh: self.regenerated_wh,
+            total_wh: self.travel_wh + self.doors_wh + standby_wh - self.regenerated_wh,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heavy_car_needs_more_energy_going_up() {
+        let model = EnergyModel::default();
+
+        let (empty, _) = model.travel(3.0, 0.0);
+        let (full, _) = model.travel(3.0, 150.0);
+        assert!(full > empty);
+        // Going down it is the other way round
+        assert!(model.travel(-3.0, 0.0).0 > model.travel(-3.0, 150.0).0);
+    }
+
+    #[test]
+    fn test_regenerative_braking() {
+        let model = EnergyModel::default();
+        // The empty car is lighter than the counterweight and pulled up
+        assert_eq!(model.travel(9.0, 0.0), (0.0, 0.0));
+
+        let model = EnergyModel { regeneration: 0.5, ..Default::default() };
+        let (drawn, regenerated) = model.travel(9.0, 0.0);
+        let released = 75.0 * GRAVITY * 9.0 / JOULES_PER_WH - 0.9;
+        assert_eq!(drawn, 0.0);
+        assert!((regenerated - released / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_meter_report() {
+        let model = EnergyModel { standby_w: 3600.0, ..Default::default() };
+        let mut meter = EnergyMeter::default();
+
+        meter.halted(0);
+        meter.departed(2000);
+        meter.travelled(&model, 3.0, 75.0);
+        meter.halted(5000);
+        meter.door_cycle(&model);
+
+        let report = meter.report(&model, 6000);
+        assert!((report.standby_wh - 3.0).abs() < 1e-9);
+        assert_eq!(report.doors_wh, model.door_cycle_wh);
+        assert_eq!(report.travel_wh, model.travel(3.0, 75.0).0);
+        assert!((report.total_wh - (report.travel_wh + report.doors_wh + report.standby_wh)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(EnergyModel::default().validate().is_ok());
+        assert!(EnergyModel { efficiency: 0.0, ..Default::default() }.validate().is_err());
+        assert!(EnergyModel { regeneration: 1.5, ..Default::default() }.validate().is_err());
+        assert!(EnergyModel { standby_w: -1.0, ..Default::default() }.validate().is_err());
+    }
+}
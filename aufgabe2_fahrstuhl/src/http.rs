@@ -0,0 +1,361 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use axum::body::Bytes;
+use axum::extract::{Path, State, WebSocketUpgrade};
+use axum::extract::ws::{Message, WebSocket};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, oneshot};
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use crate::mqtt::{InboundErrorReason, MqttConnector, Query, Receive, Send, Topics};
+use crate::mqtt::Send::{ElevatorTopic, PersonTopic};
+use crate::transport::Transport;
+
+// REST endpoints for input and queries, /ws streams the elevator/* and person/* events
+#[derive(Clone)]
+pub struct HttpServer {
+    addr: SocketAddr,
+    listener: Arc<Mutex<Option<TcpListener>>>,
+    events: broadcast::Sender<String>,
+}
+
+#[derive(Clone)]
+struct AppState {
+    to: Sender<Receive>,
+    events: broadcast::Sender<String>,
+}
+
+impl HttpServer {
+
+    pub async fn bind(addr: &str) -> Result<Self, String> {
+        let listener = TcpListener::bind(addr).await
+            .map_err(|e| format!("HTTP-Server kann nicht an {} binden: {}", addr, e))?;
+        let addr = listener.local_addr().map_err(|e| e.to_string())?;
+        let (events, _) = broadcast::channel(10000);
+        Ok(HttpServer {
+            addr,
+            listener: Arc::new(Mutex::new(Some(listener))),
+            events,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Same topic names as MQTT (without prefix), payload as in the legacy protocol
+    fn event(send: &Send) -> Option<String> {
+        match send {
+            ElevatorTopic { .. } | PersonTopic { .. } => Some(serde_json::json!({
+                "topic": Topics::default().topic(send),
+                "payload": send.data(),
+            }).to_string()),
+            _ => None,
+        }
+    }
+
+    fn router(to: Sender<Receive>, events: broadcast::Sender<String>) -> Router {
+        Router::new()
+            .route("/persons", post(introduce))
+            .route("/persons/{id}", get(person))
+            .route("/speed", put(speed))
+            .route("/control", post(control))
+            .route("/elevators", get(elevators))
+            .route("/elevators/{id}/command", post(command))
+            .route("/stats", get(stats))
+            .route("/metrics", get(metrics))
+            .route("/ws", get(websocket))
+            .with_state(AppState { to, events })
+    }
+}
+
+impl Transport for HttpServer {
+
+    async fn publish(&self, send: Send) {
+        if let Some(event) = HttpServer::event(&send) {
+            let _ = self.events.send(event);
+        }
+    }
+
+    fn inbound(&self, to: Sender<Receive>) -> JoinHandle<()> {
+        let listener = self.listener.lock().unwrap().take();
+        let router = HttpServer::router(to, self.events.clone());
+        tokio::spawn(async move {
+            let Some(listener) = listener else { return };
+            if let Err(e) = axum::serve(listener, router).await {
+                tracing::error!("HTTP-Server beendet: {}", e);
+            }
+        })
+    }
+}
+
+fn bad_request(reason: InboundErrorReason) -> Response {
+    (StatusCode::BAD_REQUEST, Json(reason)).into_response()
+}
+
+async fn forward(state: &AppState, receive: Receive) -> Result<(), Response> {
+    state.to.send(receive).await
+        .map_err(|_| StatusCode::SERVICE_UNAVAILABLE.into_response())
+}
+
+// Waits for the simulation, a known id is a conflict
+async fn introduce(State(state): State<AppState>, body: Bytes) -> Response {
+    let person = match MqttConnector::parse_introduce(&body) {
+        Ok(person) => person,
+        Err(reason) => return bad_request(reason),
+    };
+    let (answer, result) = oneshot::channel();
+    if let Err(response) = forward(&state, Receive::Introduce { person: Box::new(person), answer }).await {
+        return response;
+    }
+    match result.await {
+        Ok(Ok(())) => StatusCode::ACCEPTED.into_response(),
+        Ok(Err(reason @ InboundErrorReason::DuplicateId(_))) => (StatusCode::CONFLICT, Json(reason)).into_response(),
+        Ok(Err(reason)) => bad_request(reason),
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+
+async fn speed(State(state): State<AppState>, body: Bytes) -> Response {
+    match MqttConnector::parse_speed(&body) {
+        Ok(receive) => match forward(&state, receive).await {
+            Ok(()) => StatusCode::NO_CONTENT.into_response(),
+            Err(response) => response,
+        },
+        Err(reason) => bad_request(reason),
+    }
+}
+
+async fn control(State(state): State<AppState>, body: Bytes) -> Response {
+    match MqttConnector::parse_control(&body) {
+        Ok(receive) => match forward(&state, receive).await {
+            Ok(()) => StatusCode::NO_CONTENT.into_response(),
+            Err(response) => response,
+        },
+        Err(reason) => bad_request(reason),
+    }
+}
+
+// Answered asynchronously on elevator/{id}/ack
+async fn command(State(state): State<AppState>, Path(id): Path<String>, body: Bytes) -> Response {
+    match MqttConnector::parse_elevator_command(id, &body) {
+        Ok(receive) => match forward(&state, receive).await {
+            Ok(()) => StatusCode::ACCEPTED.into_response(),
+            Err(response) => response,
+        },
+        Err(reason) => bad_request(reason),
+    }
+}
+
+async fn ask(state: &AppState, query: Query) -> Response {
+    let (answer, result) = oneshot::channel();
+    if let Err(response) = forward(state, Receive::Ask { query, answer }).await {
+        return response;
+    }
+    match result.await {
+        Ok(value) if value.get("error").is_some() => (StatusCode::NOT_FOUND, Json(value)).into_response(),
+        Ok(value) => Json(value).into_response(),
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+
+async fn elevators(State(state): State<AppState>) -> Response {
+    ask(&state, Query::Elevators).await
+}
+
+async fn stats(State(state): State<AppState>) -> Response {
+    ask(&state, Query::Stats).await
+}
+
+async fn metrics(State(state): State<AppState>) -> Response {
+    let (answer, result) = oneshot::channel();
+    if let Err(response) = forward(&state, Receive::Ask { query: Query::Metrics, answer }).await {
+        return response;
+    }
+    match result.await {
+        Ok(serde_json::Value::String(text)) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response(),
+        _ => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+
+async fn person(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    ask(&state, Query::Person(id)).await
+}
+
+async fn websocket(State(state): State<AppState>, upgrade: WebSocketUpgrade) -> Response {
+    let events = state.events.subscribe();
+    upgrade.on_upgrade(|socket| stream_events(socket, events))
+}
+
+async fn stream_events(mut socket: WebSocket, mut events: broadcast::Receiver<String>) {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                if socket.send(Message::Text(event.into())).await.is_err() {
+                    return;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use futures_util::StreamExt;
+    use http_body_util::BodyExt;
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+    use crate::controller::Floor::*;
+    use crate::mqtt::{Control, ElevatorCommand, ElevatorMsg};
+
+    fn request(method: &str, uri: &str, body: &str) -> Request<Body> {
+        Request::builder().method(method).uri(uri).body(Body::from(body.to_string())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_introduce_person() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let router = HttpServer::router(tx, broadcast::channel(1).0);
+        // Answers like the simulation: the first introduction of an id wins
+        let simulation = tokio::spawn(async move {
+            let mut persons = Vec::new();
+            while let Some(Receive::Introduce { person, answer }) = rx.recv().await {
+                let Receive::Person { id, curr: Ground, dest: Third, .. } = *person else { panic!("Expected Receive::Person") };
+                let result = match persons.contains(&id) {
+                    true => Err(InboundErrorReason::DuplicateId(id)),
+                    false => {
+                        persons.push(id);
+                        Ok(())
+                    }
+                };
+                let _ = answer.send(result);
+            }
+        });
+
+        let body = r#"{"id":"P1","curr":"Ground","dest":"Third"}"#;
+        let response = router.clone().oneshot(request("POST", "/persons", body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let response = router.oneshot(request("POST", "/persons", body)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["reason"], "DuplicateId");
+        simulation.abort();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_input_is_rejected() {
+        let (tx, _rx) = mpsc::channel(10);
+        let router = HttpServer::router(tx, broadcast::channel(1).0);
+
+        let response = router.clone().oneshot(request("POST", "/persons", r#"{"id":"P1","curr":"Ground","dest":"Ground"}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["reason"], "SameOriginAndDestination");
+
+        let response = router.oneshot(request("PUT", "/speed", r#"{"speed":0}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_change_speed() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let router = HttpServer::router(tx, broadcast::channel(1).0);
+
+        let response = router.oneshot(request("PUT", "/speed", r#"{"speed":50}"#)).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(matches!(rx.recv().await, Some(Receive::Speed { speed: 50 })));
+    }
+
+    #[tokio::test]
+    async fn test_elevator_command() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let router = HttpServer::router(tx, broadcast::channel(1).0);
+
+        let response = router.clone().oneshot(request("POST", "/elevators/E1/command", r#"{"command":"emergency_stop"}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(matches!(rx.recv().await, Some(Receive::ElevatorCommand { elevator, command: ElevatorCommand::EmergencyStop }) if elevator == "E1"));
+
+        let response = router.oneshot(request("POST", "/elevators/E1/command", r#"{"command":"open"}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_control() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let router = HttpServer::router(tx, broadcast::channel(1).0);
+
+        let response = router.clone().oneshot(request("POST", "/control", r#"{"command":"step","ms":100}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(matches!(rx.recv().await, Some(Receive::Control(Control::Step { ms: Some(100) }))));
+
+        let response = router.oneshot(request("POST", "/control", r#"{"command":"stop"}"#)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_query_is_answered() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let router = HttpServer::router(tx, broadcast::channel(1).0);
+        tokio::spawn(async move {
+            while let Some(Receive::Ask { query, answer }) = rx.recv().await {
+                let _ = answer.send(match query {
+                    Query::Stats => serde_json::json!({ "trips": 3 }),
+                    Query::Metrics => serde_json::json!("elevator_trips_total 3\n"),
+                    _ => serde_json::json!({ "error": "Unknown person P9" }),
+                });
+            }
+        });
+
+        let response = router.clone().oneshot(request("GET", "/stats", "")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["trips"], 3);
+
+        let response = router.clone().oneshot(request("GET", "/persons/P9", "")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = router.oneshot(request("GET", "/metrics", "")).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain; version=0.0.4");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "elevator_trips_total 3\n");
+    }
+
+    #[tokio::test]
+    async fn test_websocket_streams_events() {
+        let server = HttpServer::bind("127.0.0.1:0").await.unwrap();
+        let (tx, _rx) = mpsc::channel(10);
+        server.inbound(tx);
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", server.local_addr())).await.unwrap();
+        // The subscription is created during the upgrade, wait until it exists
+        while server.events.receiver_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+        server.publish(ElevatorTopic { id: "E1".to_string(), msg: ElevatorMsg::Position { floor: First } }).await;
+
+        let message = socket.next().await.unwrap().unwrap();
+        let event: serde_json::Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+        assert_eq!(event["topic"], "elevator/E1/position");
+        assert_eq!(event["payload"]["floor"], "First");
+    }
+
+    #[test]
+    fn test_only_elevator_and_person_events_are_streamed() {
+        let reply = Send::ReplyTopic {
+            reply: crate::mqtt::Reply { topic: "client/reply".to_string(), correlation: None },
+            result: serde_json::json!({}),
+        };
+        assert!(HttpServer::event(&reply).is_none());
+    }
+}
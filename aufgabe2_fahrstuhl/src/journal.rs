@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::mqtt::Receive;
+
+// One line per inbound message, JSON: {"at_ms": 1234, "message": {...}}
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JournalEntry {
+    pub at_ms: u64,
+    pub message: Receive,
+}
+
+pub struct Journal {
+    out: BufWriter<File>,
+}
+
+impl Journal {
+
+    pub fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Journal { out: BufWriter::new(file) })
+    }
+
+    // Snapshot requests and queries do not change the simulation and are not recorded.
+    // Neither is pause / step: the simulated time stands still meanwhile, so the
+    // following entries already carry the right times and a replay must not stop
+    pub fn record(&mut self, at_ms: u64, message: &Receive) {
+        if matches!(message, Receive::Control(_)) {
+            return;
+        }
+        if let Ok(message) = serde_json::to_value(message) {
+            let entry = serde_json::json!({ "at_ms": at_ms, "message": message });
+            let _ = writeln!(self.out, "{}", entry);
+            let _ = self.out.flush();
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Vec<JournalEntry>, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut entries = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| format!("{}: {}", path.display(), e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str(&line)
+                .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Floor::*;
+    use crate::mqtt::{Control, ElevatorCommand, Query};
+
+    #[test]
+    fn test_record_and_load() {
+        let path = std::env::temp_dir().join(format!("journal_{}.jsonl", std::process::id()));
+        let mut journal = Journal::create(&path).unwrap();
+
+        journal.record(100, &Receive::Person { id: "P1".to_string(), curr: Ground, dest: Third, attributes: Default::default(), payload: None });
+        journal.record(200, &Receive::SnapshotRequest);
+        journal.record(300, &Receive::Speed { speed: 50 });
+        journal.record(400, &Receive::Control(Control::Step { ms: Some(250) }));
+        journal.record(500, &Receive::ElevatorCommand { elevator: "E1".to_string(), command: ElevatorCommand::RetargetMission { floor: Ground } });
+        drop(journal);
+
+        let entries = Journal::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].at_ms, 100);
+        assert!(matches!(&entries[0].message, Receive::Person { id, curr: Ground, dest: Third, .. } if id == "P1"));
+        assert!(matches!(entries[1].message, Receive::Speed { speed: 50 }));
+        assert!(matches!(&entries[2].message, Receive::ElevatorCommand { elevator, command: ElevatorCommand::RetargetMission { floor: Ground } } if elevator == "E1"));
+    }
+
+    #[test]
+    fn test_queries_are_not_recorded() {
+        let (answer, _) = tokio::sync::oneshot::channel();
+        assert!(serde_json::to_value(Receive::Ask { query: Query::Stats, answer }).is_err());
+    }
+
+    #[test]
+    fn test_load_reports_line() {
+        let path = std::env::temp_dir().join(format!("journal_broken_{}.jsonl", std::process::id()));
+        std::fs::write(&path, "{\"at_ms\":1,\"message\":{\"speed\":5}}\nnot json\n").unwrap();
+
+        let error = Journal::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.contains(":2:"));
+    }
+}
@@ -0,0 +1,245 @@
+use serde::Deserialize;
+use crate::controller::Floor;
+
+pub const FLOOR_HEIGHT_M: f64 = 3.0;
+
+pub fn height(floor: Floor) -> f64 {
+    (floor as u8 - 1) as f64 * FLOOR_HEIGHT_M
+}
+
+// None between floors
+pub fn floor_at(height: f64) -> Option<Floor> {
+    [Floor::Ground, Floor::First, Floor::Second, Floor::Third].into_iter()
+        .find(|&floor| (self::height(floor) - height).abs() < 1e-6)
+}
+
+// Limits of a car, can be set per elevator in the config file:
+//   [motion.Dorisch]
+//   max_speed = 2.5
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MotionProfile {
+    // m/s
+    pub max_speed: f64,
+    // m/s²
+    pub acceleration: f64,
+    // m/s³
+    pub jerk: f64,
+}
+
+impl Default for MotionProfile {
+    fn default() -> Self {
+        MotionProfile {
+            max_speed: 1.6,
+            acceleration: 1.0,
+            jerk: 2.0,
+        }
+    }
+}
+
+impl MotionProfile {
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, value) in [("max_speed", self.max_speed), ("acceleration", self.acceleration), ("jerk", self.jerk)] {
+            if !(value.is_finite() && value > 0.0) {
+                return Err(format!("{} muss größer als 0 sein", name));
+            }
+        }
+        Ok(())
+    }
+
+    // Jerk up, constant acceleration, jerk down (tj, ta, tj) to change the speed by `delta`
+    fn acceleration_phase(&self, delta: f64) -> (f64, f64) {
+        if delta <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let peak = self.acceleration.min((delta * self.jerk).sqrt());
+        let tj = peak / self.jerk;
+        let ta = (delta / peak - tj).max(0.0);
+        (tj, ta)
+    }
+
+    // The S-curve is symmetric, the average speed is halfway between both speeds
+    fn acceleration_distance(&self, from: f64, to: f64) -> f64 {
+        let (tj, ta) = self.acceleration_phase(to - from);
+        (from + to) / 2.0 * (2.0 * tj + ta)
+    }
+
+    // Distance needed to come to a standstill from `speed`
+    pub fn braking_distance(&self, speed: f64) -> f64 {
+        self.acceleration_distance(0.0, speed)
+    }
+
+    // S-curve from standstill to standstill over `distance` metres
+    pub fn trip(&self, distance: f64) -> Trip {
+        self.trip_from(0.0, distance).unwrap_or(Trip { phases: Vec::new(), speed: 0.0, distance: 0.0 })
+    }
+
+    // S-curve from `speed` to standstill, None if the car cannot brake within `distance`
+    pub fn trip_from(&self, speed: f64, distance: f64) -> Option<Trip> {
+        let distance = distance.abs();
+        let speed = speed.clamp(0.0, self.max_speed);
+        let needed = |peak: f64| self.acceleration_distance(speed, peak) + self.braking_distance(peak);
+        if distance <= f64::EPSILON || needed(speed) > distance + 1e-9 {
+            return None;
+        }
+        let mut peak = self.max_speed;
+        // Short trips do not reach the maximum speed, search the peak that fits
+        if needed(peak) > distance {
+            let (mut low, mut high) = (speed, peak);
+            for _ in 0..60 {
+                let mid = (low + high) / 2.0;
+                if needed(mid) > distance {
+                    high = mid;
+                } else {
+                    low = mid;
+                }
+            }
+            peak = low;
+        }
+        let (tj, ta) = self.acceleration_phase(peak - speed);
+        let (bj, ba) = self.acceleration_phase(peak);
+        let cruise = if peak > 0.0 { (distance - needed(peak)).max(0.0) / peak } else { 0.0 };
+        let jerk = self.jerk;
+        Some(Trip {
+            phases: vec![(tj, jerk), (ta, 0.0), (tj, -jerk), (cruise, 0.0), (bj, -jerk), (ba, 0.0), (bj, jerk)],
+            speed,
+            distance,
+        })
+    }
+}
+
+// Phases of constant jerk (duration s, jerk m/s³) starting at `speed`
+#[derive(Debug)]
+pub struct Trip {
+    phases: Vec<(f64, f64)>,
+    speed: f64,
+    distance: f64,
+}
+
+impl Trip {
+
+    pub fn duration(&self) -> f64 {
+        self.phases.iter().map(|(duration, _)| duration).sum()
+    }
+
+    // Travelled distance and speed after `t` seconds
+    pub fn at(&self, t: f64) -> (f64, f64) {
+        let (mut position, mut speed, mut acceleration) = (0.0, self.speed, 0.0);
+        let mut left = t.max(0.0);
+        for &(duration, jerk) in &self.phases {
+            let dt = left.min(duration);
+            position += speed * dt + acceleration * dt * dt / 2.0 + jerk * dt * dt * dt / 6.0;
+            speed += acceleration * dt + jerk * dt * dt / 2.0;
+            acceleration += jerk * dt;
+            left -= dt;
+            if left <= 0.0 {
+                break;
+            }
+        }
+        if t >= self.duration() {
+            return (self.distance, 0.0);
+        }
+        (position.clamp(0.0, self.distance), speed.max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Floor::*;
+
+    fn samples(trip: &Trip) -> Vec<(f64, f64)> {
+        let steps = 1000;
+        (0..=steps).map(|i| trip.at(trip.duration() * i as f64 / steps as f64)).collect()
+    }
+
+    #[test]
+    fn test_floor_heights() {
+        assert_eq!(height(Ground), 0.0);
+        assert_eq!(height(Third), 3.0 * FLOOR_HEIGHT_M);
+        assert_eq!(floor_at(2.0 * FLOOR_HEIGHT_M), Some(Second));
+        assert_eq!(floor_at(4.5), None);
+    }
+
+    #[test]
+    fn test_trip_ends_at_distance() {
+        let trip = MotionProfile::default().trip(height(Third));
+
+        let (distance, speed) = trip.at(trip.duration());
+        assert!((distance - 9.0).abs() < 1e-9);
+        assert_eq!(speed, 0.0);
+        assert_eq!(trip.at(0.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_long_trip_reaches_max_speed() {
+        let profile = MotionProfile::default();
+        let trip = profile.trip(height(Third));
+
+        let top = samples(&trip).iter().map(|(_, speed)| *speed).fold(0.0, f64::max);
+        assert!((top - profile.max_speed).abs() < 1e-6);
+        // 9 m at 1.6 m/s plus acceleration and braking
+        assert!(trip.duration() > 9.0 / 1.6 && trip.duration() < 9.0 / 1.6 + 4.0);
+    }
+
+    #[test]
+    fn test_short_trip_stays_below_max_speed() {
+        let profile = MotionProfile { max_speed: 5.0, ..Default::default() };
+        let trip = profile.trip(FLOOR_HEIGHT_M);
+
+        let top = samples(&trip).iter().map(|(_, speed)| *speed).fold(0.0, f64::max);
+        assert!(top < profile.max_speed);
+        assert!((trip.at(trip.duration()).0 - FLOOR_HEIGHT_M).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_motion_is_smooth_and_within_limits() {
+        let profile = MotionProfile::default();
+        let trip = profile.trip(2.0 * FLOOR_HEIGHT_M);
+        let dt = trip.duration() / 1000.0;
+
+        let samples = samples(&trip);
+        for pair in samples.windows(2) {
+            let ((d1, v1), (d2, v2)) = (pair[0], pair[1]);
+            assert!(d2 >= d1, "Kabine fährt rückwärts");
+            assert!(v2 <= profile.max_speed + 1e-9);
+            assert!((v2 - v1).abs() / dt <= profile.acceleration + 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_no_trip_for_same_floor() {
+        let trip = MotionProfile::default().trip(0.0);
+        assert_eq!(trip.duration(), 0.0);
+        assert_eq!(trip.at(1.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_trip_from_speed() {
+        let profile = MotionProfile::default();
+        let trip = profile.trip_from(1.0, 2.0 * FLOOR_HEIGHT_M).unwrap();
+
+        assert_eq!(trip.at(0.0), (0.0, 1.0));
+        assert!((trip.at(trip.duration()).0 - 2.0 * FLOOR_HEIGHT_M).abs() < 1e-9);
+        let top = samples(&trip).iter().map(|(_, speed)| *speed).fold(0.0, f64::max);
+        assert!(top <= profile.max_speed + 1e-9);
+    }
+
+    #[test]
+    fn test_trip_from_speed_needs_braking_distance() {
+        let profile = MotionProfile::default();
+        let braking = profile.braking_distance(profile.max_speed);
+
+        assert!(profile.trip_from(profile.max_speed, braking * 0.9).is_none());
+        let trip = profile.trip_from(profile.max_speed, braking).unwrap();
+        assert!((trip.at(trip.duration()).0 - braking).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(MotionProfile::default().validate().is_ok());
+        assert!(MotionProfile { jerk: 0.0, ..Default::default() }.validate().is_err());
+        assert!(MotionProfile { max_speed: f64::NAN, ..Default::default() }.validate().is_err());
+    }
+}
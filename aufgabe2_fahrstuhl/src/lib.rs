@@ -0,0 +1,23 @@
+pub mod controller;
+pub mod elevator;
+pub mod msg;
+pub mod person;
+pub mod logger;
+pub mod utils;
+pub mod mqtt;
+pub mod stats;
+pub mod protocol;
+pub mod transport;
+pub mod simulation;
+pub mod http;
+pub mod metrics;
+pub mod config;
+pub mod journal;
+pub mod scenario;
+pub mod tui;
+pub mod kinematics;
+pub mod energy;
+pub mod routing;
+pub mod parking;
+#[cfg(feature = "embedded-broker")]
+pub mod broker;
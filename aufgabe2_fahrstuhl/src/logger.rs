@@ -1,17 +1,85 @@
-use log::{Record, Level, Metadata};
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
 
-pub struct SimpleLogger;
+// The embedded broker opens an error level span per connection, keep it quiet unless asked for
+pub const DEFAULT_FILTER: &str = "info,rumqttd=off";
 
-impl log::Log for SimpleLogger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Level::Info
-    }
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
 
-    fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            println!("[{}] {} - {}", record.level(), record.target(), record.args());
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("Unknown log format {}", other)),
         }
     }
+}
+
+// Journey and mission spans are logged when they close, with their fields and duration
+pub fn init(filter: &str, format: LogFormat) -> Result<(), String> {
+    let (filter, handle) = reload::Layer::new(parse_filter(filter)?);
+    let registry = tracing_subscriber::registry().with(filter);
+    let result = match format {
+        LogFormat::Text => registry
+            .with(fmt::layer().with_span_events(FmtSpan::CLOSE).with_ansi(std::io::stdout().is_terminal()))
+            .try_init(),
+        LogFormat::Json => registry
+            .with(fmt::layer().json().with_span_events(FmtSpan::CLOSE).with_current_span(true).with_span_list(false))
+            .try_init(),
+    };
+    result.map_err(|e| e.to_string())?;
+    let _ = FILTER.set(handle);
+    Ok(())
+}
+
+pub fn parse_filter(filter: &str) -> Result<EnvFilter, String> {
+    EnvFilter::try_new(filter).map_err(|e| e.to_string())
+}
+
+// Used by simulation/log to change the level while running
+pub fn set_filter(filter: &str) -> Result<(), String> {
+    let filter = parse_filter(filter)?;
+    match FILTER.get() {
+        Some(handle) => handle.reload(filter).map_err(|e| e.to_string()),
+        None => Err("Logging ist nicht initialisiert".to_string()),
+    }
+}
 
-    fn flush(&self) {}
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert_eq!("Text".parse(), Ok(LogFormat::Text));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert!(parse_filter(DEFAULT_FILTER).is_ok());
+        assert!(parse_filter("debug,aufgabe2_fahrstuhl::controller=trace").is_ok());
+        assert!(parse_filter("controller=loud").is_err());
+    }
+
+    #[test]
+    fn test_set_filter_rejects_invalid_filter() {
+        assert!(set_filter("controller=loud").is_err());
+    }
+}
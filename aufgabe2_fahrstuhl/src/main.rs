@@ -1,96 +1,164 @@
-use crate::controller::ElevatorController;
-use crate::elevator::Elevator;
-use crate::logger::SimpleLogger;
-use crate::mqtt::Receive;
-use crate::person::Person;
-use log::LevelFilter;
-use mqtt::MqttConnector;
-use tokio::sync::{broadcast, mpsc};
-use std::collections::HashSet;
-use crate::utils::SPEED_FACTOR;
-use std::sync::atomic::Ordering;
-mod controller;
-mod elevator;
-mod msg;
-mod person;
-mod logger;
-mod utils;
-mod mqtt;
-
-static LOGGER: SimpleLogger = SimpleLogger;
+mod cli;
+
+use aufgabe2_fahrstuhl::config::Config;
+use aufgabe2_fahrstuhl::http::HttpServer;
+use aufgabe2_fahrstuhl::journal::{Journal, JournalEntry};
+use aufgabe2_fahrstuhl::logger;
+use aufgabe2_fahrstuhl::mqtt::{MqttConnector, Topics};
+use aufgabe2_fahrstuhl::protocol::Codec;
+use aufgabe2_fahrstuhl::scenario::{self, Scenario};
+use aufgabe2_fahrstuhl::simulation::Simulation;
+use aufgabe2_fahrstuhl::tui::Tui;
+use aufgabe2_fahrstuhl::utils::{set_seed, set_speed_factor};
+use clap::Parser;
+use cli::{Cli, Command, PlaybackArgs, RunArgs};
+use std::path::Path;
+use std::time::Duration;
 
 #[tokio::main]
 async fn main() {
-    log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Error)).unwrap();
-
-    // controller -> elevators
-    let (controller_to_elevators_tx, _) = broadcast::channel(100000);
-    // elevator -> controller
-    let (elevator_to_controller_tx, elevator_to_controller_rx) = mpsc::channel(100000);
-    // persons -> controller
-    let (person_to_controller_tx, person_to_controller_rx) = mpsc::channel(100000);
-    // controller -> persons
-    let (controller_to_persons_tx, _) = broadcast::channel(100000);
-    // components -> mqtt
-    let (to_mqtt_tx, to_mqtt_rx) = mpsc::channel(100000);
-    // mqtt -> persons
-    let (mqtt_to_person_tx, mut mqtt_to_person_rx) = mpsc::channel(100000);
-
-    let controller = ElevatorController::new(
-        elevator_to_controller_rx,
-        controller_to_elevators_tx.clone(),
-        person_to_controller_rx,
-        controller_to_persons_tx.clone(),
-        to_mqtt_tx.clone(),
-        vec!["Dorisch".to_string(), "Ionisch".to_string(), "Korinthisch".to_string()]
-    );
-    let controller_handle = controller.init();
-
-    let mqtt = MqttConnector::new(to_mqtt_rx, mqtt_to_person_tx).await;
-
-    let mut threads = vec![
-        mqtt.mqtt_subscriber(),
-        mqtt.mqtt_publisher(),
-        Elevator::new("Dorisch", controller_to_elevators_tx.subscribe(), elevator_to_controller_tx.clone(), to_mqtt_tx.clone()).init(),
-        Elevator::new("Ionisch", controller_to_elevators_tx.subscribe(), elevator_to_controller_tx.clone(), to_mqtt_tx.clone()).init(),
-        Elevator::new("Korinthisch", controller_to_elevators_tx.subscribe(), elevator_to_controller_tx.clone(), to_mqtt_tx.clone()).init(),
-        controller_handle,
-    ];
-
-    for i in 0..5 {
-        let person_id = format!("Student_{}", i);
-        threads.push(Person::new(
-            &person_id,
-            controller_to_persons_tx.subscribe(),
-            person_to_controller_tx.clone(),
-            to_mqtt_tx.clone()
-        ).init());
+    let mut cli = Cli::parse();
+    let result = match cli.command.take() {
+        None => run(&cli, &cli.run).await,
+        Some(Command::Run(args)) => run(&cli, &args).await,
+        Some(Command::Scenario { file, playback }) => play_scenario(&cli, &file, &playback).await,
+        Some(Command::Replay { journal, playback }) => replay(&cli, &journal, &playback).await,
+        Some(Command::ValidateConfig { config }) => validate_config(&config),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+// Validates, then applies logging, seed and speed
+fn prepare(config: Config) -> Result<Config, String> {
+    let errors = config.validate();
+    if !errors.is_empty() {
+        return Err(format!("Ungültige Konfiguration:\n  {}", errors.join("\n  ")));
+    }
+    logger::init(&config.log_filter(), config.log_format()?)?;
+    if let Some(seed) = config.seed {
+        set_seed(seed);
+    }
+    if let Some(speed) = config.speed {
+        set_speed_factor(speed);
+    }
+    Ok(config)
+}
+
+async fn run(cli: &Cli, args: &RunArgs) -> Result<(), String> {
+    let mut config = args.config()?.overlay(cli.log_config());
+    if args.tui {
+        // The terminal belongs to the UI, log lines would tear it apart
+        config.log.filter = Some("off".to_string());
+    }
+    let config = prepare(config)?;
+
+    if args.embedded_broker {
+        start_embedded_broker()?;
+    }
+
+    // e.g. --topic-prefix building/a -> building/a/elevator/{id}/position
+    let topics = Topics::new(config.topic_prefix.as_deref().unwrap_or_default());
+    let mqtt = match args.offline {
+        true => None,
+        false => Some(MqttConnector::new(topics.clone(), Codec::new(config.protocol()?), &config.mqtt()).await
+            .map_err(|e| format!("MQTT-Verbindung fehlgeschlagen: {}", e))?),
+    };
+
+    let http = match config.http_port() {
+        Some(port) => Some(HttpServer::bind(&format!("{}:{}", config.http_bind(), port)).await?),
+        None => None,
+    };
+
+    let mut simulation = simulation(&config).with_topics(topics);
+    if config.snapshot_interval_ms() > 0 {
+        simulation = simulation.with_snapshot_interval(Duration::from_millis(config.snapshot_interval_ms()));
+    }
+    if let Some(path) = &args.journal {
+        simulation = simulation.with_journal(Journal::create(path)?);
     }
 
-    let mut created_persons: HashSet<String> = HashSet::new();
-    loop {
-
-        if let Some(msg) = mqtt_to_person_rx.recv().await {
-            match msg {
-                Receive::Person { id, curr: current_floor, dest: destination_floor } => {
-                    if created_persons.insert(id.clone()) {
-                        println!("Person erstellt via MQTT: id={}, curr={:?}, dest={:?}", id, current_floor, destination_floor);
-                        let person = Person::with(
-                            &id,
-                            controller_to_persons_tx.subscribe(),
-                            person_to_controller_tx.clone(),
-                            to_mqtt_tx.clone(),
-                            current_floor,
-                            destination_floor
-                        );
-                        threads.push(person.init());
-                    }
-                }
-                Receive::Speed { speed } => {
-                    SPEED_FACTOR.store(speed, Ordering::Relaxed);
-                    println!("Simulationsgeschwindigkeit geändert: {}%", speed);
-                }
-            }
-        }
+    let tui = args.tui.then(Tui::new);
+    let running = simulation.start(((mqtt, http), tui.clone()));
+    match tui {
+        Some(tui) => tokio::select! {
+            _ = running => {}
+            _ = tui.closed() => {}
+        },
+        None => { let _ = running.await; }
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+// Building, students and car settings, the same for live runs, scenarios and replays
+fn simulation(config: &Config) -> Simulation {
+    let mut simulation = Simulation::new(config.elevator_names())
+        .with_students(config.persons());
+    for (elevator, motion) in &config.motion {
+        simulation = simulation.with_motion(elevator, *motion);
+    }
+    for (elevator, floors) in &config.served_floors {
+        simulation = simulation.with_served_floors(elevator, floors.clone());
+    }
+    for (elevator, rated_load_kg) in &config.rated_load_kg {
+        simulation = simulation.with_rated_load(elevator, *rated_load_kg);
+    }
+    if let Some(energy) = config.energy {
+        simulation = simulation.with_energy(energy);
+    }
+    if let Some(parking) = config.parking {
+        simulation = simulation.with_parking(parking);
+    }
+    simulation
+}
+
+async fn play_scenario(cli: &Cli, file: &Path, playback: &PlaybackArgs) -> Result<(), String> {
+    let scenario = Scenario::load(file)?;
+    // Only the scripted persons unless --persons asks for students
+    let config = Config {
+        elevators: scenario.elevators.clone(),
+        persons: Some(0),
+        speed: scenario.speed,
+        seed: scenario.seed,
+        ..Default::default()
+    };
+    let config = prepare(playback.config(config)?.overlay(cli.log_config()))?;
+    play(&config, scenario.entries(), playback).await
+}
+
+async fn replay(cli: &Cli, journal: &Path, playback: &PlaybackArgs) -> Result<(), String> {
+    let entries = Journal::load(journal)?;
+    let config = prepare(playback.config(Config::default())?.overlay(cli.log_config()))?;
+    play(&config, entries, playback).await
+}
+
+async fn play(config: &Config, entries: Vec<JournalEntry>, playback: &PlaybackArgs) -> Result<(), String> {
+    let stats = scenario::play(simulation(config), entries, Duration::from_secs(playback.timeout)).await?;
+    println!("{}", serde_json::to_string_pretty(&stats).map_err(|e| e.to_string())?);
+    Ok(())
+}
+
+fn validate_config(path: &Path) -> Result<(), String> {
+    let errors = Config::load(path)?.validate();
+    if !errors.is_empty() {
+        return Err(format!("{}:\n  {}", path.display(), errors.join("\n  ")));
+    }
+    println!("{}: Konfiguration ist gültig", path.display());
+    Ok(())
+}
+
+#[cfg(feature = "embedded-broker")]
+fn start_embedded_broker() -> Result<(), String> {
+    use aufgabe2_fahrstuhl::broker;
+    broker::start(broker::TCP_PORT, broker::WS_PORT)
+        .map_err(|e| format!("Eingebetteter Broker konnte nicht gestartet werden: {}", e))?;
+    tracing::info!("Eingebetteter Broker gestartet: mqtt://localhost:{}, ws://localhost:{}", broker::TCP_PORT, broker::WS_PORT);
+    Ok(())
+}
+
+#[cfg(not(feature = "embedded-broker"))]
+fn start_embedded_broker() -> Result<(), String> {
+    Err("--embedded-broker benötigt das Feature embedded-broker: cargo run --features embedded-broker -- --embedded-broker".to_string())
+}
@@ -0,0 +1,101 @@
+use std::fmt::Write;
+use crate::stats::Histogram;
+
+// Minimal Prometheus text format (version 0.0.4) writer
+#[derive(Default)]
+pub struct MetricsText {
+    out: String,
+}
+
+impl MetricsText {
+
+    pub fn gauge(&mut self, name: &str, help: &str, samples: &[(Vec<(&str, String)>, u64)]) {
+        self.header(name, help, "gauge");
+        for (labels, value) in samples {
+            let _ = writeln!(self.out, "{}{} {}", name, MetricsText::labels(labels), value);
+        }
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, samples: &[(Vec<(&str, String)>, u64)]) {
+        self.header(name, help, "counter");
+        for (labels, value) in samples {
+            let _ = writeln!(self.out, "{}{} {}", name, MetricsText::labels(labels), value);
+        }
+    }
+
+    // Histograms are recorded in ms and exposed in seconds
+    pub fn histogram(&mut self, name: &str, help: &str, histogram: &Histogram) {
+        self.header(name, help, "histogram");
+        let mut cumulative = 0;
+        for (bound, count) in histogram.bounds_ms().iter().zip(histogram.counts()) {
+            cumulative += count;
+            let _ = writeln!(self.out, "{}_bucket{{le=\"{}\"}} {}", name, seconds(*bound), cumulative);
+        }
+        let _ = writeln!(self.out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count());
+        let _ = writeln!(self.out, "{}_sum {}", name, seconds(histogram.sum_ms()));
+        let _ = writeln!(self.out, "{}_count {}", name, histogram.count());
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    fn labels(labels: &[(&str, String)]) -> String {
+        if labels.is_empty() {
+            return String::new();
+        }
+        let pairs: Vec<String> = labels.iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, value.replace('\\', "\\\\").replace('"', "\\\"")))
+            .collect();
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{}", ms as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gauge_and_counter() {
+        let mut text = MetricsText::default();
+        text.gauge("elevator_car_passengers", "Passengers per car", &[(vec![("car", "E1".to_string())], 2)]);
+        text.counter("elevator_trips_total", "Trips", &[(vec![], 5)]);
+
+        let out = text.finish();
+        assert!(out.contains("# TYPE elevator_car_passengers gauge\n"));
+        assert!(out.contains("elevator_car_passengers{car=\"E1\"} 2\n"));
+        assert!(out.contains("# TYPE elevator_trips_total counter\nelevator_trips_total 5\n"));
+    }
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::new(&[1000, 5000]);
+        histogram.observe(500);
+        histogram.observe(3000);
+        histogram.observe(9000);
+
+        let mut text = MetricsText::default();
+        text.histogram("elevator_wait_time_seconds", "Wait time", &histogram);
+
+        let out = text.finish();
+        assert!(out.contains("elevator_wait_time_seconds_bucket{le=\"1\"} 1\n"));
+        assert!(out.contains("elevator_wait_time_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(out.contains("elevator_wait_time_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("elevator_wait_time_seconds_sum 12.5\n"));
+        assert!(out.contains("elevator_wait_time_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn test_label_values_are_escaped() {
+        assert_eq!(MetricsText::labels(&[("car", "a\"b".to_string())]), "{car=\"a\\\"b\"}");
+    }
+}
@@ -1,16 +1,29 @@
-use paho_mqtt::{AsyncClient, ConnectOptions, Message};
+use paho_mqtt::{AsyncClient, ConnectOptions, ConnectOptionsBuilder, CreateOptionsBuilder, Message, MessageBuilder, Properties, PropertyCode, SslOptionsBuilder, MQTT_VERSION_5};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use serde::{Deserialize, Serialize};
-use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::mpsc::Sender;
 use tokio::task::JoinHandle;
 use ElevatorMsg::Position;
 use PersonMsg::{Boarding, StatusUpdate};
-use Send::{ElevatorTopic, PersonTopic};
-use crate::controller::{BoardingStatus, Floor};
+use crate::mqtt::Send::{ElevatorTopic, PersonTopic, ReplyTopic, SimulationTopic};
+use crate::controller::{BoardingStatus, Floor, Snapshot};
 use crate::elevator::DoorStatus;
-use crate::mqtt::ElevatorMsg::{Door, Missions, Moving, Passengers};
-use crate::mqtt::PersonMsg::Request;
-use crate::person::PersonStatus;
+use crate::energy::{BuildingEnergy, EnergyReport};
+use crate::mqtt::ElevatorMsg::{Ack, Door, Height, Missions, Moving, Passengers, Passing};
+use crate::msg::Rejection;
+use crate::mqtt::InboundErrorReason::{DuplicateId, MalformedPayload, MissingReplyTopic, SameOriginAndDestination, SpeedOutOfRange, UnknownFloor, UnknownTopic};
+use crate::mqtt::PersonMsg::{Introduced, Request};
+use crate::person::{Attributes, PersonStatus};
+use crate::routing::Leg;
+use crate::logger;
+use crate::protocol;
+use crate::protocol::Codec;
+use crate::transport::Transport;
 
+#[derive(Clone, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Send {
     ElevatorTopic {
         id: String,
@@ -19,29 +32,150 @@ pub enum Send {
     PersonTopic {
         id: String,
         msg: PersonMsg
+    },
+    SimulationTopic {
+        msg: SimulationMsg
+    },
+    ReplyTopic {
+        reply: Reply,
+        result: serde_json::Value
     }
 }
 
+#[derive(Clone, PartialEq, Debug)]
+pub enum Query {
+    Elevators,
+    Person(String),
+    Stats,
+    // Prometheus text, answered as a JSON string (only used by HTTP /metrics)
+    Metrics,
+    // Same content as simulation/snapshot (only used by the terminal UI)
+    Snapshot
+}
+
+// Where to answer a query: MQTT v5 response topic / correlation data or
+// "reply_to" / "correlation" in the request payload
+#[derive(Clone, PartialEq, Debug)]
+pub struct Reply {
+    pub topic: String,
+    pub correlation: Option<Vec<u8>>,
+}
 
-#[derive(Serialize, Deserialize)]
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum ElevatorMsg {
     Position { floor: Floor },
     Door { status: DoorStatus },
-    Moving { from: Floor, to: Floor },
+    Moving {
+        from: Floor,
+        to: Floor,
+        // Empty trip to the parking floor
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        parking: bool,
+    },
     Passengers { passengers: Vec<String> },
-    Missions { missions: Vec<Floor> }
+    Missions { missions: Vec<Floor> },
+    // Metres above the ground floor, sent every HEIGHT_INTERVAL_MS while moving
+    Height { height: f64, velocity: f64 },
+    Passing { passing: Floor },
+    // Answer to a command on elevator/{id}/command
+    Ack {
+        #[serde(flatten)]
+        command: ElevatorCommand,
+        accepted: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stop: Option<Floor>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<Rejection>,
+    },
+    // Consumption since the start, sent on every arrival
+    Energy(EnergyReport),
+    // Sent whenever someone gets in or out, percent of the rated load
+    Load { load_kg: f64, percent: f64, overloaded: bool },
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum PersonMsg {
-    StatusUpdate { status: PersonStatus },
-    Boarding { status: BoardingStatus },
+    StatusUpdate {
+        status: PersonStatus,
+        // Only for journeys with transfers: all legs and the index of the current one
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        legs: Vec<Leg>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        leg: Option<usize>,
+    },
+    Boarding {
+        #[serde(flatten)]
+        status: BoardingStatus,
+    },
     Request { floor: Floor },
+    Introduced { curr: Floor, dest: Floor },
     // ...
 }
 
+#[derive(Clone, Debug, Serialize)]
+#[serde(untagged)]
+pub enum SimulationMsg {
+    Snapshot(Snapshot),
+    Error(InboundError),
+    // All cars together, sent on every arrival and emergency stop and after every snapshot
+    Energy(BuildingEnergy),
+}
+
+// simulation/control: {"command": "pause"}, {"command": "resume"}, {"command": "step", "ms": 500}
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum Control {
+    Pause,
+    Resume,
+    // Without ms to the next pending timer (door, travel, person)
+    Step {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ms: Option<u64>,
+    },
+}
+
+// elevator/{id}/command: {"command": "cancel_mission"}, {"command": "retarget_mission", "floor": "Ground"},
+// {"command": "emergency_stop"}
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ElevatorCommand {
+    CancelMission,
+    RetargetMission { floor: Floor },
+    EmergencyStop,
+}
+
+pub const MIN_SPEED: u64 = 1;
+pub const MAX_SPEED: u64 = 10000;
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+#[serde(tag = "reason", content = "detail")]
+pub enum InboundErrorReason {
+    MalformedPayload(String),
+    UnknownFloor(String),
+    SameOriginAndDestination,
+    DuplicateId(String),
+    SpeedOutOfRange(u64),
+    MissingReplyTopic,
+    UnknownTopic,
+    UnsupportedVersion(u32),
+    UnexpectedType(String),
+    InvalidLogFilter(String),
+    // No car serves both the origin and the destination
+    NoElevatorForTrip,
+    InvalidAttributes(String),
+}
+
+// Published on simulation/errors, echoes the offending message
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct InboundError {
+    pub topic: String,
+    pub payload: String,
+    pub error: InboundErrorReason,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum Receive {
@@ -49,93 +183,894 @@ pub enum Receive {
         id: String,
         curr: Floor,
         dest: Floor,
+        #[serde(default, skip_serializing_if = "Attributes::is_default")]
+        attributes: Attributes,
+        // Payload as received on person/introduce (or POST /persons), echoed in errors
+        #[serde(skip)]
+        payload: Option<String>,
     },
     Speed {
         speed: u64,
+    },
+    LogFilter {
+        filter: String,
+    },
+    Control(Control),
+    ElevatorCommand {
+        elevator: String,
+        command: ElevatorCommand,
+    },
+    #[serde(skip)]
+    SnapshotRequest,
+    #[serde(skip)]
+    Query {
+        query: Query,
+        reply: Reply,
+    },
+    #[serde(skip)]
+    Ask {
+        query: Query,
+        answer: tokio::sync::oneshot::Sender<serde_json::Value>,
+    },
+    // A Receive::Person whose outcome is answered in-process (HTTP), journaled as the person
+    #[serde(skip)]
+    Introduce {
+        person: Box<Receive>,
+        answer: tokio::sync::oneshot::Sender<Result<(), InboundErrorReason>>,
+    },
+}
+
+impl Receive {
+
+    // Checks on top of the payload format, also for messages injected in-process
+    pub fn validate(&self) -> Result<(), InboundErrorReason> {
+        match self {
+            Receive::Person { curr, dest, .. } if curr == dest => Err(SameOriginAndDestination),
+            Receive::Person { attributes, .. } => attributes.validate().map_err(InboundErrorReason::InvalidAttributes),
+            Receive::Speed { speed } if !(MIN_SPEED..=MAX_SPEED).contains(speed) => Err(SpeedOutOfRange(*speed)),
+            Receive::LogFilter { filter } => logger::parse_filter(filter).map(|_| ()).map_err(InboundErrorReason::InvalidLogFilter),
+            Receive::Introduce { person, .. } => person.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    // Topic the message comes in on, None for in-process requests
+    pub fn topic(&self, topics: &Topics) -> Option<String> {
+        match self {
+            Receive::Person { .. } => Some(topics.person_introduce()),
+            Receive::Speed { .. } => Some(topics.simulation_speed()),
+            Receive::LogFilter { .. } => Some(topics.simulation_log()),
+            Receive::Control(_) => Some(topics.simulation_control()),
+            Receive::ElevatorCommand { elevator, .. } => Some(format!("{}elevator/{}/command", topics.prefix, elevator)),
+            Receive::SnapshotRequest => Some(topics.snapshot_request()),
+            Receive::Introduce { person, .. } => person.topic(topics),
+            Receive::Query { .. } | Receive::Ask { .. } => None,
+        }
     }
 }
 
+#[derive(Deserialize, Default)]
+struct QueryRequest {
+    reply_to: Option<String>,
+    correlation: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct IntroduceRequest {
+    id: String,
+    curr: serde_json::Value,
+    dest: serde_json::Value,
+    #[serde(default)]
+    attributes: Attributes,
+}
+
+#[derive(Deserialize)]
+struct LogFilterRequest {
+    filter: String,
+}
+
+#[derive(Deserialize)]
+struct SpeedRequest {
+    speed: u64,
+}
+
+// Single place for the topic layout. Every topic is put below `prefix`, so several
+// simulations (e.g. "building/a/", "building/b/") can share one broker.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Topics {
+    prefix: String,
+}
+
+impl Topics {
+    pub fn new(prefix: &str) -> Self {
+        let prefix = prefix.trim_matches('/');
+        if prefix.is_empty() {
+            Topics::default()
+        } else {
+            Topics { prefix: format!("{}/", prefix) }
+        }
+    }
+
+    pub fn elevator(&self, id: &str, msg: &ElevatorMsg) -> String {
+        let kind = match msg {
+            Position { .. } => "position",
+            Door { .. } => "door",
+            Moving { .. } => "moving",
+            Passengers { .. } => "passengers",
+            Missions { .. } => "missions",
+            Height { .. } => "height",
+            Passing { .. } => "passing",
+            Ack { .. } => "ack",
+            ElevatorMsg::Energy(_) => "energy",
+            ElevatorMsg::Load { .. } => "load",
+        };
+        format!("{}elevator/{}/{}", self.prefix, id, kind)
+    }
+
+    pub fn person(&self, id: &str, msg: &PersonMsg) -> String {
+        let kind = match msg {
+            StatusUpdate { .. } => "status",
+            Boarding { .. } => "boarding",
+            Request { .. } => "request",
+            Introduced { .. } => "introduced",
+        };
+        format!("{}person/{}/{}", self.prefix, id, kind)
+    }
+
+    pub fn person_introduce(&self) -> String {
+        format!("{}person/introduce", self.prefix)
+    }
+
+    pub fn simulation_speed(&self) -> String {
+        format!("{}simulation/speed", self.prefix)
+    }
+
+    pub fn simulation(&self, msg: &SimulationMsg) -> String {
+        let kind = match msg {
+            SimulationMsg::Snapshot(_) => "snapshot",
+            SimulationMsg::Error(_) => "errors",
+            SimulationMsg::Energy(_) => "energy",
+        };
+        format!("{}simulation/{}", self.prefix, kind)
+    }
+
+    pub fn simulation_control(&self) -> String {
+        format!("{}simulation/control", self.prefix)
+    }
+
+    pub fn elevator_commands(&self) -> String {
+        format!("{}elevator/+/command", self.prefix)
+    }
+
+    // elevator/{id}/command -> id
+    pub fn parse_elevator_command(&self, topic: &str) -> Option<String> {
+        topic.strip_prefix(&self.prefix)?
+            .strip_prefix("elevator/")?
+            .strip_suffix("/command")
+            .filter(|id| !id.is_empty() && !id.contains('/'))
+            .map(str::to_string)
+    }
+
+    pub fn simulation_log(&self) -> String {
+        format!("{}simulation/log", self.prefix)
+    }
+
+    pub fn snapshot_request(&self) -> String {
+        format!("{}simulation/snapshot/request", self.prefix)
+    }
+
+    pub fn query(&self) -> String {
+        format!("{}simulation/query/", self.prefix)
+    }
+
+    pub fn query_filters(&self) -> Vec<String> {
+        vec![
+            format!("{}elevators", self.query()),
+            format!("{}stats", self.query()),
+            format!("{}person/+", self.query()),
+        ]
+    }
+
+    pub fn parse_query(&self, topic: &str) -> Option<Query> {
+        match topic.strip_prefix(&self.query())? {
+            "elevators" => Some(Query::Elevators),
+            "stats" => Some(Query::Stats),
+            other => other.strip_prefix("person/")
+                .filter(|id| !id.is_empty() && !id.contains('/'))
+                .map(|id| Query::Person(id.to_string())),
+        }
+    }
+
+    pub fn topic(&self, send: &Send) -> String {
+        match send {
+            ElevatorTopic { id, msg } => self.elevator(id, msg),
+            PersonTopic { id, msg } => self.person(id, msg),
+            SimulationTopic { msg } => self.simulation(msg),
+            // the requester chooses the full reply topic, no prefix
+            ReplyTopic { reply, .. } => reply.topic.clone(),
+        }
+    }
+}
+
+impl Send {
+    pub fn data(&self) -> serde_json::Value {
+        match self {
+            ElevatorTopic { msg, .. } => serde_json::to_value(msg).unwrap(),
+            PersonTopic { msg, .. } => serde_json::to_value(msg).unwrap(),
+            SimulationTopic { msg } => serde_json::to_value(msg).unwrap(),
+            ReplyTopic { reply, result } => {
+                let correlation = reply.correlation.as_ref().map(|c| String::from_utf8_lossy(c).to_string());
+                serde_json::json!({ "correlation": correlation, "result": result })
+            }
+        }
+    }
+
+    pub fn payload(&self) -> String {
+        self.data().to_string()
+    }
+}
+
+impl InboundError {
+    pub fn new(topic: &str, payload: &[u8], error: InboundErrorReason) -> Self {
+        InboundError {
+            topic: topic.to_string(),
+            payload: String::from_utf8_lossy(payload).to_string(),
+            error,
+        }
+    }
+
+    pub fn duplicate(topics: &Topics, id: &str, payload: &str) -> Self {
+        InboundError::new(&topics.person_introduce(), payload.as_bytes(), DuplicateId(id.to_string()))
+    }
+
+    pub fn no_elevator(topics: &Topics, payload: &str) -> Self {
+        InboundError::new(&topics.person_introduce(), payload.as_bytes(), InboundErrorReason::NoElevatorForTrip)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MqttConfig {
+    pub server_uri: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // CA certificate (PEM) used to verify the broker
+    pub ca_file: Option<String>,
+    // Client certificate and private key (PEM) for mutual TLS
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+    pub key_password: Option<String>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            server_uri: "mqtt://localhost:1883".to_string(),
+            username: None,
+            password: None,
+            ca_file: None,
+            cert_file: None,
+            key_file: None,
+            key_password: None,
+        }
+    }
+}
+
+impl MqttConfig {
+
+    pub fn is_tls(&self) -> bool {
+        ["ssl://", "mqtts://", "wss://"].iter().any(|scheme| self.server_uri.starts_with(scheme))
+    }
+
+    pub fn connect_options(&self) -> Result<ConnectOptions, String> {
+        let mut builder = ConnectOptionsBuilder::new_v5();
+        builder.connect_timeout(Duration::from_secs(10));
+
+        if self.password.is_some() && self.username.is_none() {
+            return Err("MQTT_PASSWORD gesetzt, aber kein MQTT_USERNAME".to_string());
+        }
+        if let Some(username) = &self.username {
+            builder.user_name(username);
+        }
+        if let Some(password) = &self.password {
+            builder.password(password);
+        }
+
+        let uses_certificates = self.ca_file.is_some() || self.cert_file.is_some() || self.key_file.is_some();
+        if !self.is_tls() {
+            if uses_certificates {
+                return Err(format!("Zertifikate angegeben, aber {} ist keine TLS-Adresse (ssl:// oder mqtts://)", self.server_uri));
+            }
+            return Ok(builder.finalize());
+        }
+
+        let mut ssl = SslOptionsBuilder::new();
+        ssl.enable_server_cert_auth(true).verify(true);
+        if let Some(ca_file) = &self.ca_file {
+            ssl.trust_store(MqttConfig::existing_file("MQTT_CA_FILE", ca_file)?).map_err(|e| e.to_string())?;
+        }
+        match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                ssl.key_store(MqttConfig::existing_file("MQTT_CERT_FILE", cert_file)?).map_err(|e| e.to_string())?;
+                ssl.private_key(MqttConfig::existing_file("MQTT_KEY_FILE", key_file)?).map_err(|e| e.to_string())?;
+            }
+            (None, None) => {}
+            _ => return Err("MQTT_CERT_FILE und MQTT_KEY_FILE müssen zusammen gesetzt werden".to_string()),
+        }
+        if let Some(key_password) = &self.key_password {
+            ssl.private_key_password(key_password);
+        }
+        builder.ssl_options(ssl.finalize());
+        Ok(builder.finalize())
+    }
+
+    fn existing_file<'a>(name: &str, path: &'a str) -> Result<&'a str, String> {
+        if Path::new(path).is_file() {
+            Ok(path)
+        } else {
+            Err(format!("{}: Datei {} nicht gefunden", name, path))
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct MqttConnector {
-    from: Receiver<Send>,
-    to: Sender<Receive>,
     client: AsyncClient,
+    topics: Topics,
+    codec: Arc<Codec>,
 }
 
-impl MqttConnector {
+impl Transport for MqttConnector {
 
-    pub async fn new(from: Receiver<Send>, to: Sender<Receive>) -> Self {
-        let client = AsyncClient::new("mqtt://localhost:1883").unwrap();
-        let _ = client.connect(ConnectOptions::new()).await;
-        MqttConnector {
-            from,
-            to,
-            client,
-        }
+    async fn publish(&self, send: Send) {
+        let _ = self.client.publish(MqttConnector::message(&self.topics, &self.codec, &send)).await;
     }
 
-    pub fn mqtt_subscriber(&self) -> JoinHandle<()> {
+    fn inbound(&self, to: Sender<Receive>) -> JoinHandle<()> {
         let mut client = self.client.clone();
-        let to = self.to.clone();
+        let topics = self.topics.clone();
+        let codec = self.codec.clone();
         tokio::spawn(async move {
-            client.subscribe("person/introduce", 1).await.unwrap();
-            client.subscribe("simulation/speed", 1).await.unwrap();
+            client.subscribe(topics.person_introduce(), 1).await.unwrap();
+            client.subscribe(topics.simulation_speed(), 1).await.unwrap();
+            client.subscribe(topics.snapshot_request(), 1).await.unwrap();
+            client.subscribe(topics.simulation_log(), 1).await.unwrap();
+            client.subscribe(topics.simulation_control(), 1).await.unwrap();
+            client.subscribe(topics.elevator_commands(), 1).await.unwrap();
+            for filter in topics.query_filters() {
+                client.subscribe(filter, 1).await.unwrap();
+            }
             let receiver = client.get_stream(100);
             loop {
                 if let Ok(Some(msg)) = receiver.recv().await {
-                    if let Some(receive) = MqttConnector::parse(&msg) {
-                        let _ = to.send(receive).await;
+                    match MqttConnector::parse(&topics, &msg) {
+                        Ok(receive) => {
+                            let _ = to.send(receive).await;
+                        }
+                        Err(error) => {
+                            let send = SimulationTopic {
+                                msg: SimulationMsg::Error(InboundError::new(msg.topic(), msg.payload(), error)),
+                            };
+                            let _ = client.publish(MqttConnector::message(&topics, &codec, &send)).await;
+                        }
                     }
                 }
             }
         })
     }
+}
 
-    pub fn mqtt_publisher(mut self) -> JoinHandle<()> {
-        let client = self.client.clone();
-        tokio::spawn(async move {
-            loop {
-                if let Some(send) = self.from.recv().await {
-                    match send {
-                        ElevatorTopic { id, msg } => {
-                            match msg {
-                                Position { .. } => {
-                                    let _ = client.publish(Message::new(format!("elevator/{}/position", id), serde_json::to_string(&msg).unwrap(), 1)).await;
-                                },
-                                Door { .. } => {
-                                    let _ = client.publish(Message::new(format!("elevator/{}/door", id), serde_json::to_string(&msg).unwrap(), 1)).await;
-                                },
-                                Moving { .. } => {
-                                    let _ = client.publish(Message::new(format!("elevator/{}/moving", id), serde_json::to_string(&msg).unwrap(), 1)).await;
-                                },
-                                Passengers { .. } => {
-                                    let _ = client.publish(Message::new(format!("elevator/{}/passengers", id), serde_json::to_string(&msg).unwrap(), 1)).await;
-                                },
-                                Missions { .. } => {
-                                    let _ = client.publish(Message::new(format!("elevator/{}/missions", id), serde_json::to_string(&msg).unwrap(), 1)).await;
-                                },
-                            }
-                        }
-                        PersonTopic { id, msg } => {
-                            match msg {
-                                StatusUpdate { .. } => {
-                                    let _ = client.publish(Message::new(format!("person/{}/status", id), serde_json::to_string(&msg).unwrap(), 1)).await;
-                                },
-                                Boarding { .. } => {
-                                    let _ = client.publish(Message::new(format!("person/{}/boarding", id), serde_json::to_string(&msg).unwrap(), 1)).await;
-                                }
-                                Request { .. } => {
-                                    let _ = client.publish(Message::new(format!("person/{}/request", id), serde_json::to_string(&msg).unwrap(), 1)).await;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+impl MqttConnector {
+
+    pub async fn new(topics: Topics, codec: Codec, config: &MqttConfig) -> Result<Self, String> {
+        let connect = config.connect_options()?;
+        let create = CreateOptionsBuilder::new()
+            .server_uri(&config.server_uri)
+            .mqtt_version(MQTT_VERSION_5)
+            .finalize();
+        let client = AsyncClient::new(create)
+            .map_err(|e| format!("Ungültige Broker-Adresse {}: {}", config.server_uri, e))?;
+        client.connect(connect).await
+            .map_err(|e| format!("Verbindung zu {} fehlgeschlagen: {}", config.server_uri, e))?;
+        Ok(MqttConnector {
+            client,
+            topics,
+            codec: Arc::new(codec),
+        })
+    }
+
+    fn message(topics: &Topics, codec: &Codec, send: &Send) -> Message {
+        let mut properties = Properties::new();
+        if let ReplyTopic { reply: Reply { correlation: Some(correlation), .. }, .. } = send {
+            let _ = properties.push_binary(PropertyCode::CorrelationData, correlation.clone());
+        }
+        MessageBuilder::new()
+            .topic(topics.topic(send))
+            .payload(codec.encode(send))
+            .qos(1)
+            .properties(properties)
+            .finalize()
+    }
+
+    fn parse(topics: &Topics, msg: &Message) -> Result<Receive, InboundErrorReason> {
+        let topic = msg.topic();
+        if topic == topics.person_introduce() {
+            return MqttConnector::parse_introduce(&protocol::open("person.introduce", msg.payload())?);
+        }
+        if topic == topics.simulation_speed() {
+            return MqttConnector::parse_speed(&protocol::open("simulation.speed", msg.payload())?);
+        }
+        if topic == topics.simulation_log() {
+            return MqttConnector::parse_log_filter(&protocol::open("simulation.log", msg.payload())?);
+        }
+        if topic == topics.simulation_control() {
+            return MqttConnector::parse_control(&protocol::open("simulation.control", msg.payload())?);
+        }
+        if let Some(elevator) = topics.parse_elevator_command(topic) {
+            return MqttConnector::parse_elevator_command(elevator, &protocol::open("elevator.command", msg.payload())?);
+        }
+        if topic == topics.snapshot_request() {
+            protocol::open("simulation.snapshot_request", msg.payload())?;
+            return Ok(Receive::SnapshotRequest);
+        }
+        if let Some(query) = topics.parse_query(topic) {
+            let payload = protocol::open("simulation.query", msg.payload())?;
+            return MqttConnector::parse_reply(msg, &payload).map(|reply| Receive::Query { query, reply });
+        }
+        Err(UnknownTopic)
+    }
+
+    pub(crate) fn parse_introduce(payload: &[u8]) -> Result<Receive, InboundErrorReason> {
+        let request: IntroduceRequest = serde_json::from_slice(payload)
+            .map_err(|e| MalformedPayload(e.to_string()))?;
+        let curr = MqttConnector::parse_floor(request.curr)?;
+        let dest = MqttConnector::parse_floor(request.dest)?;
+        let receive = Receive::Person { id: request.id, curr, dest, attributes: request.attributes, payload: Some(String::from_utf8_lossy(payload).to_string()) };
+        receive.validate()?;
+        Ok(receive)
+    }
+
+    fn parse_floor(value: serde_json::Value) -> Result<Floor, InboundErrorReason> {
+        serde_json::from_value(value.clone()).map_err(|_| match value {
+            serde_json::Value::String(floor) => UnknownFloor(floor),
+            other => UnknownFloor(other.to_string()),
         })
     }
 
-    fn parse(msg: &Message) -> Option<Receive> {
-        let payload = msg.payload();
-        serde_json::from_slice(payload).ok()
+    fn parse_log_filter(payload: &[u8]) -> Result<Receive, InboundErrorReason> {
+        let request: LogFilterRequest = serde_json::from_slice(payload)
+            .map_err(|e| MalformedPayload(e.to_string()))?;
+        let receive = Receive::LogFilter { filter: request.filter };
+        receive.validate()?;
+        Ok(receive)
+    }
+
+    pub(crate) fn parse_speed(payload: &[u8]) -> Result<Receive, InboundErrorReason> {
+        let request: SpeedRequest = serde_json::from_slice(payload)
+            .map_err(|e| MalformedPayload(e.to_string()))?;
+        let receive = Receive::Speed { speed: request.speed };
+        receive.validate()?;
+        Ok(receive)
+    }
+
+    pub(crate) fn parse_control(payload: &[u8]) -> Result<Receive, InboundErrorReason> {
+        serde_json::from_slice(payload)
+            .map(Receive::Control)
+            .map_err(|e| MalformedPayload(e.to_string()))
+    }
+
+    pub(crate) fn parse_elevator_command(elevator: String, payload: &[u8]) -> Result<Receive, InboundErrorReason> {
+        serde_json::from_slice(payload)
+            .map(|command| Receive::ElevatorCommand { elevator, command })
+            .map_err(|e| MalformedPayload(e.to_string()))
+    }
+
+    fn parse_reply(msg: &Message, payload: &[u8]) -> Result<Reply, InboundErrorReason> {
+        let request: QueryRequest = serde_json::from_slice(payload).unwrap_or_default();
+        let properties = msg.properties();
+        let topic = properties.get_string(PropertyCode::ResponseTopic).or(request.reply_to);
+        let correlation = properties.get_binary(PropertyCode::CorrelationData)
+            .or(request.correlation.map(String::into_bytes));
+        topic.map(|topic| Reply { topic, correlation }).ok_or(MissingReplyTopic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mqtt::InboundErrorReason::{InvalidLogFilter, UnexpectedType};
+    use crate::controller::Floor::*;
+    use crate::controller::RejectReason;
+    use crate::person::PersonStatus::Idle;
+
+    #[test]
+    fn test_topics_without_prefix_keep_default_layout() {
+        let topics = Topics::new("");
+
+        assert_eq!(topics.elevator("E1", &Position { floor: First }), "elevator/E1/position");
+        assert_eq!(topics.elevator("E1", &Height { height: 1.5, velocity: 0.8 }), "elevator/E1/height");
+        assert_eq!(topics.elevator("E1", &Passing { passing: First }), "elevator/E1/passing");
+        assert_eq!(topics.elevator("E1", &ElevatorMsg::Energy(EnergyReport::default())), "elevator/E1/energy");
+        assert_eq!(topics.elevator("E1", &ElevatorMsg::Load { load_kg: 90.0, percent: 50.0, overloaded: false }), "elevator/E1/load");
+        assert_eq!(topics.simulation(&SimulationMsg::Energy(BuildingEnergy::default())), "simulation/energy");
+        assert_eq!(topics.person("P1", &StatusUpdate { status: Idle, legs: Vec::new(), leg: None }), "person/P1/status");
+        assert_eq!(topics.person_introduce(), "person/introduce");
+        assert_eq!(topics.simulation_speed(), "simulation/speed");
+    }
+
+    #[test]
+    fn test_topics_with_prefix() {
+        let topics = Topics::new("building/a");
+
+        assert_eq!(topics.elevator("E1", &Door { status: DoorStatus::Open }), "building/a/elevator/E1/door");
+        assert_eq!(topics.person("P1", &Request { floor: Third }), "building/a/person/P1/request");
+        assert_eq!(topics.person_introduce(), "building/a/person/introduce");
+        assert_eq!(topics.simulation_speed(), "building/a/simulation/speed");
+        assert_eq!(topics.snapshot_request(), "building/a/simulation/snapshot/request");
+    }
+
+    #[test]
+    fn test_parse_snapshot_request_by_topic() {
+        let topics = Topics::new("building/a");
+        let msg = Message::new("building/a/simulation/snapshot/request", "", 1);
+
+        assert!(matches!(MqttConnector::parse(&topics, &msg), Ok(Receive::SnapshotRequest)));
+    }
+
+    #[test]
+    fn test_parse_query_topics() {
+        let topics = Topics::new("building/a");
+
+        assert_eq!(topics.parse_query("building/a/simulation/query/elevators"), Some(Query::Elevators));
+        assert_eq!(topics.parse_query("building/a/simulation/query/stats"), Some(Query::Stats));
+        assert_eq!(topics.parse_query("building/a/simulation/query/person/P1"), Some(Query::Person("P1".to_string())));
+        assert_eq!(topics.parse_query("building/a/simulation/query/person/"), None);
+        assert_eq!(topics.parse_query("building/a/simulation/query/other"), None);
+        assert_eq!(topics.parse_query("simulation/query/stats"), None);
+    }
+
+    #[test]
+    fn test_parse_query_with_reply_in_payload() {
+        let topics = Topics::new("");
+        let msg = Message::new("simulation/query/stats", r#"{"reply_to":"client/1/reply","correlation":"abc"}"#, 1);
+
+        match MqttConnector::parse(&topics, &msg) {
+            Ok(Receive::Query { query, reply }) => {
+                assert_eq!(query, Query::Stats);
+                assert_eq!(reply.topic, "client/1/reply");
+                assert_eq!(reply.correlation, Some(b"abc".to_vec()));
+            }
+            _ => panic!("Expected Receive::Query"),
+        }
+    }
+
+    #[test]
+    fn test_parse_query_with_v5_properties() {
+        let topics = Topics::new("");
+        let mut properties = Properties::new();
+        properties.push_string(PropertyCode::ResponseTopic, "client/2/reply").unwrap();
+        properties.push_binary(PropertyCode::CorrelationData, b"42".to_vec()).unwrap();
+        let msg = MessageBuilder::new()
+            .topic("simulation/query/elevators")
+            .payload("")
+            .properties(properties)
+            .finalize();
+
+        match MqttConnector::parse(&topics, &msg) {
+            Ok(Receive::Query { query, reply }) => {
+                assert_eq!(query, Query::Elevators);
+                assert_eq!(reply.topic, "client/2/reply");
+                assert_eq!(reply.correlation, Some(b"42".to_vec()));
+            }
+            _ => panic!("Expected Receive::Query"),
+        }
+    }
+
+    #[test]
+    fn test_parse_query_without_reply_topic_is_rejected() {
+        let topics = Topics::new("");
+        let msg = Message::new("simulation/query/stats", "{}", 1);
+
+        assert_eq!(MqttConnector::parse(&topics, &msg).err(), Some(MissingReplyTopic));
+    }
+
+    #[test]
+    fn test_reply_message() {
+        let topics = Topics::new("building/a");
+        let send = ReplyTopic {
+            reply: Reply { topic: "client/1/reply".to_string(), correlation: Some(b"abc".to_vec()) },
+            result: serde_json::json!({ "trips": 3 }),
+        };
+
+        let msg = MqttConnector::message(&topics, &Codec::new(Default::default()), &send);
+        assert_eq!(msg.topic(), "client/1/reply");
+        assert_eq!(msg.properties().get_binary(PropertyCode::CorrelationData), Some(b"abc".to_vec()));
+        let payload: serde_json::Value = serde_json::from_slice(msg.payload()).unwrap();
+        assert_eq!(payload["correlation"], "abc");
+        assert_eq!(payload["result"]["trips"], 3);
+    }
+
+    #[test]
+    fn test_parse_person_introduce() {
+        let topics = Topics::new("");
+        let msg = Message::new("person/introduce", r#"{"id":"P1","curr":"Ground","dest":"Third"}"#, 1);
+
+        match MqttConnector::parse(&topics, &msg) {
+            Ok(Receive::Person { id, curr, dest, .. }) => {
+                assert_eq!(id, "P1");
+                assert_eq!(curr, Ground);
+                assert_eq!(dest, Third);
+            }
+            _ => panic!("Expected Receive::Person"),
+        }
+    }
+
+    fn parse_error(topic: &str, payload: &str) -> Option<InboundErrorReason> {
+        MqttConnector::parse(&Topics::new(""), &Message::new(topic, payload, 1)).err()
+    }
+
+    #[test]
+    fn test_parse_introduce_unknown_floor() {
+        assert_eq!(parse_error("person/introduce", r#"{"id":"P1","curr":"Fourth","dest":"First"}"#), Some(UnknownFloor("Fourth".to_string())));
+        assert_eq!(parse_error("person/introduce", r#"{"id":"P1","curr":"First","dest":7}"#), Some(UnknownFloor("7".to_string())));
+    }
+
+    #[test]
+    fn test_parse_introduce_same_origin_and_destination() {
+        assert_eq!(parse_error("person/introduce", r#"{"id":"P1","curr":"First","dest":"First"}"#), Some(SameOriginAndDestination));
+    }
+
+    #[test]
+    fn test_parse_introduce_malformed() {
+        assert!(matches!(parse_error("person/introduce", r#"{"id":"P1"}"#), Some(MalformedPayload(_))));
+        assert!(matches!(parse_error("person/introduce", "not json"), Some(MalformedPayload(_))));
+    }
+
+    #[test]
+    fn test_parse_speed_range() {
+        assert_eq!(parse_error("simulation/speed", r#"{"speed":0}"#), Some(SpeedOutOfRange(0)));
+        assert_eq!(parse_error("simulation/speed", r#"{"speed":10001}"#), Some(SpeedOutOfRange(10001)));
+        assert!(matches!(parse_error("simulation/speed", r#"{"speed":-5}"#), Some(MalformedPayload(_))));
+        assert!(matches!(MqttConnector::parse(&Topics::new(""), &Message::new("simulation/speed", r#"{"speed":50}"#, 1)), Ok(Receive::Speed { speed: 50 })));
+    }
+
+    #[test]
+    fn test_parse_log_filter() {
+        let msg = Message::new("simulation/log", r#"{"filter":"debug,rumqttd=warn"}"#, 1);
+        assert!(matches!(MqttConnector::parse(&Topics::new(""), &msg), Ok(Receive::LogFilter { filter }) if filter == "debug,rumqttd=warn"));
+        assert!(matches!(parse_error("simulation/log", r#"{"filter":"controller=loud"}"#), Some(InvalidLogFilter(_))));
+        assert!(matches!(parse_error("simulation/log", r#"{"level":"debug"}"#), Some(MalformedPayload(_))));
+    }
+
+    #[test]
+    fn test_parse_control() {
+        let topics = Topics::new("");
+        let parse = |payload: &str| MqttConnector::parse(&topics, &Message::new("simulation/control", payload, 1));
+
+        assert!(matches!(parse(r#"{"command":"pause"}"#), Ok(Receive::Control(Control::Pause))));
+        assert!(matches!(parse(r#"{"command":"resume"}"#), Ok(Receive::Control(Control::Resume))));
+        assert!(matches!(parse(r#"{"command":"step"}"#), Ok(Receive::Control(Control::Step { ms: None }))));
+        assert!(matches!(parse(r#"{"command":"step","ms":500}"#), Ok(Receive::Control(Control::Step { ms: Some(500) }))));
+        assert!(matches!(parse_error("simulation/control", r#"{"command":"rewind"}"#), Some(MalformedPayload(_))));
+    }
+
+    #[test]
+    fn test_parse_elevator_command() {
+        let topics = Topics::new("building/a");
+        let parse = |topic: &str, payload: &str| MqttConnector::parse(&topics, &Message::new(topic, payload, 1));
+
+        assert_eq!(topics.elevator_commands(), "building/a/elevator/+/command");
+        assert_eq!(topics.parse_elevator_command("building/a/elevator/E1/command"), Some("E1".to_string()));
+        assert_eq!(topics.parse_elevator_command("elevator/E1/command"), None);
+        assert_eq!(topics.parse_elevator_command("building/a/elevator//command"), None);
+        assert!(matches!(parse("building/a/elevator/E1/command", r#"{"command":"cancel_mission"}"#),
+            Ok(Receive::ElevatorCommand { elevator, command: ElevatorCommand::CancelMission }) if elevator == "E1"));
+        assert!(matches!(parse("building/a/elevator/E1/command", r#"{"command":"retarget_mission","floor":"Ground"}"#),
+            Ok(Receive::ElevatorCommand { command: ElevatorCommand::RetargetMission { floor: Ground }, .. })));
+        assert!(matches!(parse("building/a/elevator/E1/command", r#"{"command":"emergency_stop"}"#),
+            Ok(Receive::ElevatorCommand { command: ElevatorCommand::EmergencyStop, .. })));
+        assert!(matches!(parse("building/a/elevator/E1/command", r#"{"command":"retarget_mission"}"#), Err(MalformedPayload(_))));
+    }
+
+    #[test]
+    fn test_ack_payload() {
+        let accepted = Ack { command: ElevatorCommand::RetargetMission { floor: First }, accepted: true, stop: Some(First), reason: None };
+        let rejected = Ack { command: ElevatorCommand::CancelMission, accepted: false, stop: None, reason: Some(Rejection::NotMoving) };
+
+        assert_eq!(serde_json::to_value(&accepted).unwrap(), serde_json::json!({"command": "retarget_mission", "floor": "First", "accepted": true, "stop": "First"}));
+        assert_eq!(serde_json::to_value(&rejected).unwrap(), serde_json::json!({"command": "cancel_mission", "accepted": false, "reason": "NotMoving"}));
+        assert_eq!(Topics::default().elevator("E1", &rejected), "elevator/E1/ack");
+    }
+
+    #[test]
+    fn test_moving_payload_marks_parking() {
+        let trip = Moving { from: Ground, to: Third, parking: false };
+        let parking = Moving { from: Third, to: Ground, parking: true };
+
+        assert_eq!(serde_json::to_value(&trip).unwrap(), serde_json::json!({"from": "Ground", "to": "Third"}));
+        assert_eq!(serde_json::to_value(&parking).unwrap(), serde_json::json!({"from": "Third", "to": "Ground", "parking": true}));
+    }
+
+    #[test]
+    fn test_boarding_payload_carries_reason() {
+        let accepted = Boarding { status: BoardingStatus::Accepted };
+        let rejected = Boarding { status: BoardingStatus::Rejected(RejectReason::Full) };
+
+        assert_eq!(serde_json::to_value(&accepted).unwrap(), serde_json::json!({"status": "Accepted"}));
+        assert_eq!(serde_json::to_value(&rejected).unwrap(), serde_json::json!({"status": "Rejected", "reason": "Full"}));
+        assert!(matches!(serde_json::from_value(serde_json::json!({"status": "Rejected", "reason": "OutOfService"})).unwrap(),
+            Boarding { status: BoardingStatus::Rejected(RejectReason::OutOfService) }));
+    }
+
+    #[test]
+    fn test_parse_person_introduce_with_attributes() {
+        let topics = Topics::new("");
+        let msg = Message::new("person/introduce", r#"{"id":"P1","curr":"Ground","dest":"Third","attributes":{"wheelchair":true,"load":"Stretcher","mass_kg":90}}"#, 1);
+
+        match MqttConnector::parse(&topics, &msg) {
+            Ok(Receive::Person { attributes, .. }) => {
+                assert_eq!(attributes, Attributes { wheelchair: true, priority: false, load: Some(crate::person::Load::Stretcher), mass_kg: Some(90.0) });
+            }
+            _ => panic!("Expected Receive::Person"),
+        }
+        let msg = Message::new("person/introduce", r#"{"id":"P1","curr":"Ground","dest":"Third","attributes":{"vip":true}}"#, 1);
+        assert!(matches!(MqttConnector::parse(&topics, &msg), Err(MalformedPayload(_))));
+        let msg = Message::new("person/introduce", r#"{"id":"P1","curr":"Ground","dest":"Third","attributes":{"mass_kg":-5}}"#, 1);
+        assert!(matches!(MqttConnector::parse(&topics, &msg), Err(InboundErrorReason::InvalidAttributes(_))));
+    }
+
+    #[test]
+    fn test_parse_enveloped_introduce() {
+        let topics = Topics::new("");
+        let payload = r#"{"version":1,"type":"person.introduce","data":{"id":"P1","curr":"Ground","dest":"First"}}"#;
+        let msg = Message::new("person/introduce", payload, 1);
+
+        assert!(matches!(MqttConnector::parse(&topics, &msg), Ok(Receive::Person { .. })));
+        assert_eq!(parse_error("person/introduce", r#"{"version":1,"type":"simulation.speed","data":{"speed":5}}"#), Some(UnexpectedType("simulation.speed".to_string())));
+    }
+
+    #[test]
+    fn test_parse_enveloped_query_reply() {
+        let topics = Topics::new("");
+        let payload = r#"{"version":1,"type":"simulation.query","data":{"reply_to":"client/reply"}}"#;
+        let msg = Message::new("simulation/query/stats", payload, 1);
+
+        match MqttConnector::parse(&topics, &msg) {
+            Ok(Receive::Query { reply, .. }) => assert_eq!(reply.topic, "client/reply"),
+            _ => panic!("Expected Receive::Query"),
+        }
+    }
+
+    #[test]
+    fn test_v1_message() {
+        let topics = Topics::new("");
+        let send = PersonTopic { id: "P1".to_string(), msg: Request { floor: Second } };
+
+        let msg = MqttConnector::message(&topics, &Codec::new(crate::protocol::Protocol::V1), &send);
+        let envelope: serde_json::Value = serde_json::from_slice(msg.payload()).unwrap();
+        assert_eq!(msg.topic(), "person/P1/request");
+        assert_eq!(envelope["type"], "person.request");
+        assert_eq!(envelope["id"], "P1");
+        assert_eq!(envelope["data"]["floor"], "Second");
+    }
+
+    #[test]
+    fn test_config_plain_connection() {
+        assert!(MqttConfig::default().connect_options().is_ok());
+        assert!(!MqttConfig::default().is_tls());
+    }
+
+    #[test]
+    fn test_config_password_requires_username() {
+        let config = MqttConfig { password: Some("geheim".to_string()), ..Default::default() };
+        assert!(config.connect_options().is_err());
+
+        let config = MqttConfig { username: Some("sim".to_string()), ..config };
+        assert!(config.connect_options().is_ok());
+    }
+
+    #[test]
+    fn test_config_certificates_require_tls_uri() {
+        let config = MqttConfig { ca_file: Some("Cargo.toml".to_string()), ..Default::default() };
+        assert!(config.connect_options().is_err());
+
+        let config = MqttConfig { server_uri: "mqtts://localhost:8883".to_string(), ..config };
+        assert!(config.is_tls());
+        assert!(config.connect_options().is_ok());
+    }
+
+    #[test]
+    fn test_config_missing_certificate_file() {
+        let config = MqttConfig {
+            server_uri: "ssl://localhost:8883".to_string(),
+            ca_file: Some("does/not/exist.pem".to_string()),
+            ..Default::default()
+        };
+        let error = config.connect_options().unwrap_err();
+        assert!(error.contains("MQTT_CA_FILE"));
+    }
+
+    #[test]
+    fn test_config_cert_and_key_must_be_paired() {
+        let config = MqttConfig {
+            server_uri: "ssl://localhost:8883".to_string(),
+            cert_file: Some("Cargo.toml".to_string()),
+            ..Default::default()
+        };
+        assert!(config.connect_options().is_err());
+    }
+
+    // Needs a TLS mosquitto, see mosquitto/certs/generate.sh and mosquitto-tls.conf
+    #[tokio::test]
+    #[ignore]
+    async fn test_tls_handshake_against_local_broker() {
+        let certs = std::env::var("MQTT_TEST_CERTS").unwrap_or("mosquitto/certs".to_string());
+        let config = MqttConfig {
+            server_uri: "mqtts://localhost:8883".to_string(),
+            username: Some("simulation".to_string()),
+            password: Some("simulation".to_string()),
+            ca_file: Some(format!("{}/ca.crt", certs)),
+            cert_file: Some(format!("{}/client.crt", certs)),
+            key_file: Some(format!("{}/client.key", certs)),
+            key_password: None,
+        };
+        let result = MqttConnector::new(Topics::new(""), Codec::new(Default::default()), &config).await;
+        assert!(result.is_ok(), "{:?}", result.err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_error_is_reported() {
+        let config = MqttConfig { server_uri: "mqtt://localhost:1".to_string(), ..Default::default() };
+        let result = MqttConnector::new(Topics::new(""), Codec::new(Default::default()), &config).await;
+        assert!(result.err().unwrap().contains("mqtt://localhost:1"));
+    }
+
+    #[test]
+    fn test_parse_unknown_topic() {
+        assert_eq!(parse_error("something/else", "{}"), Some(UnknownTopic));
+    }
+
+    #[test]
+    fn test_inbound_error_payload() {
+        let topics = Topics::new("building/a");
+        let send = SimulationTopic {
+            msg: SimulationMsg::Error(InboundError::new("building/a/simulation/speed", br#"{"speed":0}"#, SpeedOutOfRange(0))),
+        };
+
+        assert_eq!(topics.topic(&send), "building/a/simulation/errors");
+        let payload: serde_json::Value = serde_json::from_str(&send.payload()).unwrap();
+        assert_eq!(payload["topic"], "building/a/simulation/speed");
+        assert_eq!(payload["payload"], r#"{"speed":0}"#);
+        assert_eq!(payload["error"]["reason"], "SpeedOutOfRange");
+        assert_eq!(payload["error"]["detail"], 0);
+    }
+
+    #[test]
+    fn test_duplicate_error_echoes_introduction() {
+        let topics = Topics::new("");
+        let payload = r#"{ "id": "P1", "curr": "Ground", "dest": "First" }"#;
+        let Ok(Receive::Person { id, payload: Some(received), .. }) = MqttConnector::parse_introduce(payload.as_bytes()) else {
+            panic!("Expected Receive::Person");
+        };
+        let error = InboundError::duplicate(&topics, &id, &received);
+
+        assert_eq!(error.topic, "person/introduce");
+        assert_eq!(error.payload, payload);
+        assert_eq!(error.error, DuplicateId("P1".to_string()));
+    }
+
+    #[test]
+    fn test_topics_prefix_is_normalized() {
+        assert_eq!(Topics::new("building/a/"), Topics::new("building/a"));
+        assert_eq!(Topics::new("/building/a/"), Topics::new("building/a"));
+        assert_eq!(Topics::new("/"), Topics::new(""));
+    }
+
+    #[test]
+    fn test_topics_for_send() {
+        let topics = Topics::new("building/b");
+        let send = ElevatorTopic { id: "E2".to_string(), msg: Missions { missions: vec![First, Second] } };
+
+        assert_eq!(topics.topic(&send), "building/b/elevator/E2/missions");
+        assert_eq!(send.payload(), r#"{"missions":["First","Second"]}"#);
     }
 }
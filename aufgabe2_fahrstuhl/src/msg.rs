@@ -1,14 +1,22 @@
 use crate::controller::{BoardingStatus, Floor};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use crate::mqtt::{ElevatorCommand, Query, Reply};
+use crate::person::Attributes;
 
 #[derive(Clone, PartialEq)]
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum PersonToControllerMsg {
-    PersonRequestElevator(Floor),                           // Floor
+    PersonRequestElevator(String, Floor, Floor, Attributes),   // Person ID, Floor, Destination, Attributes
     PersonEnteringElevator(String, String),                 // Person ID, Elevator ID
     PersonEnteredElevator(String, String),                  // Person ID, Elevator ID
     PersonLeavingElevator(String, String),                  // Person ID, Elevator ID
     PersonLeftElevator(String, String),                     // Person ID, Elevator ID
-    PersonChoosingFloor(String, String, Floor)              // Person ID, Elevator ID, Floor
+    PersonChoosingFloor(String, String, Floor),             // Person ID, Elevator ID, Floor
+    PersonTransferring(String, Floor),                      // Person ID, Transfer floor (sent before leaving the car)
+    PersonTakingStairs(String),                             // Person ID, withdraws the call and walks
+    PersonTookStairs(String)                                // Person ID, arrived on foot
 }
 
 #[derive(Clone, PartialEq)]
@@ -22,19 +30,64 @@ pub enum ControllerToPersonsMsg {
 #[derive(Debug)]
 pub enum ControllerToElevatorsMsg {
     ElevatorMission(String, Floor),                         // Elevator ID, Target Floor
+    CancelMission(String),                                  // Elevator ID, stop at the next floor the car can brake for
+    RetargetMission(String, Floor),                         // Elevator ID, new Target Floor
+    EmergencyStop(String),                                  // Elevator ID, brake at once, also between floors
     OpenDoors(String),                                      // Elevator ID
-    CloseDoors(String)                                      // Elevator ID
+    CloseDoors(String),                                     // Elevator ID
+    // Sent by the controller itself, acknowledged to it but not published
+    StopOnTheWay(String, Floor),                            // Elevator ID, queued floor the car passes
+    EndParking(String)                                      // Elevator ID, a call ends the parking trip at the next floor
+}
+
+impl ControllerToElevatorsMsg {
+    pub fn elevator(&self) -> &str {
+        match self {
+            ControllerToElevatorsMsg::ElevatorMission(elevator, _)
+            | ControllerToElevatorsMsg::CancelMission(elevator)
+            | ControllerToElevatorsMsg::RetargetMission(elevator, _)
+            | ControllerToElevatorsMsg::EmergencyStop(elevator)
+            | ControllerToElevatorsMsg::OpenDoors(elevator)
+            | ControllerToElevatorsMsg::CloseDoors(elevator)
+            | ControllerToElevatorsMsg::StopOnTheWay(elevator, _)
+            | ControllerToElevatorsMsg::EndParking(elevator) => elevator,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq)]
 #[derive(Debug)]
 pub enum ElevatorToControllerMsg {
     ElevatorMoving(String, Floor, Floor),                   // Elevator ID, From Floor, To Floor
+    ElevatorPassing(String, Floor),                         // Elevator ID, Floor passed without stopping
     ElevatorArrived(String, Floor),                         // Elevator ID, Floor
+    ElevatorStopped(String, f64),                           // Elevator ID, height after an emergency stop
     DoorsOpening(String),                                   // Elevator ID
     DoorsClosing(String),                                   // Elevator ID
     DoorsOpened(String),                                    // Elevator ID
-    DoorsClosed(String)                                     // Elevator ID
+    DoorsClosed(String),                                    // Elevator ID
+    CommandAck(String, ElevatorCommand, Result<Option<Floor>, Rejection>)   // Elevator ID, Command, Floor the car stops at (None: between floors)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Rejection {
+    // Cancel and retarget only apply to a travelling car
+    NotMoving,
+    // Target lies behind the car
+    WrongDirection,
+    // Too close to brake for it
+    CannotBrake,
+    EmergencyStopped,
+    // Outside the car's zone
+    FloorNotServed,
+}
+
+#[derive(Debug)]
+pub enum SimulationToControllerMsg {
+    PublishSnapshot,
+    Query(Query, Reply),
+    Command(String, ElevatorCommand),                        // Elevator ID, Command
+    Ask(Query, oneshot::Sender<serde_json::Value>)           // Query answered in-process (HTTP)
 }
 
 #[cfg(test)]
@@ -42,6 +95,7 @@ mod tests {
     use super::*;
     use crate::controller::Floor::*;
     use crate::controller::BoardingStatus::*;
+    use crate::controller::RejectReason;
 
     #[test]
     fn test_floor_distance_calculation() {
@@ -76,7 +130,7 @@ mod tests {
 
     #[test]
     fn test_person_to_controller_msg_clone() {
-        let msg = PersonToControllerMsg::PersonRequestElevator(Ground);
+        let msg = PersonToControllerMsg::PersonRequestElevator("P1".to_string(), Ground, Third, Attributes::default());
         let cloned = msg.clone();
         assert_eq!(msg, cloned);
     }
@@ -109,10 +163,11 @@ mod tests {
     #[test]
     fn test_boarding_status_clone() {
         let accepted = Accepted;
-        let rejected = Rejected;
+        let rejected = Rejected(RejectReason::Full);
         
         assert_eq!(accepted.clone(), Accepted);
-        assert_eq!(rejected.clone(), Rejected);
+        assert_eq!(rejected.clone(), Rejected(RejectReason::Full));
         assert_ne!(accepted, rejected);
+        assert_ne!(rejected, Rejected(RejectReason::OutOfService));
     }
 }
\ No newline at end of file
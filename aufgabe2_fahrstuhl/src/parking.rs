@@ -0,0 +1,169 @@
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::controller::Floor;
+
+const FLOORS: [Floor; 4] = [Floor::Ground, Floor::First, Floor::Second, Floor::Third];
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParkingStrategy {
+    // Back to the ground floor (or the lowest floor of the zone)
+    #[default]
+    Lobby,
+    // Idle cars evenly across their zone, cars with the same zone share it
+    Spread,
+    // Floors with the most calls so far
+    Demand,
+}
+
+// Can be set in the config file:
+//   [parking]
+//   strategy = "spread"
+//   idle_timeout_ms = 20000
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParkingPolicy {
+    pub strategy: ParkingStrategy,
+    // Simulated time a car has to stand still before it parks
+    pub idle_timeout_ms: u64,
+}
+
+impl Default for ParkingPolicy {
+    fn default() -> Self {
+        ParkingPolicy {
+            strategy: ParkingStrategy::default(),
+            idle_timeout_ms: 30000,
+        }
+    }
+}
+
+// An idle car: id, current floor and served floors
+pub struct IdleCar<'a> {
+    pub id: &'a str,
+    pub floor: Floor,
+    pub floors: &'a [Floor],
+}
+
+impl ParkingPolicy {
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.idle_timeout_ms == 0 {
+            return Err("idle_timeout_ms muss größer als 0 sein".to_string());
+        }
+        Ok(())
+    }
+
+    // Parking floor per idle car, `demand` counts the calls per floor
+    pub fn targets(&self, cars: &[IdleCar], demand: &BTreeMap<Floor, u64>) -> BTreeMap<String, Floor> {
+        let mut cars: Vec<&IdleCar> = cars.iter().collect();
+        cars.sort_by_key(|car| car.id);
+        match self.strategy {
+            ParkingStrategy::Lobby => cars.iter()
+                .map(|car| (car.id.to_string(), nearest(car, Floor::Ground)))
+                .collect(),
+            ParkingStrategy::Spread => {
+                let mut zones: BTreeMap<Vec<Floor>, Vec<&IdleCar>> = BTreeMap::new();
+                for car in cars {
+                    let mut zone = car.floors.to_vec();
+                    zone.sort();
+                    zone.dedup();
+                    zones.entry(zone).or_default().push(car);
+                }
+                zones.iter()
+                    .flat_map(|(zone, cars)| cars.iter().enumerate().map(|(i, car)| {
+                        // Middle of the i-th of `cars.len()` equal sections of the zone
+                        let position = ((i as f64 + 0.5) * zone.len() as f64 / cars.len() as f64 - 0.5).round() as usize;
+                        (car.id.to_string(), zone.get(position.min(zone.len().saturating_sub(1))).copied().unwrap_or(car.floor))
+                    }))
+                    .collect()
+            }
+            ParkingStrategy::Demand => {
+                let mut floors: Vec<Floor> = FLOORS.iter().copied().filter(|floor| demand.get(floor).is_some_and(|&calls| calls > 0)).collect();
+                floors.sort_by_key(|floor| std::cmp::Reverse(demand[floor]));
+                let mut targets = BTreeMap::new();
+                // The busiest floor gets the closest car serving it
+                for floor in floors {
+                    if let Some(car) = cars.iter()
+                        .filter(|car| !targets.contains_key(car.id) && car.floors.contains(&floor))
+                        .min_by_key(|car| (car.floor as i8 - floor as i8).abs()) {
+                        targets.insert(car.id.to_string(), floor);
+                    }
+                }
+                // Without (enough) calls the rest stays in the lobby
+                for car in cars {
+                    targets.entry(car.id.to_string()).or_insert_with(|| nearest(car, Floor::Ground));
+                }
+                targets
+            }
+        }
+    }
+}
+
+// Served floor closest to `floor`, the lower one on a tie
+fn nearest(car: &IdleCar, floor: Floor) -> Floor {
+    car.floors.iter().copied()
+        .min_by_key(|&served| ((served as i8 - floor as i8).abs(), served))
+        .unwrap_or(car.floor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Floor::*;
+
+    const ALL: [Floor; 4] = [Ground, First, Second, Third];
+
+    fn policy(strategy: ParkingStrategy) -> ParkingPolicy {
+        ParkingPolicy { strategy, ..Default::default() }
+    }
+
+    #[test]
+    fn test_lobby_within_zone() {
+        let high = [Second, Third];
+        let cars = [IdleCar { id: "A", floor: Third, floors: &ALL }, IdleCar { id: "B", floor: Third, floors: &high }];
+
+        let targets = policy(ParkingStrategy::Lobby).targets(&cars, &BTreeMap::new());
+        assert_eq!(targets["A"], Ground);
+        assert_eq!(targets["B"], Second);
+    }
+
+    #[test]
+    fn test_spread_evenly() {
+        let cars = [IdleCar { id: "C", floor: Ground, floors: &ALL }, IdleCar { id: "A", floor: Ground, floors: &ALL },
+                    IdleCar { id: "B", floor: Ground, floors: &ALL }];
+
+        let targets = policy(ParkingStrategy::Spread).targets(&cars, &BTreeMap::new());
+        assert_eq!(targets, BTreeMap::from([("A".to_string(), Ground), ("B".to_string(), Second), ("C".to_string(), Third)]));
+
+        let targets = policy(ParkingStrategy::Spread).targets(&cars[..2], &BTreeMap::new());
+        assert_eq!(targets, BTreeMap::from([("A".to_string(), First), ("C".to_string(), Third)]));
+    }
+
+    #[test]
+    fn test_spread_within_each_zone() {
+        let low = [Ground, First];
+        let high = [Third, Second];
+        let cars = [IdleCar { id: "A", floor: Ground, floors: &low }, IdleCar { id: "B", floor: Ground, floors: &low },
+                    IdleCar { id: "C", floor: Third, floors: &high }, IdleCar { id: "D", floor: Third, floors: &high }];
+
+        let targets = policy(ParkingStrategy::Spread).targets(&cars, &BTreeMap::new());
+        assert_eq!(targets, BTreeMap::from([("A".to_string(), Ground), ("B".to_string(), First),
+                                            ("C".to_string(), Second), ("D".to_string(), Third)]));
+    }
+
+    #[test]
+    fn test_demand_sends_closest_car_to_busiest_floor() {
+        let cars = [IdleCar { id: "A", floor: Ground, floors: &ALL }, IdleCar { id: "B", floor: Third, floors: &ALL },
+                    IdleCar { id: "C", floor: First, floors: &ALL }];
+        let demand = BTreeMap::from([(Second, 5), (Ground, 2), (Third, 0)]);
+
+        let targets = policy(ParkingStrategy::Demand).targets(&cars, &demand);
+        assert_eq!(targets, BTreeMap::from([("A".to_string(), Ground), ("B".to_string(), Second), ("C".to_string(), Ground)]));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ParkingPolicy::default().validate().is_ok());
+        assert!(ParkingPolicy { idle_timeout_ms: 0, ..Default::default() }.validate().is_err());
+    }
+}
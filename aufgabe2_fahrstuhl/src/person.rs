@@ -1,6 +1,5 @@
 use std::fmt::{Debug, Display, Formatter};
-use log::{debug, error, info, trace};
-use rand::rng;
+use tracing::{debug, error, field, info, info_span, trace, Instrument, Span};
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 use task::JoinHandle;
@@ -8,20 +7,22 @@ use tokio::sync::broadcast::Receiver;
 use tokio::sync::mpsc::Sender;
 use tokio::{spawn, task};
 use BoardingStatus::{Accepted, Rejected};
+use crate::controller::RejectReason;
 use ControllerToPersonsMsg::{UpdateBoardingStatus};
 use PersonMsg::StatusUpdate;
-use PersonStatus::{Done, Entering, Idle, InElevator};
+use PersonStatus::{Done, Entering, Idle, InElevator, Stairs};
 use PersonToControllerMsg::{PersonChoosingFloor, PersonEnteredElevator, PersonEnteringElevator};
-use crate::controller::{BoardingStatus, Floor};
+use crate::controller::{BoardingStatus, Floor, CAPACITY};
 use crate::controller::Floor::{First, Ground, Second, Third};
 use crate::mqtt::PersonMsg;
 use crate::mqtt::PersonMsg::{Boarding, Request};
 use crate::mqtt::Send::{PersonTopic};
 use crate::msg::{ControllerToPersonsMsg, PersonToControllerMsg};
 use crate::msg::ControllerToPersonsMsg::{ElevatorHalt};
-use crate::msg::PersonToControllerMsg::{PersonLeavingElevator, PersonLeftElevator, PersonRequestElevator};
+use crate::msg::PersonToControllerMsg::{PersonLeavingElevator, PersonLeftElevator, PersonRequestElevator, PersonTakingStairs, PersonTookStairs, PersonTransferring};
+use crate::routing::Leg;
 use crate::person::PersonStatus::Leaving;
-use crate::utils::{delay, random_delay_ms};
+use crate::utils::{random_delay_ms, random_normal, sim_time_ms, with_rng};
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum PersonStatus {
@@ -30,15 +31,79 @@ pub enum PersonStatus {
     Choosing,
     InElevator,
     Leaving,
+    // Gave up on the elevator and walks
+    Stairs,
     Done,
 }
 
+// Sent with person/introduce, e.g. {"wheelchair": true} or {"load": "Stretcher", "mass_kg": 140}
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Attributes {
+    // Takes the space of two persons and needs the doors open longer
+    pub wheelchair: bool,
+    // VIP, served before the other calls
+    pub priority: bool,
+    pub load: Option<Load>,
+    // Drawn at random when not given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mass_kg: Option<f64>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Load {
+    // Urgent, rides alone and needs the doors open longer
+    Stretcher,
+    // Rides alone
+    Freight,
+}
+
+impl Attributes {
+
+    pub fn is_default(&self) -> bool {
+        *self == Attributes::default()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self.mass_kg {
+            Some(mass) if !(mass.is_finite() && mass > 0.0) => Err(format!("mass_kg {} ist ungültig", mass)),
+            _ => Ok(()),
+        }
+    }
+
+    // Places taken in a car
+    pub fn space(&self) -> usize {
+        match (self.load, self.wheelchair) {
+            (Some(_), _) => CAPACITY,
+            (None, true) => 2,
+            (None, false) => 1,
+        }
+    }
+
+    pub fn urgent(&self) -> bool {
+        self.priority || self.load == Some(Load::Stretcher)
+    }
+
+    // Enters and leaves slowly, the doors wait for it
+    pub fn slow(&self) -> bool {
+        self.wheelchair || self.load == Some(Load::Stretcher)
+    }
+
+    // Loads and wheelchairs only go by car
+    pub fn can_take_stairs(&self) -> bool {
+        !self.wheelchair && self.load.is_none()
+    }
+}
+
 pub struct Person {
     pub id: String,
     from_controller: Receiver<ControllerToPersonsMsg>,
     to_controller: Sender<PersonToControllerMsg>,
     to_mqtt: Sender<crate::mqtt::Send>,
-    state: PersonState
+    state: PersonState,
+    // One leg per car, more than one when changing cars at a transfer floor
+    legs: Vec<Leg>,
+    leg: usize,
 }
 
 #[derive(Debug)]
@@ -47,11 +112,12 @@ struct PersonState {
     current_floor: Floor,
     destination_floor: Floor,
     elevator: Option<String>,
+    attributes: Attributes,
 }
 
 impl Display for PersonState {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{{ status: {:?}, floor: {:?}, dest: {:?}, elevator: {:?} }}", self.status, self.current_floor, self.destination_floor, self.elevator)
+        write!(f, "{{ status: {:?}, floor: {:?}, dest: {:?}, elevator: {:?}, attributes: {:?} }}", self.status, self.current_floor, self.destination_floor, self.elevator, self.attributes)
     }
 }
 
@@ -82,8 +148,11 @@ impl Person {
                 status: Idle,
                 current_floor,
                 destination_floor,
-                elevator: None
-            }
+                elevator: None,
+                attributes: Attributes { mass_kg: Some(Self::random_mass_kg()), ..Default::default() },
+            },
+            legs: vec![Leg { from: current_floor, to: destination_floor }],
+            leg: 0,
         }
     }
 
@@ -102,59 +171,102 @@ impl Person {
                 status: Idle,
                 current_floor,
                 destination_floor,
-                elevator: None
-            }
+                elevator: None,
+                attributes: Attributes { mass_kg: Some(Self::random_mass_kg()), ..Default::default() },
+            },
+            legs: vec![Leg { from: current_floor, to: destination_floor }],
+            leg: 0,
         }
     }
 
+    // Planned legs from the current to the destination floor
+    pub fn with_route(mut self, legs: Vec<Leg>) -> Self {
+        if !legs.is_empty() {
+            self.legs = legs;
+        }
+        self
+    }
+
+    // Keeps the drawn mass unless one is given
+    pub fn with_attributes(mut self, attributes: Attributes) -> Self {
+        self.state.attributes = Attributes { mass_kg: attributes.mass_kg.or(self.state.attributes.mass_kg), ..attributes };
+        self
+    }
+
+    // Where the current car should take the person
+    fn leg_destination(&self) -> Floor {
+        self.legs[self.leg].to
+    }
+
     pub fn init(mut self) -> JoinHandle<()> {
+        let journey = info_span!("journey",
+            person = %self.id,
+            from = %self.state.current_floor,
+            to = %self.state.destination_floor,
+            requested_at_ms = sim_time_ms(),
+            elevator = field::Empty,
+            finished_at_ms = field::Empty,
+        );
         tokio::spawn(async move {
             self.request_elevator().await;
-            loop {
+            // The journey span closes once the person has arrived
+            while self.state.status != Done {
                 match self.from_controller.recv().await {
                     Ok(msg) => {
-                        info!("{:?}", msg);
+                        trace!(?msg);
                         match msg {
                             ElevatorHalt(elevator, floor) => {
-                                self.handle_elevator_halt(elevator.clone(), floor.clone()).await
+                                self.handle_elevator_halt(elevator.clone(), floor).await
                             }
                             UpdateBoardingStatus(person, elevator, boarding_status) => {
                                 if self.id.eq(&person) {
-                                    debug!("UpdateBoardingStatus(person={}, elevator={}, boarding_status={})", person, elevator, boarding_status);
+                                    debug!(%elevator, %boarding_status, "Einstieg beantwortet");
                                 }
                                 self.handle_update_boarding_status(person, elevator, boarding_status).await
                             }
                         }
-                        info!("{:?}", self);
+                        trace!(person = %self);
                     }
                     Err(err) => {
                         error!("Error in Channel: {}", err);
                     }
                 }
             }
-        })
+        }.instrument(journey))
     }
 
     // Handlers
 
     pub async fn request_elevator(&mut self) {
-        let _ = self.to_controller.send(PersonRequestElevator(self.state.current_floor)).await;
+        let _ = self.to_controller.send(PersonRequestElevator(self.id.clone(), self.state.current_floor, self.leg_destination(), self.state.attributes)).await;
         Person::request(self.to_mqtt.clone(), self.id.clone(), self.state.current_floor);
     }
 
     async fn handle_elevator_halt(&mut self, elevator: String, floor: Floor) {
         if self.state.current_floor.eq(&floor) && self.state.status.eq(&Idle) {
             self.state.status = Entering;
-            Person::status(self.to_mqtt.clone(), self.id.clone(), self.state.status.clone());
+            self.publish_status();
             let _ = self.to_controller.send(PersonEnteringElevator(self.id.clone(), elevator.clone())).await;
-            random_delay_ms(200, 1000).await;
+            self.step_delay().await;
             let _ = self.to_controller.send(PersonEnteredElevator(self.id.clone(), elevator.clone())).await;
         }
-        if self.state.destination_floor.eq(&floor) && self.state.status.eq(&InElevator) && self.state.elevator.eq(&Some(elevator.clone())) {
+        if self.leg_destination().eq(&floor) && self.state.status.eq(&InElevator) && self.state.elevator.eq(&Some(elevator.clone())) {
+            if self.leg + 1 < self.legs.len() {
+                // Transfer floor, waits there for a car of the next leg
+                let _ = self.to_controller.send(PersonTransferring(self.id.clone(), floor)).await;
+                self.leave_elevator(self.id.clone(), elevator).await;
+                self.state.current_floor = floor;
+                self.leg += 1;
+                self.publish_status();
+                info!(%floor, "Person steigt um");
+                self.request_elevator().await;
+                return;
+            }
             self.leave_elevator(self.id.clone(), elevator).await;
             self.state.status = Done;
-            Person::status(self.to_mqtt.clone(), self.id.clone(), self.state.status.clone());
-            println!("PersonFinished(id={})", self.id);
+            self.publish_status();
+            Span::current().record("finished_at_ms", sim_time_ms());
+            info!(sim_time_ms = sim_time_ms(), "Person angekommen");
         }
     }
 
@@ -163,39 +275,87 @@ impl Person {
             Person::boarding(self.to_mqtt.clone(), person.clone(), boarding_status.clone());
             match boarding_status {
                 Accepted => {
+                    Span::current().record("elevator", elevator.as_str());
                     self.state.status = InElevator;
-                    Person::status(self.to_mqtt.clone(), person.clone(), self.state.status.clone());
+                    self.publish_status();
                     self.state.elevator = Some(elevator.clone());
-                    let _ = self.to_controller.send(PersonChoosingFloor(self.id.clone(), elevator, self.state.destination_floor)).await;
+                    let _ = self.to_controller.send(PersonChoosingFloor(self.id.clone(), elevator, self.leg_destination())).await;
                 }
-                Rejected => {
+                Rejected(reason) => {
                     self.leave_elevator(person, elevator).await;
-                    random_delay_ms(200, 1000).await;
-                    let _ = self.to_controller.send(PersonRequestElevator(self.state.current_floor)).await;
+                    match reason {
+                        // The call stays with the controller
+                        reason if reason.wait_for_next_car() => {
+                            debug!(%reason, "Person wartet auf den nächsten Aufzug");
+                        }
+                        RejectReason::OutOfService if self.state.attributes.can_take_stairs() => {
+                            self.take_stairs().await;
+                        }
+                        _ => {
+                            random_delay_ms(200, 1000).await;
+                            let _ = self.to_controller.send(PersonRequestElevator(self.id.clone(), self.state.current_floor, self.leg_destination(), self.state.attributes)).await;
+                        }
+                    }
                 }
             }
         }
     }
 
+    // Walks to the final destination, other legs included
+    async fn take_stairs(&mut self) {
+        info!(from = %self.state.current_floor, to = %self.state.destination_floor, "Person nimmt die Treppe");
+        self.state.status = Stairs;
+        self.publish_status();
+        let _ = self.to_controller.send(PersonTakingStairs(self.id.clone())).await;
+        let floors = (self.state.destination_floor as i8 - self.state.current_floor as i8).unsigned_abs();
+        for _ in 0..floors {
+            random_delay_ms(10000, 20000).await;
+        }
+        self.state.current_floor = self.state.destination_floor;
+        self.state.status = Done;
+        self.publish_status();
+        let _ = self.to_controller.send(PersonTookStairs(self.id.clone())).await;
+        Span::current().record("finished_at_ms", sim_time_ms());
+        info!(sim_time_ms = sim_time_ms(), "Person angekommen");
+    }
+
     async fn leave_elevator(&mut self, person: String, elevator: String) {
         self.state.elevator = None;
         self.state.status = Leaving;
-        Person::status(self.to_mqtt.clone(), person.clone(), self.state.status.clone());
+        self.publish_status();
         let _ = self.to_controller.send(PersonLeavingElevator(person.clone(), elevator.clone())).await;
-        random_delay_ms(200, 1000).await;
+        self.step_delay().await;
         self.state.status = Idle;
-        Person::status(self.to_mqtt.clone(), person.clone(), self.state.status.clone());
+        self.publish_status();
         let _ = self.to_controller.send(PersonLeftElevator(person, elevator.clone())).await;
     }
 
+    // Getting in or out of the car
+    async fn step_delay(&self) {
+        match self.state.attributes.slow() {
+            true => random_delay_ms(1500, 3000).await,
+            false => random_delay_ms(200, 1000).await,
+        }
+    }
+
     // MQTT-Updates
 
-    fn status(to_mqtt: Sender<crate::mqtt::Send>, id: String, status: PersonStatus) {
+    fn publish_status(&self) {
+        let (legs, leg) = match self.legs.len() > 1 {
+            true => (self.legs.clone(), Some(self.leg)),
+            false => (Vec::new(), None),
+        };
+        Person::status(self.to_mqtt.clone(), self.id.clone(), self.state.status.clone(), legs, leg);
+    }
+
+    fn status(to_mqtt: Sender<crate::mqtt::Send>, id: String, status: PersonStatus, legs: Vec<Leg>, leg: Option<usize>) {
         spawn(async move{
             let msg = PersonTopic {
                 id,
                 msg: StatusUpdate {
                     status,
+                    legs,
+                    leg,
                 },
             };
             let _ = to_mqtt.send(msg).await;
@@ -230,10 +390,14 @@ impl Person {
 
     // Other Methods
 
-    fn pick_two_distinct_floors() -> (Floor, Floor) {
-        let mut rng = rng();
-        let mut floors = vec![Ground, First, Second, Third];
-        floors.shuffle(&mut rng);
+    // Adults, roughly normally distributed
+    pub(crate) fn random_mass_kg() -> f64 {
+        random_normal(75.0, 15.0).clamp(40.0, 150.0)
+    }
+
+    pub(crate) fn pick_two_distinct_floors() -> (Floor, Floor) {
+        let mut floors = [Ground, First, Second, Third];
+        with_rng(|rng| floors.shuffle(rng));
         (floors[0], floors[1])
     }
 }
@@ -242,7 +406,6 @@ impl Person {
 mod tests {
     use super::*;
     use tokio::sync::{broadcast, mpsc};
-    use crate::controller::Floor::*;
     use std::collections::HashSet;
 
     fn create_test_person(id: &str, current: Floor, destination: Floor) -> (
@@ -298,7 +461,7 @@ mod tests {
 
     #[test]
     fn test_person_with_constructor() {
-        let (to_person_tx, to_person_rx) = broadcast::channel(100);
+        let (_to_person_tx, to_person_rx) = broadcast::channel(100);
         let (from_person_tx, _from_person_rx) = mpsc::channel(100);
         let (mqtt_tx, _mqtt_rx) = mpsc::channel(100);
 
@@ -320,7 +483,7 @@ mod tests {
 
     #[test]
     fn test_person_new_constructor() {
-        let (to_person_tx, to_person_rx) = broadcast::channel(100);
+        let (_to_person_tx, to_person_rx) = broadcast::channel(100);
         let (from_person_tx, _from_person_rx) = mpsc::channel(100);
         let (mqtt_tx, _mqtt_rx) = mpsc::channel(100);
 
@@ -345,7 +508,7 @@ mod tests {
         person.request_elevator().await;
 
         let msg = from_person_rx.recv().await.unwrap();
-        assert_eq!(msg, PersonToControllerMsg::PersonRequestElevator(Ground));
+        assert_eq!(msg, PersonToControllerMsg::PersonRequestElevator("P1".to_string(), Ground, First, person.state.attributes));
     }
 
     #[tokio::test]
@@ -369,6 +532,70 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_person_changes_cars_on_transfer_floor() {
+        let (person, _, mut from_person_rx, _) = create_test_person("P1", Ground, Third);
+        let mut person = person.with_route(vec![Leg { from: Ground, to: Second }, Leg { from: Second, to: Third }]);
+        person.state.status = InElevator;
+        person.state.elevator = Some("Low".to_string());
+
+        person.handle_elevator_halt("Low".to_string(), Second).await;
+
+        assert_eq!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonTransferring("P1".to_string(), Second));
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonLeavingElevator(..)));
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonLeftElevator(..)));
+        assert_eq!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonRequestElevator("P1".to_string(), Second, Third, person.state.attributes));
+        assert_eq!(person.state.status, Idle);
+        assert_eq!(person.state.current_floor, Second);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rejected_person_waits_for_next_car() {
+        let (mut person, _, mut from_person_rx, _) = create_test_person("P1", Ground, Third);
+        person.state.status = Entering;
+
+        person.handle_update_boarding_status("P1".to_string(), "E1".to_string(), BoardingStatus::Rejected(RejectReason::Full)).await;
+
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonLeavingElevator(..)));
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonLeftElevator(..)));
+        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+        assert!(from_person_rx.try_recv().is_err());
+        assert_eq!(person.state.status, Idle);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_takes_stairs_when_car_out_of_service() {
+        let (mut person, _, mut from_person_rx, _) = create_test_person("P1", Ground, Second);
+        person.state.status = Entering;
+
+        person.handle_update_boarding_status("P1".to_string(), "E1".to_string(), BoardingStatus::Rejected(RejectReason::OutOfService)).await;
+
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonLeavingElevator(..)));
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonLeftElevator(..)));
+        assert_eq!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonTakingStairs("P1".to_string()));
+        assert_eq!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonTookStairs("P1".to_string()));
+        assert_eq!(person.state.status, Done);
+        assert_eq!(person.state.current_floor, Second);
+
+        // A wheelchair user calls again instead
+        let (person, _, mut from_person_rx, _) = create_test_person("P2", Ground, Second);
+        let mut person = person.with_attributes(Attributes { wheelchair: true, ..Default::default() });
+        person.handle_update_boarding_status("P2".to_string(), "E1".to_string(), BoardingStatus::Rejected(RejectReason::OutOfService)).await;
+
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonLeavingElevator(..)));
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonLeftElevator(..)));
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonRequestElevator(..)));
+
+        // So does freight
+        let (person, _, mut from_person_rx, _) = create_test_person("P3", Ground, Second);
+        let mut person = person.with_attributes(Attributes { load: Some(Load::Freight), ..Default::default() });
+        person.handle_update_boarding_status("P3".to_string(), "E1".to_string(), BoardingStatus::Rejected(RejectReason::OutOfService)).await;
+
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonLeavingElevator(..)));
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonLeftElevator(..)));
+        assert!(matches!(from_person_rx.recv().await.unwrap(), PersonToControllerMsg::PersonRequestElevator(..)));
+    }
+
     #[tokio::test]
     async fn test_handle_elevator_halt_ignores_different_floor() {
         let (mut person, _, mut from_person_rx, _) = create_test_person("P1", Ground, First);
@@ -442,6 +669,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_attributes() {
+        let wheelchair = Attributes { wheelchair: true, ..Default::default() };
+        let stretcher = Attributes { load: Some(Load::Stretcher), ..Default::default() };
+        let freight = Attributes { load: Some(Load::Freight), ..Default::default() };
+
+        assert_eq!(Attributes::default().space(), 1);
+        assert_eq!(wheelchair.space(), 2);
+        assert_eq!(freight.space(), CAPACITY);
+        assert!(wheelchair.slow() && !wheelchair.urgent());
+        assert!(stretcher.slow() && stretcher.urgent());
+        assert!(!freight.slow() && !freight.urgent());
+        assert!(Attributes { priority: true, ..Default::default() }.urgent());
+    }
+
+    #[test]
+    fn test_mass_drawn_unless_given() {
+        let (person, _, _, _) = create_test_person("P1", Ground, Third);
+        let drawn = person.state.attributes.mass_kg.unwrap();
+        assert!((40.0..=150.0).contains(&drawn));
+
+        let person = person.with_attributes(Attributes { wheelchair: true, ..Default::default() });
+        assert_eq!(person.state.attributes.mass_kg, Some(drawn));
+        let person = person.with_attributes(Attributes { mass_kg: Some(120.0), ..Default::default() });
+        assert_eq!(person.state.attributes.mass_kg, Some(120.0));
+        assert!(!person.state.attributes.wheelchair);
+    }
+
     #[test]
     fn test_person_state_display() {
         let state = PersonState {
@@ -449,6 +704,7 @@ mod tests {
             current_floor: Ground,
             destination_floor: Third,
             elevator: None,
+            attributes: Attributes::default(),
         };
 
         let display = format!("{}", state);
@@ -471,7 +727,7 @@ mod tests {
         // - verlässt Fahrkabine (Leaving)
         // - Done (completed)
         
-        let states = vec![Idle, Entering, PersonStatus::Choosing, InElevator, Leaving, Done];
+        let states = [Idle, Entering, PersonStatus::Choosing, InElevator, Leaving, Done];
         assert_eq!(states.len(), 6);
         
         // All states are distinct
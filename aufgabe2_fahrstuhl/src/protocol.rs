@@ -0,0 +1,206 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::mqtt::{ElevatorMsg, InboundErrorReason, PersonMsg, Send, SimulationMsg};
+use crate::mqtt::InboundErrorReason::{UnexpectedType, UnsupportedVersion};
+use crate::mqtt::Send::{ElevatorTopic, PersonTopic, ReplyTopic, SimulationTopic};
+use crate::utils::sim_time_ms;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Protocol {
+    // Bare, untagged payloads as before (type only implied by the topic)
+    #[default]
+    Legacy,
+    // Every payload wrapped in a versioned Envelope
+    V1,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "legacy" => Ok(Protocol::Legacy),
+            "v1" => Ok(Protocol::V1),
+            other => Err(format!("Unknown protocol {}", other)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Envelope {
+    pub version: u32,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub seq: u64,
+    pub sim_time_ms: u64,
+    pub wall_time_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub id: Option<String>,
+    pub data: serde_json::Value,
+}
+
+// Inbound envelopes only need version, type and data
+#[derive(Deserialize)]
+struct InboundEnvelope {
+    version: u32,
+    #[serde(rename = "type")]
+    kind: String,
+    data: serde_json::Value,
+}
+
+pub struct Codec {
+    protocol: Protocol,
+    seq: AtomicU64,
+}
+
+impl Codec {
+    pub fn new(protocol: Protocol) -> Self {
+        Codec { protocol, seq: AtomicU64::new(0) }
+    }
+
+    pub fn encode(&self, send: &Send) -> String {
+        match self.protocol {
+            Protocol::Legacy => send.payload(),
+            Protocol::V1 => {
+                let id = match send {
+                    ElevatorTopic { id, .. } | PersonTopic { id, .. } => Some(id.clone()),
+                    _ => None,
+                };
+                let envelope = Envelope {
+                    version: SCHEMA_VERSION,
+                    kind: message_type(send).to_string(),
+                    seq: self.seq.fetch_add(1, Ordering::Relaxed),
+                    sim_time_ms: sim_time_ms(),
+                    wall_time_ms: wall_time_ms(),
+                    id,
+                    data: send.data(),
+                };
+                serde_json::to_string(&envelope).unwrap()
+            }
+        }
+    }
+}
+
+// Accepts both modes: an envelope is unwrapped and checked, anything else is passed on as is
+pub fn open(expected: &str, payload: &[u8]) -> Result<Vec<u8>, InboundErrorReason> {
+    let value: serde_json::Value = match serde_json::from_slice(payload) {
+        Ok(value) => value,
+        Err(_) => return Ok(payload.to_vec()),
+    };
+    let is_envelope = value.get("type").is_some() && value.get("data").is_some();
+    if !is_envelope {
+        return Ok(payload.to_vec());
+    }
+    let envelope: InboundEnvelope = serde_json::from_value(value)
+        .map_err(|e| InboundErrorReason::MalformedPayload(e.to_string()))?;
+    if envelope.version != SCHEMA_VERSION {
+        return Err(UnsupportedVersion(envelope.version));
+    }
+    if envelope.kind != expected {
+        return Err(UnexpectedType(envelope.kind));
+    }
+    Ok(serde_json::to_vec(&envelope.data).unwrap())
+}
+
+pub fn message_type(send: &Send) -> &'static str {
+    match send {
+        ElevatorTopic { msg, .. } => match msg {
+            ElevatorMsg::Position { .. } => "elevator.position",
+            ElevatorMsg::Door { .. } => "elevator.door",
+            ElevatorMsg::Moving { .. } => "elevator.moving",
+            ElevatorMsg::Passengers { .. } => "elevator.passengers",
+            ElevatorMsg::Missions { .. } => "elevator.missions",
+            ElevatorMsg::Height { .. } => "elevator.height",
+            ElevatorMsg::Passing { .. } => "elevator.passing",
+            ElevatorMsg::Ack { .. } => "elevator.ack",
+            ElevatorMsg::Energy(_) => "elevator.energy",
+            ElevatorMsg::Load { .. } => "elevator.load",
+        },
+        PersonTopic { msg, .. } => match msg {
+            PersonMsg::StatusUpdate { .. } => "person.status",
+            PersonMsg::Boarding { .. } => "person.boarding",
+            PersonMsg::Request { .. } => "person.request",
+            PersonMsg::Introduced { .. } => "person.introduced",
+        },
+        SimulationTopic { msg } => match msg {
+            SimulationMsg::Snapshot(_) => "simulation.snapshot",
+            SimulationMsg::Error(_) => "simulation.error",
+            SimulationMsg::Energy(_) => "simulation.energy",
+        },
+        ReplyTopic { .. } => "simulation.reply",
+    }
+}
+
+fn wall_time_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Floor::*;
+
+    fn position() -> Send {
+        ElevatorTopic { id: "E1".to_string(), msg: ElevatorMsg::Position { floor: First } }
+    }
+
+    #[test]
+    fn test_protocol_from_str() {
+        assert_eq!("legacy".parse(), Ok(Protocol::Legacy));
+        assert_eq!("V1".parse(), Ok(Protocol::V1));
+        assert!("v2".parse::<Protocol>().is_err());
+    }
+
+    #[test]
+    fn test_legacy_encoding_is_bare_payload() {
+        let codec = Codec::new(Protocol::Legacy);
+        assert_eq!(codec.encode(&position()), r#"{"floor":"First"}"#);
+    }
+
+    #[test]
+    fn test_v1_encoding_wraps_payload() {
+        let codec = Codec::new(Protocol::V1);
+
+        let envelope: Envelope = serde_json::from_str(&codec.encode(&position())).unwrap();
+        assert_eq!(envelope.version, SCHEMA_VERSION);
+        assert_eq!(envelope.kind, "elevator.position");
+        assert_eq!(envelope.id, Some("E1".to_string()));
+        assert_eq!(envelope.data, serde_json::json!({ "floor": "First" }));
+        assert!(envelope.wall_time_ms > 0);
+    }
+
+    #[test]
+    fn test_v1_sequence_numbers_increase() {
+        let codec = Codec::new(Protocol::V1);
+
+        let first: Envelope = serde_json::from_str(&codec.encode(&position())).unwrap();
+        let second: Envelope = serde_json::from_str(&codec.encode(&position())).unwrap();
+        assert_eq!(second.seq, first.seq + 1);
+    }
+
+    #[test]
+    fn test_open_legacy_payload_passes_through() {
+        let payload = br#"{"speed":50}"#;
+        assert_eq!(open("simulation.speed", payload).unwrap(), payload.to_vec());
+        assert_eq!(open("simulation.snapshot_request", b"").unwrap(), b"".to_vec());
+    }
+
+    #[test]
+    fn test_open_envelope() {
+        let payload = br#"{"version":1,"type":"simulation.speed","data":{"speed":50}}"#;
+        assert_eq!(open("simulation.speed", payload).unwrap(), br#"{"speed":50}"#.to_vec());
+    }
+
+    #[test]
+    fn test_open_envelope_with_wrong_version_or_type() {
+        let payload = br#"{"version":2,"type":"simulation.speed","data":{"speed":50}}"#;
+        assert_eq!(open("simulation.speed", payload), Err(UnsupportedVersion(2)));
+
+        let payload = br#"{"version":1,"type":"person.introduce","data":{}}"#;
+        assert_eq!(open("simulation.speed", payload), Err(UnexpectedType("person.introduce".to_string())));
+    }
+}
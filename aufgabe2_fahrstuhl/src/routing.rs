@@ -0,0 +1,72 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use serde::{Deserialize, Serialize};
+use crate::controller::Floor;
+
+// Part of a journey that a single car serves
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct Leg {
+    pub from: Floor,
+    pub to: Floor,
+}
+
+// Fewest legs from `from` to `to`, every leg is one of `trips` (origin and destination
+// pairs a car stops at). More than one leg means changing cars at a transfer floor.
+pub fn plan(trips: &HashSet<(Floor, Floor)>, from: Floor, to: Floor) -> Option<Vec<Leg>> {
+    let floors = [Floor::Ground, Floor::First, Floor::Second, Floor::Third];
+    let mut previous: HashMap<Floor, Floor> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+    while let Some(floor) = queue.pop_front() {
+        if floor == to {
+            let mut legs = Vec::new();
+            let mut at = to;
+            while at != from {
+                legs.push(Leg { from: previous[&at], to: at });
+                at = previous[&at];
+            }
+            legs.reverse();
+            return (!legs.is_empty()).then_some(legs);
+        }
+        for next in floors {
+            if next != from && !previous.contains_key(&next) && trips.contains(&(floor, next)) {
+                previous.insert(next, floor);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Floor::*;
+
+    fn trips(zones: &[&[Floor]]) -> HashSet<(Floor, Floor)> {
+        zones.iter()
+            .flat_map(|zone| zone.iter().flat_map(move |&a| zone.iter().map(move |&b| (a, b))))
+            .filter(|(a, b)| a != b)
+            .collect()
+    }
+
+    #[test]
+    fn test_direct_trip_is_one_leg() {
+        let trips = trips(&[&[Ground, First, Second, Third]]);
+        assert_eq!(plan(&trips, Ground, Third), Some(vec![Leg { from: Ground, to: Third }]));
+    }
+
+    #[test]
+    fn test_transfer_at_sky_lobby() {
+        // Low rise up to the second floor, high rise from there
+        let trips = trips(&[&[Ground, First, Second], &[Second, Third]]);
+
+        assert_eq!(plan(&trips, First, Third), Some(vec![Leg { from: First, to: Second }, Leg { from: Second, to: Third }]));
+        assert_eq!(plan(&trips, Third, Ground), Some(vec![Leg { from: Third, to: Second }, Leg { from: Second, to: Ground }]));
+    }
+
+    #[test]
+    fn test_unreachable_floor() {
+        let trips = trips(&[&[Ground, First], &[Second, Third]]);
+        assert_eq!(plan(&trips, Ground, Third), None);
+        assert_eq!(plan(&trips, Ground, Ground), None);
+    }
+}
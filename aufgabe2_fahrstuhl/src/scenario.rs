@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+use serde::Deserialize;
+use tokio::sync::oneshot;
+use crate::config::Elevators;
+use crate::controller::Floor;
+use crate::journal::JournalEntry;
+use crate::mqtt::{Query, Receive};
+use crate::person::Attributes;
+use crate::simulation::Simulation;
+use crate::transport::MemoryTransport;
+use crate::utils::delay;
+
+// Scripted persons, e.g.
+//   [[person]]
+//   id = "P1"
+//   at_ms = 2000
+//   curr = "Ground"
+//   dest = "Third"
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub elevators: Option<Elevators>,
+    pub speed: Option<u64>,
+    pub seed: Option<u64>,
+    #[serde(default, rename = "person")]
+    pub persons: Vec<ScenarioPerson>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ScenarioPerson {
+    pub id: String,
+    #[serde(default)]
+    pub at_ms: u64,
+    pub curr: Floor,
+    pub dest: Floor,
+    #[serde(default)]
+    pub attributes: Attributes,
+}
+
+impl Scenario {
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let scenario: Scenario = toml::from_str(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        scenario.validate().map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(scenario)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let mut ids = HashSet::new();
+        for person in &self.persons {
+            if !ids.insert(&person.id) {
+                return Err(format!("Person {} ist doppelt", person.id));
+            }
+            if person.curr == person.dest {
+                return Err(format!("Person {}: Start und Ziel sind gleich", person.id));
+            }
+            person.attributes.validate().map_err(|e| format!("Person {}: {}", person.id, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Vec<JournalEntry> {
+        self.persons.iter()
+            .map(|person| JournalEntry {
+                at_ms: person.at_ms,
+                message: Receive::Person { id: person.id.clone(), curr: person.curr, dest: person.dest, attributes: person.attributes, payload: None },
+            })
+            .collect()
+    }
+}
+
+// Runs the simulation in-process, feeds the entries at their simulated time and
+// returns the statistics once every introduced person has arrived
+pub async fn play(simulation: Simulation, mut entries: Vec<JournalEntry>, timeout: Duration) -> Result<serde_json::Value, String> {
+    // Older journals contain pause / step, a paused clock would never reach the next entry
+    entries.retain(|entry| !matches!(entry.message, Receive::Control(_)));
+    entries.sort_by_key(|entry| entry.at_ms);
+    let persons = entries.iter()
+        .filter_map(|entry| match &entry.message {
+            Receive::Person { id, .. } => Some(id.clone()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>()
+        .len() as u64 + simulation.students() as u64;
+
+    let transport = MemoryTransport::new();
+    simulation.start(transport.clone());
+
+    let run = async {
+        let mut now = 0;
+        for entry in entries {
+            delay(entry.at_ms.saturating_sub(now)).await;
+            now = now.max(entry.at_ms);
+            transport.send(entry.message).await;
+        }
+        loop {
+            let stats = stats(&transport).await?;
+            if arrived(&stats) >= persons {
+                return Ok(stats);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    match tokio::time::timeout(timeout, run).await {
+        Ok(result) => result,
+        Err(_) => {
+            let arrived = arrived(&stats(&transport).await?);
+            Err(format!("Zeitüberschreitung: {} von {} Personen angekommen", arrived, persons))
+        }
+    }
+}
+
+async fn stats(transport: &MemoryTransport) -> Result<serde_json::Value, String> {
+    let (answer, result) = oneshot::channel();
+    transport.send(Receive::Ask { query: Query::Stats, answer }).await;
+    result.await.map_err(|_| "Simulation antwortet nicht".to_string())
+}
+
+// By car or on foot
+fn arrived(stats: &serde_json::Value) -> u64 {
+    stats["persons_completed"].as_u64().unwrap_or(0) + stats["persons_took_stairs"].as_u64().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Floor::*;
+    use crate::mqtt::Control;
+
+    fn scenario(text: &str) -> Result<Scenario, String> {
+        let scenario: Scenario = toml::from_str(text).map_err(|e| e.to_string())?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    #[test]
+    fn test_parse_scenario() {
+        let scenario = scenario(r#"
+            elevators = 2
+            speed = 10
+
+            [[person]]
+            id = "P1"
+            curr = "Ground"
+            dest = "Third"
+
+            [[person]]
+            id = "P2"
+            at_ms = 5000
+            curr = "Second"
+            dest = "First"
+            attributes = { wheelchair = true }
+        "#).unwrap();
+
+        let entries = scenario.entries();
+        assert_eq!(scenario.elevators, Some(Elevators::Count(2)));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].at_ms, 5000);
+        assert!(matches!(&entries[1].message, Receive::Person { curr: Second, dest: First, attributes, .. } if attributes.wheelchair));
+    }
+
+    #[test]
+    fn test_invalid_scenario() {
+        assert!(scenario("[[person]]\nid = \"P1\"\ncurr = \"Ground\"\ndest = \"Ground\"").is_err());
+        assert!(scenario("[[person]]\nid = \"P1\"\ncurr = \"Ground\"\ndest = \"Fifth\"").is_err());
+        assert!(scenario("[[person]]\nid = \"P1\"\ncurr = \"Ground\"\ndest = \"First\"\n[[person]]\nid = \"P1\"\ncurr = \"Ground\"\ndest = \"First\"").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_play_until_everyone_arrived() {
+        let entries = vec![
+            JournalEntry { at_ms: 0, message: Receive::Person { id: "P1".to_string(), curr: Ground, dest: Third, attributes: Default::default(), payload: None } },
+            JournalEntry { at_ms: 3000, message: Receive::Person { id: "P2".to_string(), curr: Second, dest: Ground, attributes: Default::default(), payload: None } },
+        ];
+
+        let stats = play(Simulation::new(vec!["E1".to_string()]), entries, Duration::from_secs(600)).await.unwrap();
+
+        assert_eq!(stats["persons_completed"], 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_play_skips_pause() {
+        let entries = vec![
+            JournalEntry { at_ms: 0, message: Receive::Control(Control::Pause) },
+            JournalEntry { at_ms: 1000, message: Receive::Person { id: "P1".to_string(), curr: Ground, dest: Third, attributes: Default::default(), payload: None } },
+        ];
+
+        let stats = play(Simulation::new(vec!["E1".to_string()]), entries, Duration::from_secs(600)).await.unwrap();
+
+        assert_eq!(stats["persons_completed"], 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_play_waits_for_students() {
+        let entries = vec![
+            JournalEntry { at_ms: 0, message: Receive::Person { id: "P1".to_string(), curr: Ground, dest: Third, attributes: Default::default(), payload: None } },
+        ];
+        let simulation = Simulation::new(vec!["E1".to_string()]).with_students(2);
+
+        let stats = play(simulation, entries, Duration::from_secs(600)).await.unwrap();
+
+        assert_eq!(stats["persons_completed"], 3);
+    }
+}
@@ -0,0 +1,484 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use crate::controller::{ElevatorController, Floor, DEFAULT_RATED_LOAD_KG};
+use crate::elevator::Elevator;
+use crate::journal::Journal;
+use crate::kinematics::MotionProfile;
+use crate::energy::EnergyModel;
+use crate::parking::ParkingPolicy;
+use crate::mqtt::{Control, InboundError, InboundErrorReason, PersonMsg, Receive, SimulationMsg, Topics};
+use crate::mqtt::Send::{PersonTopic, SimulationTopic};
+use crate::msg::SimulationToControllerMsg;
+use crate::msg::SimulationToControllerMsg::PublishSnapshot;
+use crate::person::{Attributes, Person};
+use crate::routing;
+use crate::transport::Transport;
+use crate::logger;
+use crate::utils::{pause, resume, set_speed_factor, sim_time_ms, step};
+use tracing::{info, warn};
+
+// Wires controller, elevators and persons to a transport
+pub struct Simulation {
+    elevators: Vec<String>,
+    students: usize,
+    snapshot_interval: Option<Duration>,
+    topics: Topics,
+    journal: Option<Journal>,
+    motion: HashMap<String, MotionProfile>,
+    energy: EnergyModel,
+    served_floors: HashMap<String, Vec<Floor>>,
+    parking: Option<ParkingPolicy>,
+    rated_load_kg: HashMap<String, f64>,
+}
+
+impl Simulation {
+
+    pub fn new(elevators: Vec<String>) -> Self {
+        Simulation {
+            elevators,
+            students: 0,
+            snapshot_interval: None,
+            topics: Topics::default(),
+            journal: None,
+            motion: HashMap::new(),
+            energy: EnergyModel::default(),
+            served_floors: HashMap::new(),
+            parking: None,
+            rated_load_kg: HashMap::new(),
+        }
+    }
+
+    // Randomly travelling persons created at startup
+    pub fn with_students(mut self, students: usize) -> Self {
+        self.students = students;
+        self
+    }
+
+    pub(crate) fn students(&self) -> usize {
+        self.students
+    }
+
+    pub fn with_snapshot_interval(mut self, interval: Duration) -> Self {
+        self.snapshot_interval = Some(interval);
+        self
+    }
+
+    pub fn with_topics(mut self, topics: Topics) -> Self {
+        self.topics = topics;
+        self
+    }
+
+    // Records every inbound message, can be played back with `replay`
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    // Cars without a profile use MotionProfile::default()
+    pub fn with_motion(mut self, elevator: &str, motion: MotionProfile) -> Self {
+        self.motion.insert(elevator.to_string(), motion);
+        self
+    }
+
+    // Zone of a car, cars without an entry serve every floor
+    pub fn with_served_floors(mut self, elevator: &str, floors: Vec<Floor>) -> Self {
+        self.served_floors.insert(elevator.to_string(), floors);
+        self
+    }
+
+    // Origin and destination pairs at least one car stops at, longer journeys change cars
+    fn trips(&self) -> HashSet<(Floor, Floor)> {
+        let floors = [Floor::Ground, Floor::First, Floor::Second, Floor::Third];
+        let served = |elevator: &String, floor: Floor| self.served_floors.get(elevator).is_none_or(|floors| floors.contains(&floor));
+        floors.iter()
+            .flat_map(|&curr| floors.iter().map(move |&dest| (curr, dest)))
+            .filter(|&(curr, dest)| curr != dest && self.elevators.iter().any(|e| served(e, curr) && served(e, dest)))
+            .collect()
+    }
+
+    pub fn with_energy(mut self, energy: EnergyModel) -> Self {
+        self.energy = energy;
+        self
+    }
+
+    // Cars without an entry carry DEFAULT_RATED_LOAD_KG
+    pub fn with_rated_load(mut self, elevator: &str, rated_load_kg: f64) -> Self {
+        self.rated_load_kg.insert(elevator.to_string(), rated_load_kg);
+        self
+    }
+
+    pub fn with_parking(mut self, parking: ParkingPolicy) -> Self {
+        self.parking = Some(parking);
+        self
+    }
+
+    pub fn start<T: Transport>(self, transport: T) -> JoinHandle<()> {
+        // controller -> elevators
+        let (controller_to_elevators_tx, _) = broadcast::channel(100000);
+        // elevator -> controller
+        let (elevator_to_controller_tx, elevator_to_controller_rx) = mpsc::channel(100000);
+        // persons -> controller
+        let (person_to_controller_tx, person_to_controller_rx) = mpsc::channel(100000);
+        // controller -> persons
+        let (controller_to_persons_tx, _) = broadcast::channel(100000);
+        // components -> transport
+        let (to_mqtt_tx, to_mqtt_rx) = mpsc::channel(100000);
+        // transport -> simulation
+        let (mqtt_to_person_tx, mut mqtt_to_person_rx) = mpsc::channel(100000);
+        // simulation -> controller
+        let (simulation_to_controller_tx, simulation_to_controller_rx) = mpsc::channel(100);
+
+        let mut controller = ElevatorController::new(
+            elevator_to_controller_rx,
+            controller_to_elevators_tx.clone(),
+            person_to_controller_rx,
+            controller_to_persons_tx.clone(),
+            simulation_to_controller_rx,
+            to_mqtt_tx.clone(),
+            self.elevators.clone()
+        ).with_energy_model(self.energy);
+        for (elevator, floors) in &self.served_floors {
+            controller = controller.with_served_floors(elevator, floors.clone());
+        }
+        for (elevator, rated_load_kg) in &self.rated_load_kg {
+            controller = controller.with_rated_load(elevator, *rated_load_kg);
+        }
+        if let Some(interval) = self.snapshot_interval {
+            controller = controller.with_snapshot_interval(interval);
+        }
+        if let Some(parking) = self.parking {
+            controller = controller.with_parking(parking);
+        }
+
+        let mut threads = vec![
+            transport.inbound(mqtt_to_person_tx),
+            transport.publisher(to_mqtt_rx),
+            controller.init(),
+        ];
+        for elevator in &self.elevators {
+            let motion = self.motion.get(elevator).copied().unwrap_or_default();
+            let mut car = Elevator::new(elevator, controller_to_elevators_tx.subscribe(), elevator_to_controller_tx.clone(), to_mqtt_tx.clone()).with_motion(motion);
+            if let Some(floors) = self.served_floors.get(elevator) {
+                car = car.with_served_floors(floors.clone());
+            }
+            threads.push(car.init());
+        }
+
+        // Heavier persons would overload every car
+        let max_rated_load_kg = self.elevators.iter()
+            .map(|elevator| self.rated_load_kg.get(elevator).copied().unwrap_or(DEFAULT_RATED_LOAD_KG))
+            .fold(0.0, f64::max);
+        let trips = self.trips();
+        let students = match trips.is_empty() {
+            true if self.students > 0 => {
+                warn!("Kein Aufzug bedient zwei Ebenen, keine Studenten erstellt");
+                0
+            }
+            _ => self.students,
+        };
+        let mut created_persons: HashSet<String> = HashSet::new();
+        for i in 0..students {
+            let person_id = format!("Student_{}", i);
+            created_persons.insert(person_id.clone());
+            // Random trips, drawn again until there is a route
+            let (current_floor, destination_floor, legs) = loop {
+                let (curr, dest) = Person::pick_two_distinct_floors();
+                if let Some(legs) = routing::plan(&trips, curr, dest) {
+                    break (curr, dest, legs);
+                }
+            };
+            threads.push(Person::with(
+                &person_id,
+                controller_to_persons_tx.subscribe(),
+                person_to_controller_tx.clone(),
+                to_mqtt_tx.clone(),
+                current_floor,
+                destination_floor
+            ).with_route(legs).with_attributes(Attributes { mass_kg: Some(Person::random_mass_kg().min(max_rated_load_kg)), ..Default::default() }).init());
+        }
+
+        let topics = self.topics;
+        let mut journal = self.journal;
+        tokio::spawn(async move {
+            while let Some(msg) = mqtt_to_person_rx.recv().await {
+                let (mut msg, answer) = match msg {
+                    Receive::Introduce { person, answer } => (*person, Some(answer)),
+                    msg => (msg, None),
+                };
+                // Drawn before journaling so a replay gets the same mass, always drawn to keep the random numbers in step
+                if let Receive::Person { attributes, .. } = &mut msg {
+                    let drawn = Person::random_mass_kg().min(max_rated_load_kg);
+                    attributes.mass_kg.get_or_insert(drawn);
+                }
+                let reply = |result: Result<(), InboundErrorReason>| if let Some(answer) = answer {
+                    let _ = answer.send(result);
+                };
+                if let Some(journal) = &mut journal {
+                    journal.record(sim_time_ms(), &msg);
+                }
+                match msg {
+                    Receive::Person { id, curr: current_floor, dest: destination_floor, attributes, payload } => {
+                        // In-process introductions (TUI, scenario) have no payload, their journal form is echoed
+                        let payload = payload.unwrap_or_else(|| serde_json::to_string(&Receive::Person {
+                            id: id.clone(), curr: current_floor, dest: destination_floor, attributes, payload: None,
+                        }).unwrap());
+                        if let Some(mass_kg) = attributes.mass_kg && mass_kg > max_rated_load_kg {
+                            warn!(person = %id, mass_kg, "Schwerer als die Nennlast jeder Kabine");
+                            let reason = format!("mass_kg {} übersteigt die größte Nennlast von {} kg", mass_kg, max_rated_load_kg);
+                            let error = InboundError::new(&topics.person_introduce(), payload.as_bytes(), InboundErrorReason::InvalidAttributes(reason));
+                            reply(Err(error.error.clone()));
+                            let _ = to_mqtt_tx.send(SimulationTopic { msg: SimulationMsg::Error(error) }).await;
+                            continue;
+                        }
+                        let Some(legs) = routing::plan(&trips, current_floor, destination_floor) else {
+                            warn!(person = %id, from = %current_floor, to = %destination_floor, "Kein Aufzug bedient diese Fahrt");
+                            let error = InboundError::no_elevator(&topics, &payload);
+                            reply(Err(error.error.clone()));
+                            let _ = to_mqtt_tx.send(SimulationTopic { msg: SimulationMsg::Error(error) }).await;
+                            continue;
+                        };
+                        if created_persons.insert(id.clone()) {
+                            info!(person = %id, from = %current_floor, to = %destination_floor, transfers = legs.len() - 1, ?attributes, "Person erstellt");
+                            let person = Person::with(
+                                &id,
+                                controller_to_persons_tx.subscribe(),
+                                person_to_controller_tx.clone(),
+                                to_mqtt_tx.clone(),
+                                current_floor,
+                                destination_floor
+                            ).with_route(legs).with_attributes(attributes);
+                            threads.push(person.init());
+                            let ack = PersonMsg::Introduced { curr: current_floor, dest: destination_floor };
+                            let _ = to_mqtt_tx.send(PersonTopic { id, msg: ack }).await;
+                            reply(Ok(()));
+                        } else {
+                            let error = InboundError::duplicate(&topics, &id, &payload);
+                            reply(Err(error.error.clone()));
+                            let _ = to_mqtt_tx.send(SimulationTopic { msg: SimulationMsg::Error(error) }).await;
+                        }
+                    }
+                    Receive::Speed { speed } => {
+                        set_speed_factor(speed);
+                        info!(speed, "Simulationsgeschwindigkeit geändert");
+                    }
+                    Receive::LogFilter { filter } => {
+                        match logger::set_filter(&filter) {
+                            Ok(()) => info!(%filter, "Log-Filter geändert"),
+                            Err(e) => warn!(%filter, "Log-Filter nicht geändert: {}", e),
+                        }
+                    }
+                    Receive::Control(control) => {
+                        match control {
+                            Control::Pause => {
+                                pause();
+                                info!(sim_time_ms = sim_time_ms(), "Simulation angehalten");
+                            }
+                            Control::Resume => {
+                                resume();
+                                info!(sim_time_ms = sim_time_ms(), "Simulation fortgesetzt");
+                            }
+                            Control::Step { ms } => {
+                                let now = step(ms);
+                                info!(sim_time_ms = now, "Simulation einen Schritt weiter");
+                            }
+                        }
+                        // Dashboards see the new state right away
+                        let _ = simulation_to_controller_tx.send(PublishSnapshot).await;
+                    }
+                    Receive::ElevatorCommand { elevator, command } => {
+                        let _ = simulation_to_controller_tx.send(SimulationToControllerMsg::Command(elevator, command)).await;
+                    }
+                    Receive::SnapshotRequest => {
+                        let _ = simulation_to_controller_tx.send(PublishSnapshot).await;
+                    }
+                    Receive::Query { query, reply } => {
+                        let _ = simulation_to_controller_tx.send(SimulationToControllerMsg::Query(query, reply)).await;
+                    }
+                    Receive::Ask { query, answer } => {
+                        let _ = simulation_to_controller_tx.send(SimulationToControllerMsg::Ask(query, answer)).await;
+                    }
+                    // Unwrapped into the person above
+                    Receive::Introduce { .. } => {}
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Floor::*;
+    use crate::mqtt::{ElevatorMsg, Send};
+    use crate::mqtt::Send::ElevatorTopic;
+    use crate::person::{Attributes, PersonStatus};
+    use crate::routing::Leg;
+    use crate::transport::MemoryTransport;
+
+    async fn wait_for(observer: &mut broadcast::Receiver<Send>, matches: impl Fn(&Send) -> bool) {
+        loop {
+            match observer.recv().await {
+                Ok(send) if matches(&send) => return,
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(e) => panic!("Transport closed: {}", e),
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_person_travels_without_broker() {
+        let transport = MemoryTransport::new().with_recording();
+        let mut observer = transport.subscribe();
+        Simulation::new(vec!["E1".to_string()]).start(transport.clone());
+
+        transport.send(Receive::Person { id: "P1".to_string(), curr: Ground, dest: Second, attributes: Default::default(), payload: None }).await;
+
+        wait_for(&mut observer, |send| matches!(send,
+            PersonTopic { id, msg: PersonMsg::StatusUpdate { status: PersonStatus::Done, .. } } if id == "P1")).await;
+
+        let published = transport.published();
+        assert!(published.iter().any(|send| matches!(send, PersonTopic { id, msg: PersonMsg::Introduced { .. } } if id == "P1")));
+        assert!(published.iter().any(|send| matches!(send, ElevatorTopic { id, msg: ElevatorMsg::Position { floor: Second } } if id == "E1")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_duplicate_person_reported() {
+        let transport = MemoryTransport::new().with_recording();
+        let mut observer = transport.subscribe();
+        Simulation::new(vec!["E1".to_string()]).with_students(1).start(transport.clone());
+
+        transport.send(Receive::Person { id: "Student_0".to_string(), curr: Ground, dest: First, attributes: Default::default(), payload: None }).await;
+
+        wait_for(&mut observer, |send| matches!(send, SimulationTopic { msg: SimulationMsg::Error(_) })).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_trip_without_serving_car_reported() {
+        let transport = MemoryTransport::new().with_recording();
+        let mut observer = transport.subscribe();
+        Simulation::new(vec!["E1".to_string(), "E2".to_string()])
+            .with_served_floors("E1", vec![Ground, First])
+            .with_served_floors("E2", vec![Second, Third])
+            .start(transport.clone());
+
+        transport.send(Receive::Person { id: "P1".to_string(), curr: Ground, dest: Third, attributes: Default::default(), payload: None }).await;
+
+        wait_for(&mut observer, |send| matches!(send,
+            SimulationTopic { msg: SimulationMsg::Error(InboundError { error: crate::mqtt::InboundErrorReason::NoElevatorForTrip, .. }) })).await;
+        assert!(!transport.published().iter().any(|send| matches!(send, PersonTopic { msg: PersonMsg::Introduced { .. }, .. })));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_person_heavier_than_every_car_reported() {
+        let transport = MemoryTransport::new().with_recording();
+        let mut observer = transport.subscribe();
+        Simulation::new(vec!["E1".to_string(), "E2".to_string()])
+            .with_rated_load("E1", 100.0)
+            .start(transport.clone());
+
+        let attributes = Attributes { mass_kg: Some(200.0), ..Default::default() };
+        transport.send(Receive::Person { id: "P1".to_string(), curr: Ground, dest: Third, attributes, payload: None }).await;
+
+        wait_for(&mut observer, |send| matches!(send,
+            SimulationTopic { msg: SimulationMsg::Error(InboundError { error: crate::mqtt::InboundErrorReason::InvalidAttributes(_), .. }) })).await;
+        assert!(!transport.published().iter().any(|send| matches!(send, PersonTopic { msg: PersonMsg::Introduced { .. }, .. })));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drawn_mass_is_journaled() {
+        let path = std::env::temp_dir().join(format!("journal_mass_{}.jsonl", std::process::id()));
+        let transport = MemoryTransport::new().with_recording();
+        let mut observer = transport.subscribe();
+        Simulation::new(vec!["E1".to_string()])
+            .with_rated_load("E1", 60.0)
+            .with_journal(Journal::create(&path).unwrap())
+            .start(transport.clone());
+
+        transport.send(Receive::Person { id: "P1".to_string(), curr: Ground, dest: First, attributes: Default::default(), payload: None }).await;
+        wait_for(&mut observer, |send| matches!(send, PersonTopic { id, msg: PersonMsg::Introduced { .. } } if id == "P1")).await;
+
+        let entries = Journal::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // At most the rated load of the car
+        assert!(matches!(&entries[0].message, Receive::Person { attributes: Attributes { mass_kg: Some(mass), .. }, .. } if *mass > 0.0 && *mass <= 60.0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_person_uses_car_serving_the_trip() {
+        let transport = MemoryTransport::new().with_recording();
+        let mut observer = transport.subscribe();
+        Simulation::new(vec!["E1".to_string(), "E2".to_string()])
+            .with_served_floors("E1", vec![Ground, Third])
+            .start(transport.clone());
+
+        transport.send(Receive::Person { id: "P1".to_string(), curr: First, dest: Second, attributes: Default::default(), payload: None }).await;
+
+        wait_for(&mut observer, |send| matches!(send,
+            PersonTopic { id, msg: PersonMsg::StatusUpdate { status: PersonStatus::Done, .. } } if id == "P1")).await;
+        let published = transport.published();
+        assert!(published.iter().any(|send| matches!(send, ElevatorTopic { id, msg: ElevatorMsg::Position { floor: Second } } if id == "E2")));
+        assert!(!published.iter().any(|send| matches!(send, ElevatorTopic { id, msg: ElevatorMsg::Moving { .. } } if id == "E1")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_person_changes_cars_at_transfer_floor() {
+        let transport = MemoryTransport::new().with_recording();
+        let mut observer = transport.subscribe();
+        Simulation::new(vec!["Low".to_string(), "High".to_string()])
+            .with_served_floors("Low", vec![Ground, First, Second])
+            .with_served_floors("High", vec![Second, Third])
+            .start(transport.clone());
+
+        transport.send(Receive::Person { id: "P1".to_string(), curr: Ground, dest: Third, attributes: Default::default(), payload: None }).await;
+
+        wait_for(&mut observer, |send| matches!(send,
+            PersonTopic { id, msg: PersonMsg::StatusUpdate { status: PersonStatus::Done, .. } } if id == "P1")).await;
+        let published = transport.published();
+        let legs = vec![Leg { from: Ground, to: Second }, Leg { from: Second, to: Third }];
+        assert!(published.iter().any(|send| matches!(send,
+            PersonTopic { msg: PersonMsg::StatusUpdate { status: PersonStatus::InElevator, legs: l, leg: Some(1) }, .. } if *l == legs)));
+        assert!(published.iter().any(|send| matches!(send, ElevatorTopic { id, msg: ElevatorMsg::Position { floor: Second } } if id == "Low")));
+        assert!(published.iter().any(|send| matches!(send, ElevatorTopic { id, msg: ElevatorMsg::Position { floor: Third } } if id == "High")));
+
+        let (answer, stats) = tokio::sync::oneshot::channel();
+        transport.send(Receive::Ask { query: crate::mqtt::Query::Stats, answer }).await;
+        let stats = stats.await.unwrap();
+        assert_eq!(stats["transfers"], 1);
+        assert_eq!(stats["persons_completed"], 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_car_returns_to_lobby() {
+        let transport = MemoryTransport::new().with_recording();
+        let mut observer = transport.subscribe();
+        Simulation::new(vec!["E1".to_string()])
+            .with_parking(ParkingPolicy { idle_timeout_ms: 5000, ..Default::default() })
+            .start(transport.clone());
+
+        transport.send(Receive::Person { id: "P1".to_string(), curr: Ground, dest: Second, attributes: Default::default(), payload: None }).await;
+
+        wait_for(&mut observer, |send| matches!(send,
+            ElevatorTopic { id, msg: ElevatorMsg::Moving { from: Second, to: Ground, parking: true } } if id == "E1")).await;
+        wait_for(&mut observer, |send| matches!(send, ElevatorTopic { id, msg: ElevatorMsg::Position { floor: Ground } } if id == "E1")).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wheelchair_user_travels() {
+        let transport = MemoryTransport::new().with_recording();
+        let mut observer = transport.subscribe();
+        Simulation::new(vec!["E1".to_string()]).start(transport.clone());
+
+        let attributes = Attributes { wheelchair: true, ..Default::default() };
+        transport.send(Receive::Person { id: "P1".to_string(), curr: Ground, dest: Second, attributes, payload: None }).await;
+        transport.send(Receive::Person { id: "P2".to_string(), curr: Ground, dest: Second, attributes: Default::default(), payload: None }).await;
+
+        let done = |send: &Send| matches!(send, PersonTopic { msg: PersonMsg::StatusUpdate { status: PersonStatus::Done, .. }, .. });
+        wait_for(&mut observer, done).await;
+        wait_for(&mut observer, done).await;
+        let published = transport.published();
+        // Never together in the car
+        assert!(!published.iter().any(|send| matches!(send, ElevatorTopic { msg: ElevatorMsg::Passengers { passengers }, .. } if passengers.len() > 1)));
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
+use crate::controller::Floor;
+use crate::energy::{BuildingEnergy, EnergyMeter, EnergyModel, EnergyReport};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum JourneyStatus {
+    Waiting,
+    Riding,
+    // Waiting on a transfer floor for the car of the next leg
+    Transferring,
+    // Walking after a car went out of service
+    Stairs,
+    Done
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Journey {
+    pub person: String,
+    pub status: JourneyStatus,
+    pub origin: Floor,
+    pub destination: Option<Floor>,
+    pub elevator: Option<String>,
+    pub requested_at_ms: u64,
+    pub boarded_at_ms: Option<u64>,
+    pub finished_at_ms: Option<u64>,
+    pub transfers: u64,
+    pub transfer_wait_ms: u64,
+    #[serde(skip)]
+    transfer_since: Option<u64>,
+}
+
+// Upper bounds in ms (simulated time) for wait and journey times
+pub const TIME_BUCKETS_MS: [u64; 9] = [1000, 2000, 5000, 10000, 20000, 30000, 60000, 120000, 300000];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    bounds_ms: Vec<u64>,
+    counts: Vec<u64>,
+    sum_ms: u64,
+    count: u64,
+}
+
+impl Histogram {
+
+    pub fn new(bounds_ms: &[u64]) -> Self {
+        Histogram {
+            bounds_ms: bounds_ms.to_vec(),
+            counts: vec![0; bounds_ms.len()],
+            sum_ms: 0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, ms: u64) {
+        if let Some(bucket) = self.bounds_ms.iter().position(|bound| ms <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.sum_ms += ms;
+        self.count += 1;
+    }
+
+    pub fn bounds_ms(&self) -> &[u64] {
+        &self.bounds_ms
+    }
+
+    // Per bucket, not cumulative; values above the last bound only show up in count
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn sum_ms(&self) -> u64 {
+        self.sum_ms
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new(&TIME_BUCKETS_MS)
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Statistics {
+    trips: u64,
+    parking_trips: u64,
+    door_cycles: u64,
+    boardings_accepted: u64,
+    boardings_rejected: u64,
+    persons_completed: u64,
+    persons_took_stairs: u64,
+    total_wait_ms: u64,
+    max_wait_ms: u64,
+    total_journey_ms: u64,
+    total_transfer_wait_ms: u64,
+    wait_times: Histogram,
+    transfer_wait_times: Histogram,
+    journey_times: Histogram,
+    journeys: HashMap<String, Journey>,
+    // Requests per floor, the parking strategy predicts the demand from it
+    calls: BTreeMap<Floor, u64>,
+    energy_model: EnergyModel,
+    energy: BTreeMap<String, EnergyMeter>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct StatsSummary {
+    pub trips: u64,
+    pub parking_trips: u64,
+    pub door_cycles: u64,
+    pub boardings_accepted: u64,
+    pub boardings_rejected: u64,
+    pub persons_waiting: u64,
+    pub persons_riding: u64,
+    pub persons_completed: u64,
+    pub persons_took_stairs: u64,
+    pub avg_wait_ms: u64,
+    pub max_wait_ms: u64,
+    pub avg_journey_ms: u64,
+    pub persons_transferring: u64,
+    pub transfers: u64,
+    pub avg_transfer_wait_ms: u64,
+    pub energy: BuildingEnergy,
+}
+
+impl Statistics {
+
+    pub fn with_energy_model(mut self, model: EnergyModel) -> Self {
+        self.energy_model = model;
+        self
+    }
+
+    pub fn requested(&mut self, person: &str, floor: Floor, now: u64) {
+        *self.calls.entry(floor).or_default() += 1;
+        // A rejected person requests again, the journey keeps its first request time
+        self.journeys.entry(person.to_string()).or_insert_with(|| Journey {
+            person: person.to_string(),
+            status: JourneyStatus::Waiting,
+            origin: floor,
+            destination: None,
+            elevator: None,
+            requested_at_ms: now,
+            boarded_at_ms: None,
+            finished_at_ms: None,
+            transfers: 0,
+            transfer_wait_ms: 0,
+            transfer_since: None,
+        });
+    }
+
+    pub fn boarded(&mut self, person: &str, elevator: &str, now: u64) {
+        self.boardings_accepted += 1;
+        if let Some(journey) = self.journeys.get_mut(person) {
+            journey.status = JourneyStatus::Riding;
+            journey.elevator = Some(elevator.to_string());
+            // Waiting for the next leg is counted apart from the first wait
+            if let Some(since) = journey.transfer_since.take() {
+                let wait = now.saturating_sub(since);
+                journey.transfer_wait_ms += wait;
+                self.total_transfer_wait_ms += wait;
+                self.transfer_wait_times.observe(wait);
+                return;
+            }
+            let wait = now.saturating_sub(journey.requested_at_ms);
+            self.total_wait_ms += wait;
+            self.max_wait_ms = self.max_wait_ms.max(wait);
+            self.wait_times.observe(wait);
+            journey.boarded_at_ms = Some(now);
+        }
+    }
+
+    pub fn rejected(&mut self) {
+        self.boardings_rejected += 1;
+    }
+
+    pub fn destination_chosen(&mut self, person: &str, floor: Floor) {
+        if let Some(journey) = self.journeys.get_mut(person) {
+            journey.destination = Some(floor);
+        }
+    }
+
+    // Left the car on a transfer floor
+    pub fn transferred(&mut self, person: &str, now: u64) {
+        if let Some(journey) = self.journeys.get_mut(person) {
+            journey.status = JourneyStatus::Transferring;
+            journey.transfers += 1;
+            journey.transfer_since = Some(now);
+        }
+    }
+
+    pub fn completed(&mut self, person: &str, now: u64) {
+        self.persons_completed += 1;
+        if let Some(journey) = self.journeys.get_mut(person) {
+            let duration = now.saturating_sub(journey.requested_at_ms);
+            self.total_journey_ms += duration;
+            self.journey_times.observe(duration);
+            journey.status = JourneyStatus::Done;
+            journey.finished_at_ms = Some(now);
+        }
+    }
+
+    pub fn taking_stairs(&mut self, person: &str) {
+        if let Some(journey) = self.journeys.get_mut(person) {
+            journey.status = JourneyStatus::Stairs;
+        }
+    }
+
+    // Walked, neither completed nor part of the journey times
+    pub fn took_stairs(&mut self, person: &str, now: u64) {
+        self.persons_took_stairs += 1;
+        if let Some(journey) = self.journeys.get_mut(person) {
+            journey.status = JourneyStatus::Done;
+            journey.finished_at_ms = Some(now);
+        }
+    }
+
+    pub fn trip(&mut self) {
+        self.trips += 1;
+    }
+
+    pub fn parking_trip(&mut self) {
+        self.parking_trips += 1;
+    }
+
+    pub fn door_cycle(&mut self, elevator: &str) {
+        self.door_cycles += 1;
+        self.energy.entry(elevator.to_string()).or_default().door_cycle(&self.energy_model);
+    }
+
+    pub fn departed(&mut self, elevator: &str, now: u64) {
+        self.energy.entry(elevator.to_string()).or_default().departed(now);
+    }
+
+    // Standing still (also initially), counts as standby until the next departure
+    pub fn halted(&mut self, elevator: &str, now: u64) {
+        self.energy.entry(elevator.to_string()).or_default().halted(now);
+    }
+
+    // `distance` in metres, negative is down
+    pub fn travelled(&mut self, elevator: &str, distance: f64, load_kg: f64) {
+        self.energy.entry(elevator.to_string()).or_default().travelled(&self.energy_model, distance, load_kg);
+    }
+
+    pub fn energy_model(&self) -> &EnergyModel {
+        &self.energy_model
+    }
+
+    pub fn energy(&self, elevator: &str, now: u64) -> EnergyReport {
+        self.energy.get(elevator).map(|meter| meter.report(&self.energy_model, now)).unwrap_or_default()
+    }
+
+    pub fn building_energy(&self, now: u64) -> BuildingEnergy {
+        let elevators: BTreeMap<_, _> = self.energy.iter()
+            .map(|(elevator, meter)| (elevator.clone(), meter.report(&self.energy_model, now)))
+            .collect();
+        BuildingEnergy {
+            total_wh: elevators.values().map(|report| report.total_wh).sum(),
+            elevators,
+        }
+    }
+
+    pub fn journey(&self, person: &str) -> Option<&Journey> {
+        self.journeys.get(person)
+    }
+
+    pub fn calls(&self) -> &BTreeMap<Floor, u64> {
+        &self.calls
+    }
+
+    pub fn wait_times(&self) -> &Histogram {
+        &self.wait_times
+    }
+
+    pub fn journey_times(&self) -> &Histogram {
+        &self.journey_times
+    }
+
+    pub fn transfer_wait_times(&self) -> &Histogram {
+        &self.transfer_wait_times
+    }
+
+    pub fn summary(&self, now: u64) -> StatsSummary {
+        let count = |status: JourneyStatus| self.journeys.values().filter(|j| j.status == status).count() as u64;
+        StatsSummary {
+            trips: self.trips,
+            parking_trips: self.parking_trips,
+            door_cycles: self.door_cycles,
+            boardings_accepted: self.boardings_accepted,
+            boardings_rejected: self.boardings_rejected,
+            persons_waiting: count(JourneyStatus::Waiting),
+            persons_riding: count(JourneyStatus::Riding),
+            persons_completed: self.persons_completed,
+            persons_took_stairs: self.persons_took_stairs,
+            avg_wait_ms: self.total_wait_ms.checked_div(self.wait_times.count()).unwrap_or(0),
+            max_wait_ms: self.max_wait_ms,
+            avg_journey_ms: self.total_journey_ms.checked_div(self.persons_completed).unwrap_or(0),
+            persons_transferring: count(JourneyStatus::Transferring),
+            transfers: self.journeys.values().map(|j| j.transfers).sum(),
+            avg_transfer_wait_ms: self.total_transfer_wait_ms.checked_div(self.transfer_wait_times.count()).unwrap_or(0),
+            energy: self.building_energy(now),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Floor::*;
+
+    #[test]
+    fn test_journey_lifecycle() {
+        let mut stats = Statistics::default();
+
+        stats.requested("P1", Ground, 1000);
+        assert_eq!(stats.journey("P1").unwrap().status, JourneyStatus::Waiting);
+
+        stats.boarded("P1", "E1", 4000);
+        stats.destination_chosen("P1", Third);
+        let journey = stats.journey("P1").unwrap();
+        assert_eq!(journey.status, JourneyStatus::Riding);
+        assert_eq!(journey.elevator, Some("E1".to_string()));
+        assert_eq!(journey.destination, Some(Third));
+
+        stats.completed("P1", 10000);
+        let journey = stats.journey("P1").unwrap();
+        assert_eq!(journey.status, JourneyStatus::Done);
+        assert_eq!(journey.finished_at_ms, Some(10000));
+    }
+
+    #[test]
+    fn test_repeated_request_keeps_first_request_time() {
+        let mut stats = Statistics::default();
+
+        stats.requested("P1", Ground, 1000);
+        stats.rejected();
+        stats.requested("P1", Ground, 3000);
+
+        assert_eq!(stats.journey("P1").unwrap().requested_at_ms, 1000);
+        // Every request is a call on its floor
+        assert_eq!(stats.calls(), &BTreeMap::from([(Ground, 2)]));
+    }
+
+    #[test]
+    fn test_summary_averages() {
+        let mut stats = Statistics::default();
+
+        stats.requested("P1", Ground, 0);
+        stats.requested("P2", First, 0);
+        stats.requested("P3", Second, 0);
+        stats.boarded("P1", "E1", 2000);
+        stats.boarded("P2", "E1", 4000);
+        stats.completed("P1", 10000);
+        stats.took_stairs("P3", 30000);
+        stats.rejected();
+        stats.trip();
+        stats.parking_trip();
+        stats.door_cycle("E1");
+
+        let summary = stats.summary(10000);
+        assert_eq!(summary.avg_wait_ms, 3000);
+        assert_eq!(summary.max_wait_ms, 4000);
+        assert_eq!(summary.avg_journey_ms, 10000);
+        assert_eq!(summary.persons_completed, 1);
+        assert_eq!(summary.persons_took_stairs, 1);
+        assert_eq!(summary.persons_riding, 1);
+        assert_eq!(summary.persons_waiting, 0);
+        assert_eq!(summary.boardings_rejected, 1);
+        assert_eq!(summary.trips, 1);
+        assert_eq!(summary.parking_trips, 1);
+        assert_eq!(summary.door_cycles, 1);
+    }
+
+    #[test]
+    fn test_transfer_wait_counted_separately() {
+        let mut stats = Statistics::default();
+
+        stats.requested("P1", Ground, 0);
+        stats.boarded("P1", "Low", 2000);
+        stats.transferred("P1", 8000);
+        assert_eq!(stats.journey("P1").unwrap().status, JourneyStatus::Transferring);
+        assert_eq!(stats.summary(8000).persons_transferring, 1);
+
+        // The second request keeps the first request time
+        stats.requested("P1", Second, 8500);
+        stats.boarded("P1", "High", 11000);
+        stats.completed("P1", 15000);
+
+        let journey = stats.journey("P1").unwrap();
+        assert_eq!(journey.transfers, 1);
+        assert_eq!(journey.transfer_wait_ms, 3000);
+        assert_eq!(journey.elevator, Some("High".to_string()));
+        assert_eq!(journey.boarded_at_ms, Some(2000));
+        let summary = stats.summary(15000);
+        assert_eq!(summary.avg_wait_ms, 2000);
+        assert_eq!(summary.transfers, 1);
+        assert_eq!(summary.avg_transfer_wait_ms, 3000);
+        assert_eq!(summary.avg_journey_ms, 15000);
+        assert_eq!(stats.transfer_wait_times().count(), 1);
+        assert_eq!(stats.wait_times().count(), 1);
+    }
+
+    #[test]
+    fn test_wait_and_journey_histograms() {
+        let mut stats = Statistics::default();
+
+        stats.requested("P1", Ground, 0);
+        stats.boarded("P1", "E1", 1500);
+        stats.completed("P1", 400000);
+
+        assert_eq!(stats.wait_times().count(), 1);
+        assert_eq!(stats.wait_times().counts()[1], 1);
+        // Above the last bucket, only counted in the total
+        assert_eq!(stats.journey_times().count(), 1);
+        assert_eq!(stats.journey_times().counts().iter().sum::<u64>(), 0);
+    }
+
+    #[test]
+    fn test_empty_summary() {
+        let summary = Statistics::default().summary(0);
+        assert_eq!(summary.avg_wait_ms, 0);
+        assert_eq!(summary.avg_journey_ms, 0);
+        assert_eq!(summary.energy, BuildingEnergy::default());
+    }
+
+    #[test]
+    fn test_building_energy_sums_all_cars() {
+        let mut stats = Statistics::default().with_energy_model(EnergyModel { standby_w: 0.0, ..Default::default() });
+
+        stats.halted("E1", 0);
+        stats.halted("E2", 0);
+        stats.departed("E1", 0);
+        stats.travelled("E1", 6.0, 150.0);
+        stats.halted("E1", 8000);
+        stats.door_cycle("E1");
+        stats.door_cycle("E2");
+
+        let energy = stats.summary(10000).energy;
+        assert_eq!(energy.elevators.len(), 2);
+        assert!(energy.elevators["E1"].travel_wh > 0.0);
+        assert_eq!(energy.elevators["E2"].travel_wh, 0.0);
+        assert!((energy.total_wh - energy.elevators["E1"].total_wh - energy.elevators["E2"].total_wh).abs() < 1e-9);
+        assert_eq!(stats.energy("E2", 10000), energy.elevators["E2"]);
+    }
+}
@@ -0,0 +1,232 @@
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::task::JoinHandle;
+use tracing::warn;
+use crate::mqtt::{InboundError, Receive, Send, SimulationMsg, Topics};
+
+// Everything leaving the simulation goes through publish, everything coming in
+// is pushed into the Receive channel by the inbound task
+pub trait Transport: Clone + std::marker::Send + Sync + 'static {
+    fn publish(&self, send: Send) -> impl Future<Output = ()> + std::marker::Send;
+
+    fn inbound(&self, to: Sender<Receive>) -> JoinHandle<()>;
+
+    fn publisher(self, mut from: Receiver<Send>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            while let Some(send) = from.recv().await {
+                self.publish(send).await;
+            }
+        })
+    }
+}
+
+// Runs two transports side by side, e.g. MQTT and HTTP
+impl<A: Transport, B: Transport> Transport for (A, B) {
+
+    async fn publish(&self, send: Send) {
+        self.0.publish(send.clone()).await;
+        self.1.publish(send).await;
+    }
+
+    fn inbound(&self, to: Sender<Receive>) -> JoinHandle<()> {
+        let first = self.0.inbound(to.clone());
+        let second = self.1.inbound(to);
+        tokio::spawn(async move {
+            let _ = tokio::join!(first, second);
+        })
+    }
+}
+
+// An optional transport that is switched off publishes nothing and receives nothing
+impl<T: Transport> Transport for Option<T> {
+
+    async fn publish(&self, send: Send) {
+        if let Some(transport) = self {
+            transport.publish(send).await;
+        }
+    }
+
+    fn inbound(&self, to: Sender<Receive>) -> JoinHandle<()> {
+        match self {
+            Some(transport) => transport.inbound(to),
+            None => tokio::spawn(async {}),
+        }
+    }
+}
+
+// In-process bus without a broker: lets tests / embedders observe what is
+// published and inject inbound messages, recording is opt-in
+#[derive(Clone)]
+pub struct MemoryTransport {
+    published: Option<Arc<Mutex<Vec<Send>>>>,
+    observers: broadcast::Sender<Send>,
+    inject: Sender<Receive>,
+    injected: Arc<Mutex<Option<Receiver<Receive>>>>,
+}
+
+impl Default for MemoryTransport {
+    fn default() -> Self {
+        MemoryTransport::new()
+    }
+}
+
+impl MemoryTransport {
+
+    pub fn new() -> Self {
+        let (observers, _) = broadcast::channel(10000);
+        let (inject, injected) = mpsc::channel(100);
+        MemoryTransport {
+            published: None,
+            observers,
+            inject,
+            injected: Arc::new(Mutex::new(Some(injected))),
+        }
+    }
+
+    // Keeps everything published, grows with every message
+    pub fn with_recording(mut self) -> Self {
+        self.published = Some(Arc::new(Mutex::new(Vec::new())));
+        self
+    }
+
+    // Invalid messages are reported on simulation/errors like MQTT payloads
+    pub async fn send(&self, receive: Receive) {
+        if let Err(error) = receive.validate() {
+            warn!(?error, "Ungültige Nachricht verworfen");
+            let topic = receive.topic(&Topics::default()).unwrap_or_default();
+            let payload = serde_json::to_string(&receive).unwrap_or_default();
+            let error = InboundError::new(&topic, payload.as_bytes(), error);
+            self.publish(Send::SimulationTopic { msg: SimulationMsg::Error(error) }).await;
+            return;
+        }
+        let _ = self.inject.send(receive).await;
+    }
+
+    // Empty unless recording
+    pub fn published(&self) -> Vec<Send> {
+        self.published.as_ref().map(|published| published.lock().unwrap().clone()).unwrap_or_default()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Send> {
+        self.observers.subscribe()
+    }
+}
+
+impl Transport for MemoryTransport {
+
+    async fn publish(&self, send: Send) {
+        if let Some(published) = &self.published {
+            published.lock().unwrap().push(send.clone());
+        }
+        let _ = self.observers.send(send);
+    }
+
+    fn inbound(&self, to: Sender<Receive>) -> JoinHandle<()> {
+        let injected = self.injected.lock().unwrap().take();
+        tokio::spawn(async move {
+            let Some(mut injected) = injected else { return };
+            while let Some(receive) = injected.recv().await {
+                if to.send(receive).await.is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Floor::*;
+    use crate::mqtt::ElevatorMsg;
+    use crate::mqtt::Send::ElevatorTopic;
+
+    fn position(id: &str) -> Send {
+        ElevatorTopic { id: id.to_string(), msg: ElevatorMsg::Position { floor: First } }
+    }
+
+    #[tokio::test]
+    async fn test_memory_transport_records_published() {
+        let transport = MemoryTransport::new().with_recording();
+        let mut observer = transport.subscribe();
+
+        transport.publish(position("E1")).await;
+        transport.publish(position("E2")).await;
+
+        assert_eq!(transport.published().len(), 2);
+        assert!(matches!(observer.recv().await, Ok(ElevatorTopic { id, .. }) if id == "E1"));
+    }
+
+    #[tokio::test]
+    async fn test_memory_transport_records_only_on_request() {
+        let transport = MemoryTransport::new();
+
+        transport.publish(position("E1")).await;
+
+        assert!(transport.published().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_memory_transport_rejects_invalid_injected() {
+        let transport = MemoryTransport::new().with_recording();
+        let (tx, mut rx) = mpsc::channel(10);
+
+        transport.inbound(tx);
+        transport.send(Receive::Speed { speed: 0 }).await;
+        transport.send(Receive::Person { id: "P1".to_string(), curr: First, dest: First, attributes: Default::default(), payload: None }).await;
+
+        assert!(rx.try_recv().is_err());
+        let errors: Vec<_> = transport.published().into_iter()
+            .filter_map(|send| match send {
+                Send::SimulationTopic { msg: SimulationMsg::Error(error) } => Some(error),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].topic, "simulation/speed");
+        assert_eq!(errors[1].topic, "person/introduce");
+    }
+
+    #[tokio::test]
+    async fn test_memory_transport_publisher_drains_channel() {
+        let transport = MemoryTransport::new().with_recording();
+        let (tx, rx) = mpsc::channel(10);
+
+        let handle = transport.clone().publisher(rx);
+        tx.send(position("E1")).await.unwrap();
+        drop(tx);
+        handle.await.unwrap();
+
+        assert_eq!(transport.published().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pair_publishes_to_both_and_merges_inbound() {
+        let first = MemoryTransport::new().with_recording();
+        let second = MemoryTransport::new().with_recording();
+        let pair = (first.clone(), Some(second.clone()));
+        let (tx, mut rx) = mpsc::channel(10);
+
+        pair.publish(position("E1")).await;
+        pair.inbound(tx);
+        first.send(Receive::Speed { speed: 50 }).await;
+        second.send(Receive::SnapshotRequest).await;
+
+        assert_eq!(first.published().len(), 1);
+        assert_eq!(second.published().len(), 1);
+        assert!(rx.recv().await.is_some());
+        assert!(rx.recv().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_memory_transport_forwards_injected() {
+        let transport = MemoryTransport::new().with_recording();
+        let (tx, mut rx) = mpsc::channel(10);
+
+        transport.inbound(tx);
+        transport.send(Receive::Speed { speed: 50 }).await;
+
+        assert!(matches!(rx.recv().await, Some(Receive::Speed { speed: 50 })));
+    }
+}
@@ -0,0 +1,341 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Cell, Paragraph, Row, Table};
+use ratatui::Frame;
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
+use crate::controller::Floor::*;
+use crate::mqtt::{Control, Query, Receive, Send, MAX_SPEED, MIN_SPEED};
+use crate::person::Person;
+use crate::transport::Transport;
+
+// Draws the building in the terminal and turns key presses into inbound messages.
+// The view is refreshed from snapshot and stats queries, single events are not needed.
+#[derive(Clone)]
+pub struct Tui {
+    closed: watch::Sender<bool>,
+}
+
+impl Default for Tui {
+    fn default() -> Self {
+        Tui::new()
+    }
+}
+
+impl Tui {
+
+    pub fn new() -> Self {
+        Tui { closed: watch::channel(false).0 }
+    }
+
+    // Resolves when the user quits with q / Esc
+    pub async fn closed(&self) {
+        let _ = self.closed.subscribe().wait_for(|closed| *closed).await;
+    }
+}
+
+impl Transport for Tui {
+
+    async fn publish(&self, _send: Send) {}
+
+    fn inbound(&self, to: Sender<Receive>) -> JoinHandle<()> {
+        let closed = self.closed.clone();
+        tokio::spawn(async move {
+            let mut terminal = ratatui::init();
+            let (keys_tx, mut keys) = mpsc::channel(16);
+            let stop = Arc::new(AtomicBool::new(false));
+            let reading = stop.clone();
+            // crossterm only offers a blocking read without the event-stream feature
+            std::thread::spawn(move || {
+                while !reading.load(Ordering::Relaxed) {
+                    if let Ok(true) = event::poll(Duration::from_millis(100))
+                        && let Ok(Event::Key(key)) = event::read()
+                        && key.kind == KeyEventKind::Press
+                        && keys_tx.blocking_send(key.code).is_err() {
+                        return;
+                    }
+                }
+            });
+
+            let mut view = View::default();
+            let mut refresh = tokio::time::interval(Duration::from_millis(200));
+            loop {
+                tokio::select! {
+                    _ = refresh.tick() => {
+                        view.snapshot = ask(&to, Query::Snapshot).await;
+                        view.stats = ask(&to, Query::Stats).await;
+                        let _ = terminal.draw(|frame| view.draw(frame));
+                    }
+                    Some(key) = keys.recv() => match view.key(key) {
+                        Some(Action::Quit) => break,
+                        Some(Action::Send(receive)) => { let _ = to.send(receive).await; }
+                        None => {}
+                    }
+                }
+            }
+
+            stop.store(true, Ordering::Relaxed);
+            ratatui::restore();
+            let _ = closed.send(true);
+        })
+    }
+}
+
+async fn ask(to: &Sender<Receive>, query: Query) -> Value {
+    let (answer, result) = oneshot::channel();
+    if to.send(Receive::Ask { query, answer }).await.is_err() {
+        return Value::Null;
+    }
+    result.await.unwrap_or(Value::Null)
+}
+
+#[derive(Debug)]
+enum Action {
+    Quit,
+    Send(Receive),
+}
+
+#[derive(Default)]
+struct View {
+    snapshot: Value,
+    stats: Value,
+    added: usize,
+    message: String,
+}
+
+impl View {
+
+    fn key(&mut self, key: KeyCode) -> Option<Action> {
+        let speed = self.snapshot["speed"].as_u64().unwrap_or(100);
+        match key {
+            KeyCode::Char('q') | KeyCode::Esc => Some(Action::Quit),
+            KeyCode::Char('n') => {
+                self.added += 1;
+                let id = format!("TUI_{}", self.added);
+                let (curr, dest) = Person::pick_two_distinct_floors();
+                self.message = format!("{}: {} → {}", id, curr, dest);
+                Some(Action::Send(Receive::Person { id, curr, dest, attributes: Default::default(), payload: None }))
+            }
+            // The speed factor scales the delays: smaller is faster
+            KeyCode::Char('+') => {
+                let speed = (speed / 2).max(MIN_SPEED);
+                self.message = format!("Geschwindigkeit {} %", speed);
+                Some(Action::Send(Receive::Speed { speed }))
+            }
+            KeyCode::Char('-') => {
+                let speed = (speed * 2).min(MAX_SPEED);
+                self.message = format!("Geschwindigkeit {} %", speed);
+                Some(Action::Send(Receive::Speed { speed }))
+            }
+            KeyCode::Char('p') if self.snapshot["paused"] == true => {
+                self.message = "Fortgesetzt".to_string();
+                Some(Action::Send(Receive::Control(Control::Resume)))
+            }
+            KeyCode::Char('p') => {
+                self.message = "Angehalten".to_string();
+                Some(Action::Send(Receive::Control(Control::Pause)))
+            }
+            KeyCode::Char('s') => {
+                self.message = "Ein Schritt".to_string();
+                Some(Action::Send(Receive::Control(Control::Step { ms: None })))
+            }
+            _ => None,
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([Constraint::Length(1), Constraint::Min(8), Constraint::Length(1)])
+            .areas(frame.area());
+        let [building, side] = Layout::horizontal([Constraint::Percentage(55), Constraint::Percentage(45)])
+            .areas(body);
+        let [elevators, stats] = Layout::vertical([Constraint::Min(4), Constraint::Length(10)])
+            .areas(side);
+
+        frame.render_widget(self.header(), header);
+        self.draw_building(frame, building);
+        frame.render_widget(self.elevator_details(), elevators);
+        frame.render_widget(self.statistics(), stats);
+        frame.render_widget(Line::from(vec![
+            Span::from(" n Person  + schneller  - langsamer  p Pause  s Schritt  q Beenden  ").reversed(),
+            Span::from(format!(" {}", self.message)),
+        ]), footer);
+    }
+
+    fn header(&self) -> Line<'_> {
+        let mut line = Line::from(format!(
+            " Fahrstuhl-Simulation | Zeit {:.1} s | Geschwindigkeit {} %",
+            self.snapshot["sim_time_ms"].as_u64().unwrap_or(0) as f64 / 1000.0,
+            self.snapshot["speed"].as_u64().unwrap_or(100),
+        )).bold();
+        if self.snapshot["paused"] == true {
+            line.push_span(Span::from(" | angehalten").yellow());
+        }
+        line
+    }
+
+    fn elevators(&self) -> &[Value] {
+        self.snapshot["elevators"].as_array().map_or(&[], Vec::as_slice)
+    }
+
+    // One row per floor, one column per shaft: the car shows its passenger count,
+    // [|n|] with closed doors and [<n>] with open doors, * marks a pending mission
+    fn draw_building(&self, frame: &mut Frame, area: Rect) {
+        let elevators = self.elevators();
+        let rows = [Third, Second, First, Ground].map(|floor| {
+            let floor = floor.to_string();
+            let mut cells = vec![Cell::from(floor.clone())];
+            for elevator in elevators {
+                let pending = elevator["mission"] == floor.as_str()
+                    || elevator["missions"].as_array().is_some_and(|missions| missions.iter().any(|m| m == floor.as_str()));
+                cells.push(if elevator["floor"] == floor.as_str() {
+                    let passengers = elevator["passengers"].as_array().map_or(0, Vec::len);
+                    if elevator["door"] == "Open" {
+                        Cell::from(format!("[<{}>]", passengers)).style(Style::new().fg(Color::Green).bold())
+                    } else {
+                        Cell::from(format!("[|{}|]", passengers)).bold()
+                    }
+                } else if pending {
+                    Cell::from("  *  ").yellow()
+                } else {
+                    Cell::from("  :  ").dark_gray()
+                });
+            }
+            let waiting: Vec<&str> = self.snapshot["waiting"][floor.as_str()].as_array()
+                .map_or(Vec::new(), |persons| persons.iter().filter_map(Value::as_str).collect());
+            cells.push(Cell::from(match waiting.len() {
+                0 => String::new(),
+                n => format!("{}: {}", n, waiting.join(", ")),
+            }));
+            Row::new(cells).height(2)
+        });
+
+        let mut widths = vec![Constraint::Length(7)];
+        widths.extend(elevators.iter().map(|elevator| Constraint::Length(elevator["id"].as_str().map_or(7, |id| id.chars().count().max(7)) as u16)));
+        widths.push(Constraint::Min(10));
+        let mut header = vec![Cell::from("Ebene")];
+        header.extend(elevators.iter().map(|elevator| Cell::from(elevator["id"].as_str().unwrap_or("?").to_string())));
+        header.push(Cell::from("Wartend"));
+
+        let table = Table::new(rows, widths)
+            .header(Row::new(header).bold())
+            .block(Block::bordered().title(" Gebäude "));
+        frame.render_widget(table, area);
+    }
+
+    fn elevator_details(&self) -> Paragraph<'_> {
+        let text = |value: &Value| value.as_str().unwrap_or("-").to_string();
+        let list = |value: &Value| value.as_array()
+            .map(|items| items.iter().filter_map(Value::as_str).collect::<Vec<_>>().join(", "))
+            .filter(|items| !items.is_empty())
+            .unwrap_or("-".to_string());
+        let mut lines = Vec::new();
+        for elevator in self.elevators() {
+            lines.push(Line::from(format!("{}  {}  Tür {}", text(&elevator["id"]), text(&elevator["floor"]), text(&elevator["door"]))).bold());
+            lines.push(Line::from(format!("  Ziel {}  Aufträge {}", text(&elevator["mission"]), list(&elevator["missions"]))));
+            lines.push(Line::from(format!("  Fahrgäste {}", list(&elevator["passengers"]))));
+        }
+        Paragraph::new(lines).block(Block::bordered().title(" Kabinen "))
+    }
+
+    fn statistics(&self) -> Paragraph<'_> {
+        let count = |name: &str| self.stats[name].as_u64().unwrap_or(0);
+        let seconds = |name: &str| format!("{:.1} s", count(name) as f64 / 1000.0);
+        Paragraph::new(vec![
+            Line::from(format!("Angekommen      {}", count("persons_completed"))),
+            Line::from(format!("Treppe          {}", count("persons_took_stairs"))),
+            Line::from(format!("Wartend         {}", count("persons_waiting"))),
+            Line::from(format!("In Kabinen      {}", count("persons_riding"))),
+            Line::from(format!("Fahrten         {}", count("trips"))),
+            Line::from(format!("Ø Wartezeit     {}", seconds("avg_wait_ms"))),
+            Line::from(format!("Max. Wartezeit  {}", seconds("max_wait_ms"))),
+            Line::from(format!("Ø Reisezeit     {}", seconds("avg_journey_ms"))),
+            Line::from(format!("Zustiege        {} / {} abgelehnt", count("boardings_accepted"), count("boardings_rejected"))),
+        ]).block(Block::bordered().title(" Statistik "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn view() -> View {
+        View {
+            snapshot: serde_json::json!({
+                "sim_time_ms": 12300,
+                "speed": 100,
+                "elevators": [
+                    { "id": "E1", "floor": "Second", "mission": "Third", "missions": [], "passengers": ["P1", "P2"], "door": "Open" },
+                    { "id": "E2", "floor": "Ground", "mission": null, "missions": [], "passengers": [], "door": "Closed" },
+                ],
+                "waiting": { "First": ["P3"] },
+                "hall_calls": [],
+            }),
+            stats: serde_json::json!({ "persons_completed": 4, "avg_wait_ms": 2500 }),
+            ..Default::default()
+        }
+    }
+
+    fn render(view: &View) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| view.draw(frame)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer.content().chunks(buffer.area.width as usize)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_draw_building() {
+        let screen = render(&view());
+
+        assert!(screen.contains("Zeit 12.3 s"));
+        assert!(screen.contains("[<2>]"));
+        assert!(screen.contains("[|0|]"));
+        assert!(screen.contains("1: P3"));
+        assert!(screen.contains("Fahrgäste P1, P2"));
+        assert!(screen.contains("Angekommen      4"));
+        assert!(screen.contains("Ø Wartezeit     2.5 s"));
+    }
+
+    #[test]
+    fn test_pending_mission_is_marked() {
+        let screen = render(&view());
+        let third = screen.lines().find(|line| line.contains("Third")).unwrap();
+        assert!(third.contains('*'));
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut view = view();
+
+        assert!(matches!(view.key(KeyCode::Char('q')), Some(Action::Quit)));
+        assert!(matches!(view.key(KeyCode::Char('+')), Some(Action::Send(Receive::Speed { speed: 50 }))));
+        assert!(matches!(view.key(KeyCode::Char('-')), Some(Action::Send(Receive::Speed { speed: 200 }))));
+        assert!(matches!(view.key(KeyCode::Char('n')), Some(Action::Send(Receive::Person { id, curr, dest, .. })) if id == "TUI_1" && curr != dest));
+        assert!(matches!(view.key(KeyCode::Char('p')), Some(Action::Send(Receive::Control(Control::Pause)))));
+        assert!(matches!(view.key(KeyCode::Char('s')), Some(Action::Send(Receive::Control(Control::Step { ms: None })))));
+
+        view.snapshot["paused"] = serde_json::json!(true);
+        assert!(matches!(view.key(KeyCode::Char('p')), Some(Action::Send(Receive::Control(Control::Resume)))));
+        assert!(render(&view).contains("angehalten"));
+    }
+
+    #[test]
+    fn test_speed_stays_in_range() {
+        let mut view = View { snapshot: serde_json::json!({ "speed": MIN_SPEED }), ..Default::default() };
+        assert!(matches!(view.key(KeyCode::Char('+')), Some(Action::Send(Receive::Speed { speed: MIN_SPEED }))));
+
+        view.snapshot = serde_json::json!({ "speed": MAX_SPEED });
+        assert!(matches!(view.key(KeyCode::Char('-')), Some(Action::Send(Receive::Speed { speed: MAX_SPEED }))));
+    }
+}
@@ -1,32 +1,218 @@
 
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::Duration;
-use rand::{rng, Rng};
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use rand::rngs::StdRng;
+use rand::{rng, Rng, SeedableRng};
 use tokio::sync::broadcast::Sender;
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
 use crate::msg::ControllerToElevatorsMsg;
 use crate::msg::ControllerToElevatorsMsg::CloseDoors;
 
-pub static SPEED_FACTOR: AtomicU64 = AtomicU64::new(100);
+static CLOCK: LazyLock<SimClock> = LazyLock::new(|| SimClock::new(100));
+
+// Shared random source for floors and delays, --seed makes the choices repeatable
+static RNG: LazyLock<Mutex<StdRng>> = LazyLock::new(|| Mutex::new(StdRng::seed_from_u64(rng().random())));
+
+pub fn set_seed(seed: u64) {
+    *RNG.lock().unwrap() = StdRng::seed_from_u64(seed);
+}
+
+pub(crate) fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    f(&mut RNG.lock().unwrap())
+}
+
+// Simulated time: wall time is scaled by the speed factor that was active while it passed.
+// It stands still while paused and jumps forward on step, every delay waits for it.
+// Running delays are woken on every change and recompute their remaining wall time,
+// so a new speed applies to a car that is already travelling.
+pub struct SimClock {
+    state: Mutex<ClockState>,
+    // Deadlines (simulated ms) of the running delays, step without ms jumps to the earliest
+    timers: Mutex<BTreeMap<u64, usize>>,
+    changed: watch::Sender<()>,
+}
+
+struct ClockState {
+    // wall clock at last change, simulated ms at last change, factor since then
+    since: Instant,
+    sim_ms: u64,
+    factor: u64,
+    paused: bool,
+}
+
+impl ClockState {
+    fn now_ms(&self) -> u64 {
+        match self.paused {
+            true => self.sim_ms,
+            false => self.sim_ms + scale_to_sim(self.since.elapsed(), self.factor),
+        }
+    }
+}
+
+// Unregisters the deadline when the delay finishes or is dropped (e.g. in a select!)
+struct Timer<'a> {
+    clock: &'a SimClock,
+    deadline: u64,
+}
+
+impl Drop for Timer<'_> {
+    fn drop(&mut self) {
+        let mut timers = self.clock.timers.lock().unwrap();
+        if let Some(count) = timers.get_mut(&self.deadline) {
+            *count -= 1;
+            if *count == 0 {
+                timers.remove(&self.deadline);
+            }
+        }
+    }
+}
+
+impl SimClock {
+
+    pub fn new(factor: u64) -> Self {
+        SimClock {
+            state: Mutex::new(ClockState { since: Instant::now(), sim_ms: 0, factor, paused: false }),
+            timers: Mutex::new(BTreeMap::new()),
+            changed: watch::channel(()).0,
+        }
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.state.lock().unwrap().now_ms()
+    }
+
+    pub fn set_speed(&self, speed: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.sim_ms = state.now_ms();
+        state.since = Instant::now();
+        state.factor = speed;
+        drop(state);
+        self.changed.send_replace(());
+    }
+
+    pub fn speed(&self) -> u64 {
+        self.state.lock().unwrap().factor
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    pub fn pause(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !state.paused {
+            state.sim_ms = state.now_ms();
+            state.paused = true;
+        }
+        drop(state);
+        self.changed.send_replace(());
+    }
+
+    pub fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.paused {
+            state.since = Instant::now();
+            state.paused = false;
+        }
+        drop(state);
+        self.changed.send_replace(());
+    }
+
+    // Pauses, then advances by `ms` or to the next pending deadline. Returns the new time.
+    pub fn step(&self, ms: Option<u64>) -> u64 {
+        self.pause();
+        let mut state = self.state.lock().unwrap();
+        let next = match ms {
+            Some(ms) => Some(state.sim_ms + ms),
+            None => self.timers.lock().unwrap().keys().next().copied(),
+        };
+        if let Some(next) = next {
+            state.sim_ms = state.sim_ms.max(next);
+        }
+        let now = state.sim_ms;
+        drop(state);
+        self.changed.send_replace(());
+        now
+    }
+
+    pub async fn delay(&self, ms: u64) {
+        let mut changed = self.changed.subscribe();
+        let deadline = self.now_ms() + ms;
+        *self.timers.lock().unwrap().entry(deadline).or_default() += 1;
+        let _timer = Timer { clock: self, deadline };
+        loop {
+            let (now, paused, factor) = {
+                let state = self.state.lock().unwrap();
+                (state.now_ms(), state.paused, state.factor)
+            };
+            if now >= deadline {
+                return;
+            }
+            if paused {
+                let _ = changed.changed().await;
+                continue;
+            }
+            let adjusted = ((deadline - now) * factor) / 100;
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(adjusted)) => return,
+                _ = changed.changed() => {}
+            }
+        }
+    }
+}
+
+pub fn sim_time_ms() -> u64 {
+    CLOCK.now_ms()
+}
+
+pub fn set_speed_factor(speed: u64) {
+    CLOCK.set_speed(speed);
+}
+
+pub fn speed_factor() -> u64 {
+    CLOCK.speed()
+}
+
+pub fn is_paused() -> bool {
+    CLOCK.is_paused()
+}
+
+pub fn pause() {
+    CLOCK.pause();
+}
+
+pub fn resume() {
+    CLOCK.resume();
+}
+
+pub fn step(ms: Option<u64>) -> u64 {
+    CLOCK.step(ms)
+}
+
+fn scale_to_sim(wall: Duration, factor: u64) -> u64 {
+    (wall.as_millis() as u64 * 100) / factor.max(1)
+}
 
 pub(crate) async fn delay(ms: u64) {
-    let factor = SPEED_FACTOR.load(Ordering::Relaxed);
-    let adjusted = (ms * factor) / 100;
-    tokio::time::sleep(Duration::from_millis(adjusted)).await;
+    CLOCK.delay(ms).await;
 }
 
 pub(crate) async fn random_delay_ms(from: u64, to: u64) {
-    let factor = SPEED_FACTOR.load(Ordering::Relaxed);
-    let base_delay = rng().random_range(from..=to);
-    let adjusted = (base_delay * factor) / 100;
-    tokio::time::sleep(Duration::from_millis(adjusted)).await;
+    delay(with_rng(|rng| rng.random_range(from..=to))).await;
 }
 
+// Box-Muller transform
+pub(crate) fn random_normal(mean: f64, sd: f64) -> f64 {
+    let (u, v): (f64, f64) = with_rng(|rng| (rng.random(), rng.random()));
+    mean + sd * (-2.0 * (1.0 - u).ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+}
+
+#[allow(dead_code)]
 pub(crate) fn get_closing_task(to_elevators: Sender<ControllerToElevatorsMsg>, elevator: String) -> Option<JoinHandle<()>> {
     Some(tokio::spawn(async move {
-        let factor = SPEED_FACTOR.load(Ordering::Relaxed);
-        let adjusted = (5000 * factor) / 100;
-        tokio::time::sleep(Duration::from_millis(adjusted)).await;
+        delay(5000).await;
         let _ = to_elevators.send(CloseDoors(elevator));
     }))
 }
@@ -39,7 +225,7 @@ mod tests {
     #[tokio::test]
     async fn test_delay_respects_speed_factor() {
         // Set a very fast speed factor (1% of normal time)
-        SPEED_FACTOR.store(1, Ordering::Relaxed);
+        set_speed_factor(1);
 
         let start = Instant::now();
         delay(1000).await; // Should take ~10ms instead of 1000ms
@@ -49,12 +235,12 @@ mod tests {
         assert!(elapsed.as_millis() < 100, "Delay should be adjusted by speed factor");
 
         // Reset
-        SPEED_FACTOR.store(100, Ordering::Relaxed);
+        set_speed_factor(100);
     }
 
     #[tokio::test]
     async fn test_delay_with_100_percent_factor() {
-        SPEED_FACTOR.store(100, Ordering::Relaxed);
+        set_speed_factor(100);
 
         let start = Instant::now();
         delay(50).await;
@@ -66,7 +252,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_delay_with_zero_factor() {
-        SPEED_FACTOR.store(0, Ordering::Relaxed);
+        set_speed_factor(0);
 
         let start = Instant::now();
         delay(1000).await;
@@ -75,12 +261,12 @@ mod tests {
         // With 0 factor, delay should be instant
         assert!(elapsed.as_millis() < 50);
 
-        SPEED_FACTOR.store(100, Ordering::Relaxed);
+        set_speed_factor(100);
     }
 
     #[tokio::test]
     async fn test_random_delay_ms_within_range() {
-        SPEED_FACTOR.store(100, Ordering::Relaxed);
+        set_speed_factor(100);
 
         // Run multiple times to test randomness
         for _ in 0..10 {
@@ -95,7 +281,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_random_delay_respects_speed_factor() {
-        SPEED_FACTOR.store(10, Ordering::Relaxed); // 10% speed
+        set_speed_factor(10); // 10% speed
 
         let start = Instant::now();
         random_delay_ms(100, 200).await;
@@ -104,18 +290,33 @@ mod tests {
         // Should be 10-20ms instead of 100-200ms
         assert!(elapsed.as_millis() < 50, "Random delay should respect speed factor");
 
-        SPEED_FACTOR.store(100, Ordering::Relaxed);
+        set_speed_factor(100);
     }
 
     #[test]
     fn test_speed_factor_default() {
         // Default should be 100 (100% = normal speed)
-        let factor = SPEED_FACTOR.load(Ordering::Relaxed);
+        let factor = speed_factor();
         // Note: This may fail if other tests modified it
         // In a real scenario, you'd want test isolation
         assert!(factor > 0, "Speed factor should be positive");
     }
 
+    #[test]
+    fn test_scale_to_sim() {
+        // 100% = real time, 50% = delays take half as long = simulated time runs twice as fast
+        assert_eq!(scale_to_sim(Duration::from_millis(1000), 100), 1000);
+        assert_eq!(scale_to_sim(Duration::from_millis(1000), 50), 2000);
+        assert_eq!(scale_to_sim(Duration::from_millis(1000), 1000), 100);
+    }
+
+    #[test]
+    fn test_sim_time_is_monotonic() {
+        let t1 = sim_time_ms();
+        let t2 = sim_time_ms();
+        assert!(t2 >= t1);
+    }
+
     #[tokio::test]
     async fn test_get_closing_task_returns_handle() {
         use tokio::sync::broadcast;
@@ -128,4 +329,105 @@ mod tests {
         // Cancel the task to avoid waiting
         handle.unwrap().abort();
     }
-}
\ No newline at end of file
+
+    // Own clocks, pausing the shared one would stall the other tests
+    fn clock(factor: u64) -> &'static SimClock {
+        Box::leak(Box::new(SimClock::new(factor)))
+    }
+
+    async fn pending(clock: &SimClock, timers: usize) {
+        while clock.timers.lock().unwrap().values().sum::<usize>() != timers {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pause_freezes_time_and_delays() {
+        let clock = clock(10);
+
+        clock.pause();
+        let paused_at = clock.now_ms();
+        let delay = tokio::spawn(clock.delay(100));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(clock.now_ms(), paused_at);
+        assert!(!delay.is_finished());
+
+        clock.resume();
+        tokio::time::timeout(Duration::from_millis(200), delay).await.unwrap().unwrap();
+        assert!(clock.now_ms() >= paused_at + 100);
+    }
+
+    #[tokio::test]
+    async fn test_step_by_ms() {
+        let clock = clock(100);
+
+        clock.pause();
+        let start = clock.now_ms();
+        let delay = tokio::spawn(clock.delay(300));
+        pending(clock, 1).await;
+
+        assert_eq!(clock.step(Some(200)), start + 200);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!delay.is_finished());
+
+        clock.step(Some(100));
+        tokio::time::timeout(Duration::from_millis(100), delay).await.unwrap().unwrap();
+        assert!(clock.is_paused());
+    }
+
+    #[tokio::test]
+    async fn test_step_to_next_timer() {
+        let clock = clock(100);
+
+        clock.pause();
+        let start = clock.now_ms();
+        let first = tokio::spawn(clock.delay(300));
+        let second = tokio::spawn(clock.delay(800));
+        pending(clock, 2).await;
+
+        assert_eq!(clock.step(None), start + 300);
+        tokio::time::timeout(Duration::from_millis(100), first).await.unwrap().unwrap();
+        pending(clock, 1).await;
+        assert!(!second.is_finished());
+
+        assert_eq!(clock.step(None), start + 800);
+        tokio::time::timeout(Duration::from_millis(100), second).await.unwrap().unwrap();
+        assert_eq!(clock.step(None), start + 800);
+    }
+
+    #[tokio::test]
+    async fn test_speed_up_applies_to_running_delay() {
+        let clock = clock(100);
+
+        let delay = tokio::spawn(clock.delay(2000));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        clock.set_speed(1);
+
+        // ~1980 ms left, at 1% that is about 20 ms instead of 1980 ms
+        tokio::time::timeout(Duration::from_millis(300), delay).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_slow_down_applies_to_running_delay() {
+        let clock = clock(1);
+
+        let delay = tokio::spawn(clock.delay(5000));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        clock.set_speed(100);
+
+        // Would have finished after 50 ms with the old speed
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(!delay.is_finished());
+        assert_eq!(clock.speed(), 100);
+        delay.abort();
+    }
+
+    #[tokio::test]
+    async fn test_step_pauses_running_clock() {
+        let clock = clock(100);
+
+        let now = clock.step(Some(1000));
+        assert!(clock.is_paused());
+        assert_eq!(clock.now_ms(), now);
+    }
+}